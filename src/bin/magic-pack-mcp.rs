@@ -2,8 +2,16 @@ use std::env;
 use std::io::{self, BufRead, Write};
 use std::path::{Component, Path, PathBuf};
 
-use magic_pack::contents::enums::FileType;
-use magic_pack::service::{self, CompressRequest, DecompressRequest};
+use magic_pack::contents::enums::{
+    ConflictPolicy, Destination, DirectoryMode, FileType, LinkPolicy, SpecialFilePolicy, TarFormat,
+    UnreadablePolicy, UnsafeEntryPolicy,
+};
+use magic_pack::modules::options::{
+    CompressOptions, DecompressOptions, FilterOptions, GzipOptions, Limits, NestedOptions,
+    PermissionOptions, ReproducibleOptions, SevenZOptions, SourceOptions,
+};
+use magic_pack::modules::transform::Transform;
+use magic_pack::service::{self, CompressRequest, DecompressRequest, Level, UpdateRequest};
 use serde_json::{json, Map, Value};
 
 const JSONRPC_VERSION: &str = "2.0";
@@ -11,7 +19,17 @@ const LATEST_PROTOCOL_VERSION: &str = "2025-11-25";
 const SUPPORTED_PROTOCOL_VERSIONS: &[&str] =
     &["2024-11-05", "2025-03-26", "2025-06-18", "2025-11-25"];
 
+// Inputs here are picked by an agent, so decompression is always bounded
+// unless the caller asks for different limits.
+const DEFAULT_MAX_OUTPUT_BYTES: u64 = 4 << 30;
+const DEFAULT_MAX_ENTRY_BYTES: u64 = 1 << 30;
+const DEFAULT_MAX_RATIO: u64 = 1000;
+const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+const DEFAULT_MAX_DEPTH: u32 = 5;
+const DEFAULT_RECURSIVE_DEPTH: u32 = 4;
+
 fn main() -> io::Result<()> {
+    service::remove_staged_on_signal()?;
     let stdin = io::stdin();
     let stdout = io::stdout();
     let mut writer = stdout.lock();
@@ -289,6 +307,7 @@ fn dispatch_tool(
             let output = optional_path(arguments, "output_path", state)?
                 .unwrap_or_else(|| PathBuf::from("."));
             let file_type = required_file_type(arguments, "file_type")?;
+            let options = compress_options(arguments, state)?;
             ensure_allowed_path(&input, state)?;
             ensure_allowed_path(&output, state)?;
 
@@ -296,13 +315,15 @@ fn dispatch_tool(
                 file_type,
                 input,
                 output,
+                options,
             })
             .map_err(|err| ToolCallError::Tool(err.to_string()))?;
 
             Ok(json!({
                 "ok": true,
                 "message": result.message,
-                "output_path": result.output_path
+                "output_path": result.output_path,
+                "warnings": result.warnings
             })
             .to_string())
         }
@@ -310,9 +331,8 @@ fn dispatch_tool(
             let input = required_path(arguments, "input_path", state)?;
             let output = optional_path(arguments, "output_path", state)?
                 .unwrap_or_else(|| PathBuf::from("."));
-            let level = optional_i64(arguments, "level")?.unwrap_or(5);
-            let level = i8::try_from(level)
-                .map_err(|_| invalid_params("level must fit in an 8-bit signed integer"))?;
+            let level = level(arguments)?;
+            let options = decompress_options(arguments)?;
             ensure_allowed_path(&input, state)?;
             ensure_allowed_path(&output, state)?;
 
@@ -320,13 +340,57 @@ fn dispatch_tool(
                 input,
                 output,
                 level,
+                options,
+            })
+            .map_err(|err| ToolCallError::Tool(err.to_string()))?;
+
+            Ok(json!({
+                "ok": true,
+                "message": result.message,
+                "output_path": result.output_path,
+                "warnings": result.warnings,
+                "layers": result
+                    .layers
+                    .iter()
+                    .map(|layer| file_type_name(*layer))
+                    .collect::<Vec<_>>(),
+                "nested_archives": result.nested_archives
+            })
+            .to_string())
+        }
+        "update" => {
+            let archive = required_path(arguments, "archive_path", state)?;
+            let file_type = required_file_type(arguments, "file_type")?;
+            let add: Vec<PathBuf> = optional_strings(arguments, "add")?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|raw| absolutize_path(&state.cwd, &PathBuf::from(raw)))
+                .collect();
+            let delete = optional_strings(arguments, "delete")?
+                .unwrap_or_default()
+                .into_iter()
+                .map(PathBuf::from)
+                .collect();
+            let options = compress_options(arguments, state)?;
+            ensure_allowed_path(&archive, state)?;
+            for path in &add {
+                ensure_allowed_path(path, state)?;
+            }
+
+            let result = service::update(UpdateRequest {
+                file_type,
+                archive,
+                add,
+                delete,
+                options,
             })
             .map_err(|err| ToolCallError::Tool(err.to_string()))?;
 
             Ok(json!({
                 "ok": true,
                 "message": result.message,
-                "output_path": result.output_path
+                "output_path": result.output_path,
+                "warnings": result.warnings
             })
             .to_string())
         }
@@ -389,6 +453,310 @@ fn optional_i64(arguments: &Map<String, Value>, key: &str) -> Result<Option<i64>
     }
 }
 
+// An integer layer count or "auto".
+fn level(arguments: &Map<String, Value>) -> Result<Level, ToolCallError> {
+    match arguments.get("level") {
+        Some(Value::String(raw)) if raw == "auto" => Ok(Level::Auto),
+        Some(Value::String(_)) => Err(invalid_params("level must be an integer or \"auto\"")),
+        _ => match optional_i64(arguments, "level")? {
+            Some(level) => i8::try_from(level)
+                .map(Level::Layers)
+                .map_err(|_| invalid_params("level must fit in an 8-bit signed integer")),
+            None => Ok(Level::default()),
+        },
+    }
+}
+
+fn optional_bool(arguments: &Map<String, Value>, key: &str) -> Result<Option<bool>, ToolCallError> {
+    match arguments.get(key) {
+        Some(Value::Bool(value)) => Ok(Some(*value)),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(invalid_params(format!("{} must be a boolean", key))),
+    }
+}
+
+fn optional_string(
+    arguments: &Map<String, Value>,
+    key: &str,
+) -> Result<Option<String>, ToolCallError> {
+    match arguments.get(key) {
+        Some(Value::String(raw)) => Ok(Some(raw.clone())),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(invalid_params(format!("{} must be a string", key))),
+    }
+}
+
+fn optional_strings(
+    arguments: &Map<String, Value>,
+    key: &str,
+) -> Result<Option<Vec<String>>, ToolCallError> {
+    match arguments.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(String::from)
+                    .ok_or_else(|| invalid_params(format!("{} must be an array of strings", key)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        Some(Value::Null) | None => Ok(None),
+        Some(_) => Err(invalid_params(format!(
+            "{} must be an array of strings",
+            key
+        ))),
+    }
+}
+
+fn optional_u64(arguments: &Map<String, Value>, key: &str) -> Result<Option<u64>, ToolCallError> {
+    match optional_i64(arguments, key)? {
+        Some(value) => u64::try_from(value)
+            .map(Some)
+            .map_err(|_| invalid_params(format!("{} must not be negative", key))),
+        None => Ok(None),
+    }
+}
+
+fn compress_options(
+    arguments: &Map<String, Value>,
+    state: &ServerState,
+) -> Result<CompressOptions, ToolCallError> {
+    let mut sevenz = SevenZOptions::default();
+    if let Some(solid) = optional_bool(arguments, "solid")? {
+        sevenz.solid = solid;
+    }
+    sevenz.solid_block_size = optional_u64(arguments, "solid_block_size")?;
+    if let Some(preset) = optional_u64(arguments, "lzma2_preset")? {
+        sevenz.preset = u32::try_from(preset)
+            .map_err(|_| invalid_params("lzma2_preset must be between 0 and 9"))?;
+    }
+    if let Some(dict_size) = optional_u64(arguments, "lzma2_dict_size")? {
+        sevenz.dict_size = Some(
+            u32::try_from(dict_size)
+                .map_err(|_| invalid_params("lzma2_dict_size must fit in 32 bits"))?,
+        );
+    }
+    if let Some(sort) = optional_bool(arguments, "sort_by_extension")? {
+        sevenz.sort_by_extension = sort;
+    }
+    let gzip = GzipOptions {
+        store_name: !optional_bool(arguments, "no_name")?.unwrap_or(false),
+    };
+    let directories = match arguments.get("directory_mode") {
+        Some(Value::String(raw)) if raw == "tar" => DirectoryMode::Tar,
+        Some(Value::String(raw)) if raw == "per-file" => DirectoryMode::PerFile,
+        Some(Value::Null) | None => DirectoryMode::Tar,
+        Some(_) => {
+            return Err(invalid_params(
+                "directory_mode must be one of tar, per-file",
+            ))
+        }
+    };
+    let links = match arguments.get("links") {
+        Some(Value::String(raw)) if raw == "store" => LinkPolicy::Store,
+        Some(Value::String(raw)) if raw == "follow" => LinkPolicy::Follow,
+        Some(Value::String(raw)) if raw == "skip" => LinkPolicy::Skip,
+        Some(Value::Null) | None => LinkPolicy::Store,
+        Some(_) => return Err(invalid_params("links must be one of store, follow, skip")),
+    };
+    // a followed link is read wherever it points, so only without a root
+    if links == LinkPolicy::Follow && state.allowed_root.is_some() {
+        return Err(ToolCallError::Tool(
+            "links=follow is not allowed while MAGIC_PACK_MCP_ALLOWED_ROOT is set".into(),
+        ));
+    }
+    // read by the server like the input, so held to the same root
+    let mut exclude_from = Vec::new();
+    for raw in optional_strings(arguments, "exclude_from")?.unwrap_or_default() {
+        let path = absolutize_path(&state.cwd, &PathBuf::from(raw));
+        ensure_allowed_path(&path, state)?;
+        exclude_from.push(path);
+    }
+    let tar_format = match arguments.get("tar_format") {
+        Some(Value::String(raw)) if raw == "gnu" => TarFormat::Gnu,
+        Some(Value::String(raw)) if raw == "ustar" => TarFormat::Ustar,
+        Some(Value::String(raw)) if raw == "pax" => TarFormat::Pax,
+        Some(Value::Null) | None => TarFormat::Gnu,
+        Some(_) => return Err(invalid_params("tar_format must be one of gnu, ustar, pax")),
+    };
+    Ok(CompressOptions {
+        sevenz,
+        gzip,
+        directories,
+        source: source_options(arguments)?,
+        filter: FilterOptions {
+            include: optional_strings(arguments, "include")?.unwrap_or_default(),
+            exclude: optional_strings(arguments, "exclude")?.unwrap_or_default(),
+            exclude_from,
+            gitignore: optional_bool(arguments, "gitignore")?.unwrap_or(false),
+            exclude_vcs: optional_bool(arguments, "exclude_vcs")?.unwrap_or(false),
+            exclude_hidden: optional_bool(arguments, "exclude_hidden")?.unwrap_or(false),
+        },
+        links,
+        xattrs: optional_bool(arguments, "xattrs")?.unwrap_or(false),
+        tar_format,
+        special_files: special_files(arguments)?,
+        transforms: transforms(arguments)?,
+        unreadable: on_unreadable(arguments)?,
+        reproducible: ReproducibleOptions {
+            enabled: optional_bool(arguments, "reproducible")?.unwrap_or(false),
+            source_date_epoch: match optional_i64(arguments, "source_date_epoch")? {
+                Some(epoch) => Some(epoch),
+                None => ReproducibleOptions::source_date_epoch_from_env(),
+            },
+        },
+    })
+}
+
+fn decompress_options(arguments: &Map<String, Value>) -> Result<DecompressOptions, ToolCallError> {
+    let unsafe_entries = match arguments.get("on_unsafe_entry") {
+        Some(Value::String(raw)) if raw == "error" => UnsafeEntryPolicy::Error,
+        Some(Value::String(raw)) if raw == "skip" => UnsafeEntryPolicy::Skip,
+        Some(Value::Null) | None => UnsafeEntryPolicy::Error,
+        Some(_) => return Err(invalid_params("on_unsafe_entry must be one of error, skip")),
+    };
+    let conflicts = match arguments.get("on_conflict") {
+        Some(Value::String(raw)) if raw == "error" => ConflictPolicy::Error,
+        Some(Value::String(raw)) if raw == "skip" => ConflictPolicy::Skip,
+        Some(Value::String(raw)) if raw == "overwrite" => ConflictPolicy::Overwrite,
+        Some(Value::String(raw)) if raw == "rename" => ConflictPolicy::Rename,
+        Some(Value::String(raw)) if raw == "newer" => ConflictPolicy::Newer,
+        Some(Value::Null) | None => ConflictPolicy::Overwrite,
+        Some(_) => {
+            return Err(invalid_params(
+                "on_conflict must be one of error, skip, overwrite, rename, newer",
+            ))
+        }
+    };
+    let max_depth = match optional_u64(arguments, "max_depth")? {
+        Some(depth) => {
+            u32::try_from(depth).map_err(|_| invalid_params("max_depth must fit in 32 bits"))?
+        }
+        None => DEFAULT_MAX_DEPTH,
+    };
+    let limits = Limits {
+        max_total_bytes: Some(
+            optional_u64(arguments, "max_output_bytes")?.unwrap_or(DEFAULT_MAX_OUTPUT_BYTES),
+        ),
+        max_entry_bytes: Some(
+            optional_u64(arguments, "max_entry_bytes")?.unwrap_or(DEFAULT_MAX_ENTRY_BYTES),
+        ),
+        max_ratio: Some(optional_u64(arguments, "max_ratio")?.unwrap_or(DEFAULT_MAX_RATIO)),
+        max_entries: Some(optional_u64(arguments, "max_entries")?.unwrap_or(DEFAULT_MAX_ENTRIES)),
+        max_depth: Some(max_depth),
+    };
+    let recursive_depth = match optional_u64(arguments, "recursive_depth")? {
+        Some(depth) => u32::try_from(depth)
+            .map_err(|_| invalid_params("recursive_depth must fit in 32 bits"))?,
+        None => DEFAULT_RECURSIVE_DEPTH,
+    };
+    let nested = NestedOptions {
+        enabled: optional_bool(arguments, "recursive")?.unwrap_or(false),
+        max_depth: recursive_depth,
+        include: optional_strings(arguments, "recursive_include")?.unwrap_or_default(),
+        delete_archives: optional_bool(arguments, "delete_nested")?.unwrap_or(false),
+    };
+    let destination = match arguments.get("destination") {
+        Some(Value::String(raw)) if raw == "subdir" => Destination::Subdir,
+        Some(Value::String(raw)) if raw == "smart" => Destination::Smart,
+        Some(Value::String(raw)) if raw == "here" => Destination::Here,
+        Some(Value::Null) | None => Destination::Subdir,
+        Some(_) => {
+            return Err(invalid_params(
+                "destination must be one of subdir, smart, here",
+            ))
+        }
+    };
+    let strip_components = match optional_u64(arguments, "strip_components")? {
+        Some(count) => u32::try_from(count)
+            .map_err(|_| invalid_params("strip_components must fit in 32 bits"))?,
+        None => 0,
+    };
+    Ok(DecompressOptions {
+        unsafe_entries,
+        conflicts,
+        limits,
+        nested,
+        destination,
+        strip_components,
+        restore_name: optional_bool(arguments, "restore_name")?.unwrap_or(false),
+        source: source_options(arguments)?,
+        xattrs: optional_bool(arguments, "xattrs")?.unwrap_or(false),
+        permissions: permission_options(arguments)?,
+        special_files: special_files(arguments)?,
+        allow_devices: optional_bool(arguments, "allow_devices")?.unwrap_or(false),
+        transforms: transforms(arguments)?,
+    })
+}
+
+// strip_prefix, then every transform expression, then prefix.
+fn transforms(arguments: &Map<String, Value>) -> Result<Vec<Transform>, ToolCallError> {
+    let substitutions = optional_strings(arguments, "transform")?
+        .unwrap_or_default()
+        .iter()
+        .map(|raw| Transform::parse(raw).map_err(invalid_params))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Transform::chain(
+        optional_string(arguments, "strip_prefix")?.as_deref(),
+        &substitutions,
+        optional_string(arguments, "prefix")?.as_deref(),
+    ))
+}
+
+fn special_files(arguments: &Map<String, Value>) -> Result<SpecialFilePolicy, ToolCallError> {
+    match arguments.get("special_files") {
+        Some(Value::String(raw)) if raw == "store" => Ok(SpecialFilePolicy::Store),
+        Some(Value::String(raw)) if raw == "skip" => Ok(SpecialFilePolicy::Skip),
+        Some(Value::String(raw)) if raw == "fail" => Ok(SpecialFilePolicy::Fail),
+        Some(Value::Null) | None => Ok(SpecialFilePolicy::Store),
+        Some(_) => Err(invalid_params(
+            "special_files must be one of store, skip, fail",
+        )),
+    }
+}
+
+fn on_unreadable(arguments: &Map<String, Value>) -> Result<UnreadablePolicy, ToolCallError> {
+    match arguments.get("on_unreadable") {
+        Some(Value::String(raw)) if raw == "skip" => Ok(UnreadablePolicy::Skip),
+        Some(Value::String(raw)) if raw == "fail" => Ok(UnreadablePolicy::Fail),
+        Some(Value::Null) | None => Ok(UnreadablePolicy::Skip),
+        Some(_) => Err(invalid_params("on_unreadable must be one of skip, fail")),
+    }
+}
+
+fn permission_options(arguments: &Map<String, Value>) -> Result<PermissionOptions, ToolCallError> {
+    let id_map = |key: &str| -> Result<Vec<(u32, u32)>, ToolCallError> {
+        optional_strings(arguments, key)?
+            .unwrap_or_default()
+            .iter()
+            .map(|raw| PermissionOptions::parse_id_map(raw).map_err(invalid_params))
+            .collect()
+    };
+    let umask = match arguments.get("umask") {
+        Some(Value::String(raw)) => {
+            Some(PermissionOptions::parse_umask(raw).map_err(invalid_params)?)
+        }
+        Some(Value::Null) | None => None,
+        Some(_) => return Err(invalid_params("umask must be an octal string such as 022")),
+    };
+    Ok(PermissionOptions {
+        numeric_owner: optional_bool(arguments, "numeric_owner")?.unwrap_or(false),
+        uid_map: id_map("uid_map")?,
+        gid_map: id_map("gid_map")?,
+        no_same_permissions: optional_bool(arguments, "no_same_permissions")?.unwrap_or(false),
+        umask,
+        special_bits: optional_bool(arguments, "keep_special_bits")?.unwrap_or(false),
+    })
+}
+
+fn source_options(arguments: &Map<String, Value>) -> Result<SourceOptions, ToolCallError> {
+    Ok(SourceOptions {
+        remove: optional_bool(arguments, "remove_source")?.unwrap_or(false),
+        preserve_metadata: optional_bool(arguments, "preserve_metadata")?.unwrap_or(false),
+    })
+}
+
 fn required_file_type(
     arguments: &Map<String, Value>,
     key: &str,
@@ -429,8 +797,69 @@ fn ensure_allowed_path(path: &Path, state: &ServerState) -> Result<(), ToolCallE
     Ok(())
 }
 
+// New entries are stored the way compress stores them, so update takes the
+// same options for them.
+const UPDATE_ENTRY_OPTIONS: [&str; 16] = [
+    "include",
+    "exclude",
+    "exclude_from",
+    "gitignore",
+    "exclude_vcs",
+    "exclude_hidden",
+    "links",
+    "xattrs",
+    "tar_format",
+    "special_files",
+    "on_unreadable",
+    "strip_prefix",
+    "transform",
+    "prefix",
+    "reproducible",
+    "source_date_epoch",
+];
+
+fn update_definition(compress: &Value) -> Value {
+    let mut definition = json!({
+        "name": "update",
+        "description": "Add, replace or delete entries of an existing tar, tar.* or zip archive. An uncompressed tar is appended to in place when nothing in it is replaced or deleted; entries zip keeps are copied without being recompressed.",
+        "inputSchema": {
+            "type": "object",
+            "properties": {
+                "archive_path": {
+                    "type": "string",
+                    "description": "Archive to change."
+                },
+                "file_type": {
+                    "type": "string",
+                    "description": "Format of the archive.",
+                    "enum": ["zip", "tar", "tarbz2", "targz", "tar.bz2", "tar.gz", "tarxz", "tar.xz", "tarzst", "tar.zst", "tarlz4", "tar.lz4"]
+                },
+                "add": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Files and directories to store under the names compress gives them, replacing entries of the same name."
+                },
+                "delete": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Entry names to remove, along with everything below them."
+                }
+            },
+            "required": ["archive_path", "file_type"],
+            "additionalProperties": false
+        }
+    });
+    let shared = &compress["inputSchema"]["properties"];
+    if let Some(properties) = definition["inputSchema"]["properties"].as_object_mut() {
+        for key in UPDATE_ENTRY_OPTIONS {
+            properties.insert(key.to_string(), shared[key].clone());
+        }
+    }
+    definition
+}
+
 fn tool_definitions() -> Vec<Value> {
-    vec![
+    let mut tools = vec![
         json!({
             "name": "compress",
             "description": "Compress a file or directory into a supported archive format.",
@@ -449,6 +878,134 @@ fn tool_definitions() -> Vec<Value> {
                         "type": "string",
                         "description": "Archive format to create.",
                         "enum": ["zip", "tar", "bz2", "gz", "tarbz2", "targz", "tar.bz2", "tar.gz", "7z", "xz", "tarxz", "tar.xz", "zst", "tarzst", "tar.zst", "lz4", "tarlz4", "tar.lz4"]
+                    },
+                    "solid": {
+                        "type": "boolean",
+                        "description": "7z only: pack files into shared solid blocks.",
+                        "default": false
+                    },
+                    "solid_block_size": {
+                        "type": "integer",
+                        "description": "7z only: maximum uncompressed bytes per solid block.",
+                        "minimum": 1
+                    },
+                    "lzma2_preset": {
+                        "type": "integer",
+                        "description": "7z only: LZMA2 preset.",
+                        "default": 6,
+                        "minimum": 0,
+                        "maximum": 9
+                    },
+                    "lzma2_dict_size": {
+                        "type": "integer",
+                        "description": "7z only: LZMA2 dictionary size in bytes."
+                    },
+                    "sort_by_extension": {
+                        "type": "boolean",
+                        "description": "7z only: group files by extension inside solid blocks.",
+                        "default": false
+                    },
+                    "directory_mode": {
+                        "type": "string",
+                        "description": "gz, bz2, xz, zst and lz4 with a directory input: pack it into a tar first (tar, so gz gives .tar.gz) or compress every file next to itself (per-file, output_path must be left out).",
+                        "enum": ["tar", "per-file"],
+                        "default": "tar"
+                    },
+                    "no_name": {
+                        "type": "boolean",
+                        "description": "gz only: leave the original file name and mtime out of the header.",
+                        "default": false
+                    },
+                    "include": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Directory input: only pack files matching one of these globs. * and ? stay within one name, ** crosses directories, [...] is a character class and \\ escapes. A glob with a / matches the path below the input, one without the name alone."
+                    },
+                    "exclude": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Directory input: leave out files and directories matching any of these globs, written as for include. A trailing / only matches directories."
+                    },
+                    "exclude_from": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Directory input: files with more exclude globs, one per line; blank lines and # comments are skipped. Relative paths resolve against the session cwd."
+                    },
+                    "gitignore": {
+                        "type": "boolean",
+                        "description": "Directory input: honour .gitignore and .ignore files in the tree.",
+                        "default": false
+                    },
+                    "exclude_vcs": {
+                        "type": "boolean",
+                        "description": "Directory input: leave out .git, .hg, .svn and other version control directories.",
+                        "default": false
+                    },
+                    "exclude_hidden": {
+                        "type": "boolean",
+                        "description": "Directory input: leave out files and directories whose name starts with a dot.",
+                        "default": false
+                    },
+                    "links": {
+                        "type": "string",
+                        "description": "Directory input: store symlinks as links, follow them (links back into their own ancestors are left out; refused when MAGIC_PACK_MCP_ALLOWED_ROOT is set) or skip them. Hardlinked files are stored once in tar, as a full copy per name in zip and 7z.",
+                        "enum": ["store", "follow", "skip"],
+                        "default": "store"
+                    },
+                    "xattrs": {
+                        "type": "boolean",
+                        "description": "tar.* only: store extended attributes, POSIX ACLs and file capabilities as PAX SCHILY.xattr records.",
+                        "default": false
+                    },
+                    "tar_format": {
+                        "type": "string",
+                        "description": "tar.* only: header format. ustar refuses entries it cannot represent (long names or link targets, 8 GiB files, large ids); pax stores them in extended headers along with sub-second mtimes.",
+                        "enum": ["gnu", "ustar", "pax"],
+                        "default": "gnu"
+                    },
+                    "special_files": {
+                        "type": "string",
+                        "description": "Directory input: FIFOs and device nodes are stored in tar (zip and 7z leave them out with a warning, as does every format for sockets), skipped with a warning, or fail the request.",
+                        "enum": ["store", "skip", "fail"],
+                        "default": "store"
+                    },
+                    "on_unreadable": {
+                        "type": "string",
+                        "description": "Directory input: files and directories that cannot be read (no permission, a followed link looping back to an ancestor) are left out with a warning, or fail the request.",
+                        "enum": ["skip", "fail"],
+                        "default": "skip"
+                    },
+                    "strip_prefix": {
+                        "type": "string",
+                        "description": "tar.*, zip and 7z: take this leading directory off the entry names below it; the directory entry itself is left out."
+                    },
+                    "transform": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "tar.*, zip and 7z: rename entries with sed expressions like tar --transform, e.g. s,^build/out,dist, (extended regex, & and \\1..\\9 in the replacement, g and i flags). Applied in order after strip_prefix."
+                    },
+                    "prefix": {
+                        "type": "string",
+                        "description": "tar.*, zip and 7z: put every entry below this directory, after the other renames."
+                    },
+                    "reproducible": {
+                        "type": "boolean",
+                        "description": "tar.*, zip and 7z: produce the same bytes for the same tree (sorted entries, no owners, 0644/0755 modes, fixed header times).",
+                        "default": false
+                    },
+                    "source_date_epoch": {
+                        "type": "integer",
+                        "description": "With reproducible: clamp entry mtimes to this Unix time. Defaults to the server's SOURCE_DATE_EPOCH."
+                    },
+                    "remove_source": {
+                        "type": "boolean",
+                        "description": "gz, bz2, xz, zst and lz4 only: delete the input file once the output decodes back to it.",
+                        "default": false
+                    },
+                    "preserve_metadata": {
+                        "type": "boolean",
+                        "description": "gz, bz2, xz, zst and lz4 only: copy the input's permissions, timestamps and (when privileged) owner to the output.",
+                        "default": false
                     }
                 },
                 "required": ["input_path", "file_type"],
@@ -470,10 +1027,160 @@ fn tool_definitions() -> Vec<Value> {
                         "description": "Destination directory. Defaults to the current directory."
                     },
                     "level": {
+                        "description": "Maximum nested archive layers to unpack, or \"auto\" to unpack until the content is no longer an archive (capped by max_depth).",
+                        "oneOf": [
+                            { "type": "integer", "minimum": 1 },
+                            { "type": "string", "enum": ["auto"] }
+                        ],
+                        "default": 5
+                    },
+                    "on_unsafe_entry": {
+                        "type": "string",
+                        "description": "What to do with entries that would escape output_path: abort (error) or leave them out with a warning (skip).",
+                        "enum": ["error", "skip"],
+                        "default": "error"
+                    },
+                    "on_conflict": {
+                        "type": "string",
+                        "description": "What to do when an output file already exists: abort (error), keep it (skip), replace it (overwrite), keep both as name_1.ext (rename), or replace only older files (newer).",
+                        "enum": ["error", "skip", "overwrite", "rename", "newer"],
+                        "default": "overwrite"
+                    },
+                    "max_output_bytes": {
                         "type": "integer",
-                        "description": "Maximum nested archive layers to unpack.",
-                        "default": 5,
-                        "minimum": 1
+                        "description": "Abort when one layer writes more than this many bytes.",
+                        "default": DEFAULT_MAX_OUTPUT_BYTES,
+                        "minimum": 0
+                    },
+                    "max_entry_bytes": {
+                        "type": "integer",
+                        "description": "Abort when a single entry is larger than this many bytes.",
+                        "default": DEFAULT_MAX_ENTRY_BYTES,
+                        "minimum": 0
+                    },
+                    "max_ratio": {
+                        "type": "integer",
+                        "description": "Abort when output grows past this many bytes per input byte.",
+                        "default": DEFAULT_MAX_RATIO,
+                        "minimum": 0
+                    },
+                    "max_entries": {
+                        "type": "integer",
+                        "description": "Abort when an archive holds more entries than this.",
+                        "default": DEFAULT_MAX_ENTRIES,
+                        "minimum": 0
+                    },
+                    "max_depth": {
+                        "type": "integer",
+                        "description": "Abort when the input is nested deeper than this many formats.",
+                        "default": DEFAULT_MAX_DEPTH,
+                        "minimum": 0
+                    },
+                    "destination": {
+                        "type": "string",
+                        "description": "Where archive entries go: a directory named after the archive (subdir), as-is when the archive holds a single top-level directory (smart), or output_path itself (here).",
+                        "enum": ["subdir", "smart", "here"],
+                        "default": "subdir"
+                    },
+                    "strip_components": {
+                        "type": "integer",
+                        "description": "Leading path components to drop from every archive entry.",
+                        "default": 0,
+                        "minimum": 0
+                    },
+                    "restore_name": {
+                        "type": "boolean",
+                        "description": "gz only: name the output after the file name stored in the header and restore the stored mtime.",
+                        "default": false
+                    },
+                    "xattrs": {
+                        "type": "boolean",
+                        "description": "tar.* only: restore extended attributes stored as PAX SCHILY.xattr records. Ones the filesystem or privileges don't allow are reported as warnings.",
+                        "default": false
+                    },
+                    "special_files": {
+                        "type": "string",
+                        "description": "FIFO and device node entries: recreate them (device nodes only with allow_devices, as root), skip them with a warning, or fail the request.",
+                        "enum": ["store", "skip", "fail"],
+                        "default": "store"
+                    },
+                    "strip_prefix": {
+                        "type": "string",
+                        "description": "After strip_components: take this leading directory off the entry names below it; the directory entry itself is left out."
+                    },
+                    "transform": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "After strip_components: rename entries with sed expressions like tar --transform, e.g. s,^build/out,dist, (extended regex, & and \\1..\\9 in the replacement, g and i flags). Applied in order after strip_prefix."
+                    },
+                    "prefix": {
+                        "type": "string",
+                        "description": "After strip_components: put every entry below this directory, after the other renames."
+                    },
+                    "allow_devices": {
+                        "type": "boolean",
+                        "description": "Let special_files=store create device nodes. Also needs the server to run as root.",
+                        "default": false
+                    },
+                    "numeric_owner": {
+                        "type": "boolean",
+                        "description": "Give extracted entries the numeric uid/gid stored in the archive (tar, and zip from Info-ZIP). Only takes effect when the server runs as root.",
+                        "default": false
+                    },
+                    "uid_map": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "With numeric_owner: stored uids to restore as other ones, each as FROM:TO."
+                    },
+                    "gid_map": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "With numeric_owner: stored gids to restore as other ones, each as FROM:TO."
+                    },
+                    "no_same_permissions": {
+                        "type": "boolean",
+                        "description": "Ignore the modes stored in the archive: files get 0666 and directories 0777, less the umask.",
+                        "default": false
+                    },
+                    "umask": {
+                        "type": "string",
+                        "description": "Octal permission bits to clear from every extracted mode, e.g. \"022\". no_same_permissions uses the server's umask without it."
+                    },
+                    "keep_special_bits": {
+                        "type": "boolean",
+                        "description": "Keep setuid, setgid and sticky bits stored in the archive instead of clearing them.",
+                        "default": false
+                    },
+                    "remove_source": {
+                        "type": "boolean",
+                        "description": "Delete the input archive once everything in it has been written out.",
+                        "default": false
+                    },
+                    "preserve_metadata": {
+                        "type": "boolean",
+                        "description": "Single-file outputs only: copy the archive's permissions, timestamps and (when privileged) owner to the output.",
+                        "default": false
+                    },
+                    "recursive": {
+                        "type": "boolean",
+                        "description": "Also unpack archives found inside the output, each into a sibling <name>.d directory. The outer and all inner archives share one output byte and entry budget.",
+                        "default": false
+                    },
+                    "recursive_depth": {
+                        "type": "integer",
+                        "description": "How many archive-in-archive levels recursive unpacking follows.",
+                        "default": DEFAULT_RECURSIVE_DEPTH,
+                        "minimum": 0
+                    },
+                    "recursive_include": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Only unpack nested archives matching one of these globs, written as for include: a glob with a / matches the relative path, one without the name alone."
+                    },
+                    "delete_nested": {
+                        "type": "boolean",
+                        "description": "Remove nested archives once they have been unpacked.",
+                        "default": false
                     }
                 },
                 "required": ["input_path"],
@@ -504,7 +1211,10 @@ fn tool_definitions() -> Vec<Value> {
                 "additionalProperties": false
             }
         }),
-    ]
+    ];
+    let update = update_definition(&tools[0]);
+    tools.insert(2, update);
+    tools
 }
 
 fn file_type_name(file_type: FileType) -> &'static str {
@@ -2,6 +2,9 @@ use clap::{ArgGroup, Parser};
 use std::path;
 
 use magic_pack::contents::enums;
+use magic_pack::modules::options::PermissionOptions;
+use magic_pack::modules::transform::Transform;
+use magic_pack::service::Level;
 
 #[derive(Parser)]
 #[command(
@@ -15,24 +18,53 @@ use magic_pack::contents::enums;
   magic-pack -d -o temp/. temp/temp.zip
   magic-pack -c -f gz -o temp/file.txt.gz temp/file.txt
   magic-pack -d -o temp/. temp/file.txt.gz
+  magic-pack -c -f gz --no-name -o temp/file.txt.gz temp/file.txt
+  magic-pack -c -f zst src
+  magic-pack -c -f tar --links follow -o temp/temp.tar src
+  magic-pack -c -f zip --gitignore --exclude-vcs --exclude 'node_modules' -o temp/temp.zip src
+  magic-pack -c -f gz --directory-mode per-file logs
+  magic-pack -d --restore-name -o temp/. temp/download.gz
+  magic-pack -c -f xz --remove-source --preserve-metadata temp/file.txt
+  magic-pack -d --remove-source --preserve-metadata -o temp/. temp/file.txt.xz
   magic-pack -c -f bz2 -o temp/file.txt.bz2 temp/file.txt
   magic-pack -d -o temp/. temp/file.txt.bz2
   magic-pack -c -f tar -o temp/temp.tar src
   magic-pack -d -o temp/. temp/temp.tar
+  magic-pack -c -f tar --xattrs -o temp/rootfs.tar rootfs
+  magic-pack -c -f targz --tar-format ustar -o temp/temp.tar.gz src
+  magic-pack -d --xattrs -o temp/. temp/rootfs.tar
   magic-pack -c -f tarbz2 -o temp/temp.tar.bz2 src
   magic-pack -d -o temp/. temp/temp.tar.bz2
   magic-pack -c -f targz -o temp/temp.tar.gz src
   magic-pack -d -o temp/. temp/temp.tar.gz
   magic-pack -c -f seven-z -o temp/temp.7z src
+  SOURCE_DATE_EPOCH=1700000000 magic-pack -c -f targz --reproducible -o temp/temp.tar.gz src
+  magic-pack -c -f seven-z --solid --solid-block-size 64m --sort-by-extension -o temp/temp.7z src
   magic-pack -d -o temp/. temp/temp.7z
   magic-pack -d -l 3 -o temp/. temp/archive.tar.gz
+  magic-pack -d -l auto -o temp/. temp/archive.tar.gz
+  magic-pack -d --recursive --recursive-include '*.tar.gz' --delete-nested -o temp/. delivery.zip
   magic-pack -d temp/temp.zip
+  magic-pack -d --on-unsafe-entry skip -o temp/. untrusted.zip
+  magic-pack -d --on-conflict rename -o temp/. temp/temp.zip
+  magic-pack -d --destination smart -o temp/. temp/temp.tar.gz
+  magic-pack -d --destination here --strip-components 1 -o temp/. temp/temp.tar.gz
+  magic-pack -d --numeric-owner --uid-map 1000:1001 --keep-special-bits -o temp/. rootfs.tar
+  magic-pack -d --no-same-permissions --umask 027 -o temp/. temp/temp.zip
+  magic-pack -c -f tar --special-files fail -o temp/temp.tar src
+  magic-pack -c -f zip --on-unreadable fail -o temp/temp.zip src
+  magic-pack -c -f targz --strip-prefix src --prefix project-1.2.3 -o temp/temp.tar.gz src
+  magic-pack -d --transform 's,^build/out,dist,' -o temp/. temp/temp.zip
+  sudo magic-pack -d --allow-devices --numeric-owner -o temp/. rootfs.tar
+  magic-pack -u -f tar --add src temp/temp.tar
+  magic-pack -u -f zip --add src/app.js --prefix src --delete src/old.js temp/temp.zip
+  magic-pack -d --max-output-size 1g --max-ratio 100 --max-entries 10000 -o temp/. untrusted.tar.gz
 "
 )]
 #[command(group(
     ArgGroup::new("functional")
         .required(true)
-        .args(["compress", "decompress"]),
+        .args(["compress", "decompress", "update"]),
 ))]
 pub struct Args {
     // Compress flag
@@ -47,11 +79,241 @@ pub struct Args {
     #[arg(short, long)]
     pub decompress: bool,
 
-    // decompress level
+    // Update flag: change the input archive in place
+    #[arg(short, long, requires = "file_type")]
+    pub update: bool,
+
+    // with --update: store this file or directory, replacing entries of the
+    // same name, repeatable
+    #[arg(long, requires = "update")]
+    pub add: Vec<path::PathBuf>,
+
+    // with --update: remove this entry and everything below it, repeatable
+    #[arg(long, requires = "update")]
+    pub delete: Vec<path::PathBuf>,
+
+    // decompress level: how many layers to unpack, or auto to unpack until done
     #[arg(short, long, default_value = "5")]
-    pub level: i8,
+    pub level: Level,
+
+    // 7z: pack files into solid blocks
+    #[arg(long)]
+    pub solid: bool,
+
+    // 7z: maximum uncompressed size of one solid block, e.g. 64m
+    #[arg(long, requires = "solid", value_parser = parse_size)]
+    pub solid_block_size: Option<u64>,
+
+    // 7z: LZMA2 preset
+    #[arg(long, default_value = "6", value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub lzma2_preset: u32,
+
+    // 7z: LZMA2 dictionary size, e.g. 16m
+    #[arg(long, value_parser = parse_size)]
+    pub lzma2_dict_size: Option<u64>,
+
+    // 7z: group files by extension inside solid blocks
+    #[arg(long)]
+    pub sort_by_extension: bool,
+
+    // gz, bz2, xz, zst, lz4 with a directory input: pack it into a tar
+    // first (tar), or compress every file next to itself (per-file)
+    #[arg(long, value_enum, default_value = "tar")]
+    pub directory_mode: enums::DirectoryMode,
+
+    // gz: leave the original file name and mtime out of the header
+    #[arg(long)]
+    pub no_name: bool,
+
+    // gz: name the output after the name stored in the header and restore
+    // its mtime
+    #[arg(long)]
+    pub restore_name: bool,
+
+    // directory input: only pack files matching this glob, repeatable. `*`
+    // and `?` stay within one name, `**` crosses directories, `[...]` is a
+    // class, `\` escapes; with a `/` the glob matches the path below the
+    // input, without one the name alone
+    #[arg(long)]
+    pub include: Vec<String>,
+
+    // directory input: leave out files and directories matching this glob,
+    // repeatable; a trailing `/` only matches directories
+    #[arg(long)]
+    pub exclude: Vec<String>,
+
+    // directory input: read more exclude globs from this file, one per line
+    // (blank lines and `#` comments skipped), repeatable
+    #[arg(long)]
+    pub exclude_from: Vec<path::PathBuf>,
+
+    // directory input: honour .gitignore and .ignore files in the tree
+    #[arg(long)]
+    pub gitignore: bool,
+
+    // directory input: leave out .git, .hg, .svn and other VCS directories
+    #[arg(long)]
+    pub exclude_vcs: bool,
+
+    // directory input: leave out files and directories starting with a dot
+    #[arg(long)]
+    pub exclude_hidden: bool,
+
+    // directory input: store symlinks as links, follow them or leave them out
+    #[arg(long, value_enum, default_value = "store")]
+    pub links: enums::LinkPolicy,
+
+    // tar.*, zip, 7z: the same bytes for the same tree (sorted entries, no
+    // owners, 0644/0755 modes, fixed header times)
+    #[arg(long)]
+    pub reproducible: bool,
+
+    // tar.*: header format; ustar refuses entries it can't represent, pax
+    // adds extended headers for them
+    #[arg(long, value_enum, default_value = "gnu")]
+    pub tar_format: enums::TarFormat,
+
+    // FIFOs, device nodes and sockets: store them (tar only; zip, 7z and
+    // sockets are left out with a warning), skip them with a warning, or
+    // fail. Applies to both compressing and decompressing
+    #[arg(long, value_enum, default_value = "store")]
+    pub special_files: enums::SpecialFilePolicy,
+
+    // files and directories below a directory input that can't be read:
+    // leave them out with a warning, or fail
+    #[arg(long, value_enum, default_value = "skip")]
+    pub on_unreadable: enums::UnreadablePolicy,
+
+    // with --special-files store: create device nodes found in an archive,
+    // which also takes root
+    #[arg(long)]
+    pub allow_devices: bool,
+
+    // tar.*: store extended attributes, POSIX ACLs and file capabilities
+    // when compressing, and restore them when decompressing
+    #[arg(long)]
+    pub xattrs: bool,
+
+    // with --reproducible: clamp entry mtimes to this Unix time, read from
+    // SOURCE_DATE_EPOCH when not given
+    #[arg(long)]
+    pub source_date_epoch: Option<i64>,
+
+    // gz, bz2, xz, zst, lz4: delete the input once the output is complete
+    // (on compress, once it decodes back to the input)
+    #[arg(long, conflicts_with = "keep_source")]
+    pub remove_source: bool,
 
-    // file / directory input path
+    // gz, bz2, xz, zst, lz4: leave the input in place (the default)
+    #[arg(long)]
+    pub keep_source: bool,
+
+    // gz, bz2, xz, zst, lz4: copy the input's permissions, timestamps and,
+    // when run as root, owner to the output
+    #[arg(long)]
+    pub preserve_metadata: bool,
+
+    // rename entries when compressing or decompressing: take this leading
+    // directory off the names below it
+    #[arg(long)]
+    pub strip_prefix: Option<String>,
+
+    // rename entries with a sed expression like tar --transform, e.g.
+    // 's,^build/out,dist,'; repeatable, applied after --strip-prefix
+    #[arg(long, value_parser = Transform::parse)]
+    pub transform: Vec<Transform>,
+
+    // put every entry below this directory, applied after --transform
+    #[arg(long)]
+    pub prefix: Option<String>,
+
+    // what to do with archive entries that would escape the output directory
+    #[arg(long, value_enum, default_value = "error")]
+    pub on_unsafe_entry: enums::UnsafeEntryPolicy,
+
+    // what to do when an output file already exists
+    #[arg(long, value_enum, default_value = "overwrite")]
+    pub on_conflict: enums::ConflictPolicy,
+
+    // where archive entries go: a directory named after the archive (subdir),
+    // as-is when the archive has one top-level directory (smart), or the
+    // output directory itself (here)
+    #[arg(long, value_enum, default_value = "subdir")]
+    pub destination: enums::Destination,
+
+    // drop this many leading path components from every archive entry
+    #[arg(long, default_value = "0")]
+    pub strip_components: u32,
+
+    // give extracted entries the numeric owner stored in the archive (tar,
+    // and zip from Info-ZIP); only takes effect as root
+    #[arg(long)]
+    pub numeric_owner: bool,
+
+    // with --numeric-owner: restore a stored uid as another one, FROM:TO,
+    // repeatable
+    #[arg(long, requires = "numeric_owner", value_parser = PermissionOptions::parse_id_map)]
+    pub uid_map: Vec<(u32, u32)>,
+
+    // with --numeric-owner: restore a stored gid as another one, FROM:TO,
+    // repeatable
+    #[arg(long, requires = "numeric_owner", value_parser = PermissionOptions::parse_id_map)]
+    pub gid_map: Vec<(u32, u32)>,
+
+    // ignore the modes stored in the archive: files get 0666 and directories
+    // 0777, less the umask
+    #[arg(long)]
+    pub no_same_permissions: bool,
+
+    // octal permission bits to clear from every extracted mode, e.g. 022;
+    // --no-same-permissions uses the process umask without it
+    #[arg(long, value_parser = PermissionOptions::parse_umask)]
+    pub umask: Option<u32>,
+
+    // keep setuid, setgid and sticky bits stored in the archive instead of
+    // clearing them
+    #[arg(long)]
+    pub keep_special_bits: bool,
+
+    // abort when one layer writes more than this, e.g. 10g
+    #[arg(long, value_parser = parse_size)]
+    pub max_output_size: Option<u64>,
+
+    // abort when a single entry is larger than this, e.g. 2g
+    #[arg(long, value_parser = parse_size)]
+    pub max_entry_size: Option<u64>,
+
+    // abort when output grows past this many bytes per input byte
+    #[arg(long)]
+    pub max_ratio: Option<u64>,
+
+    // abort when an archive holds more entries than this
+    #[arg(long)]
+    pub max_entries: Option<u64>,
+
+    // abort when the input is nested deeper than this many formats
+    #[arg(long)]
+    pub max_depth: Option<u32>,
+
+    // also unpack archives found inside the output, each into <name>.d/;
+    // together they stay within --max-output-size and --max-entries
+    #[arg(long)]
+    pub recursive: bool,
+
+    // how many archive-in-archive levels --recursive follows
+    #[arg(long, requires = "recursive", default_value = "4")]
+    pub recursive_depth: u32,
+
+    // only unpack nested archives matching this glob (as for --include),
+    // repeatable
+    #[arg(long, requires = "recursive")]
+    pub recursive_include: Vec<String>,
+
+    // remove nested archives once they have been unpacked
+    #[arg(long, requires = "recursive")]
+    pub delete_nested: bool,
+
+    // file / directory input path, the archive itself with --update
     pub input: path::PathBuf,
 
     // file / directory output path
@@ -64,3 +326,20 @@ impl Args {
         Args::parse()
     }
 }
+
+// Accepts a plain byte count or one with a k/m/g suffix (powers of 1024).
+fn parse_size(raw: &str) -> Result<u64, String> {
+    let raw = raw.trim();
+    let (digits, shift) = match raw.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('k') => (&raw[..raw.len() - 1], 10),
+        Some('m') => (&raw[..raw.len() - 1], 20),
+        Some('g') => (&raw[..raw.len() - 1], 30),
+        _ => (raw, 0),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size: {}", raw))?;
+    value
+        .checked_mul(1u64 << shift)
+        .ok_or_else(|| format!("size too large: {}", raw))
+}
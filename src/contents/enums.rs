@@ -17,6 +17,107 @@ pub enum FileType {
     Tarlz4,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum UnsafeEntryPolicy {
+    // abort extraction on the first unsafe entry
+    #[default]
+    Error,
+    // leave the entry out and record a warning
+    Skip,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum ConflictPolicy {
+    // abort when something already exists at an output path
+    Error,
+    // keep the existing file and leave the new one out
+    Skip,
+    // replace the existing file
+    #[default]
+    Overwrite,
+    // keep both, writing the new one as name_1.ext, name_2.ext, ...
+    Rename,
+    // replace the existing file only if the new one is more recent
+    Newer,
+}
+
+// Where a container's entries go below the output directory.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum Destination {
+    // always into a directory named after the archive
+    #[default]
+    Subdir,
+    // as-is when everything sits under one top-level directory, otherwise
+    // into a directory named after the archive
+    Smart,
+    // straight into the output directory
+    Here,
+}
+
+// What a single-stream codec (gz, bz2, xz, zst, lz4) does with a directory.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum DirectoryMode {
+    // pack the tree into a tar first, so gz gives .tar.gz
+    #[default]
+    Tar,
+    // compress every file on its own, next to the original, like gzip -r
+    PerFile,
+}
+
+// How compression treats symbolic links below a directory input.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum LinkPolicy {
+    // store the link itself, pointing where it pointed
+    #[default]
+    Store,
+    // store what the link points at, descending into linked directories;
+    // links that lead back into their own ancestors are left out
+    Follow,
+    // leave links out
+    Skip,
+}
+
+// Header format of the tar.* variants.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum TarFormat {
+    // GNU tar's format: long names and links in extension entries, large
+    // numbers in base-256, sparse files as old GNU sparse entries
+    #[default]
+    Gnu,
+    // strict POSIX ustar: names up to 255 bytes split at a slash, links up to
+    // 100 bytes, sizes below 8 GiB, ids below 2097152 and whole-second mtimes
+    Ustar,
+    // ustar plus PAX extended headers for whatever doesn't fit, mtimes with
+    // sub-second precision, and sparse files in GNU's PAX 1.0 layout
+    Pax,
+}
+
+// What happens to FIFOs, device nodes and sockets.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum SpecialFilePolicy {
+    // tar stores FIFOs and device nodes, zip and 7z can't and leave them out
+    // with a warning; extraction recreates FIFOs, and device nodes only when
+    // they are allowed and we run as root
+    #[default]
+    Store,
+    // leave them out and record a warning
+    Skip,
+    // abort on the first one
+    Fail,
+}
+
+// What happens to files and directories below a compression input that
+// can't be read, such as ones without read permission or a followed link
+// that loops back to an ancestor.
+#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum, Debug)]
+pub enum UnreadablePolicy {
+    // lenient: leave them out and record a warning for each
+    #[default]
+    Skip,
+    // strict: abort on the first one
+    Fail,
+}
+
 pub fn get_file_type_string(file_type: FileType) -> &'static str {
     match file_type {
         FileType::Zip => "zip",
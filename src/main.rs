@@ -1,10 +1,22 @@
 mod cli;
 
 use crate::cli::Args;
-use magic_pack::service::{self, CompressRequest, DecompressRequest};
+use magic_pack::contents::enums::{self, FileType};
+use magic_pack::modules::options::{
+    CompressOptions, DecompressOptions, FilterOptions, GzipOptions, Limits, NestedOptions,
+    PermissionOptions, ReproducibleOptions, SevenZOptions, SourceOptions,
+};
+use magic_pack::modules::transform::Transform;
+use magic_pack::service::{self, CompressRequest, DecompressRequest, UpdateRequest};
 
 fn main() {
     let args = Args::new();
+    if let Err(err) = service::remove_staged_on_signal() {
+        eprintln!(
+            "Warning: partial output won't be removed on Ctrl-C: {}",
+            err
+        );
+    }
 
     println!("Input path: {:?}", args.input);
     println!("Output path: {:?}", args.output);
@@ -15,10 +27,27 @@ fn main() {
             file_type: args.file_type.unwrap(),
             input: args.input.clone(),
             output: args.output.clone(),
+            options: compress_options(&args),
+        }) {
+            Ok(result) => result,
+            Err(err) => exit_with_error(err),
+        };
+        print_warnings(&result.warnings);
+        println!("Output file: {:?}", result.output_path);
+    }
+    if args.update {
+        println!("Update");
+        let result = match service::update(UpdateRequest {
+            file_type: args.file_type.unwrap(),
+            archive: args.input.clone(),
+            add: args.add.clone(),
+            delete: args.delete.clone(),
+            options: compress_options(&args),
         }) {
             Ok(result) => result,
             Err(err) => exit_with_error(err),
         };
+        print_warnings(&result.warnings);
         println!("Output file: {:?}", result.output_path);
     }
     if args.decompress {
@@ -27,14 +56,125 @@ fn main() {
             input: args.input.clone(),
             output: args.output.clone(),
             level: args.level,
+            options: decompress_options(&args),
         }) {
             Ok(result) => result,
             Err(err) => exit_with_error(err),
         };
+        print_warnings(&result.warnings);
+        print_layers(&result.layers);
+        for archive in &result.nested_archives {
+            println!("Nested archive: {:?}", archive);
+        }
         println!("Output file: {:?}", result.output_path);
     }
 }
 
+fn compress_options(args: &Args) -> CompressOptions {
+    let dict_size = args
+        .lzma2_dict_size
+        .map(|size| u32::try_from(size).unwrap_or(u32::MAX));
+    CompressOptions {
+        sevenz: SevenZOptions {
+            solid: args.solid,
+            solid_block_size: args.solid_block_size,
+            preset: args.lzma2_preset,
+            dict_size,
+            sort_by_extension: args.sort_by_extension,
+        },
+        gzip: GzipOptions {
+            store_name: !args.no_name,
+        },
+        directories: args.directory_mode,
+        source: source_options(args),
+        filter: FilterOptions {
+            include: args.include.clone(),
+            exclude: args.exclude.clone(),
+            exclude_from: args.exclude_from.clone(),
+            gitignore: args.gitignore,
+            exclude_vcs: args.exclude_vcs,
+            exclude_hidden: args.exclude_hidden,
+        },
+        links: args.links,
+        xattrs: args.xattrs,
+        tar_format: args.tar_format,
+        special_files: args.special_files,
+        transforms: transforms(args),
+        unreadable: args.on_unreadable,
+        reproducible: ReproducibleOptions {
+            enabled: args.reproducible,
+            source_date_epoch: args
+                .source_date_epoch
+                .or_else(ReproducibleOptions::source_date_epoch_from_env),
+        },
+    }
+}
+
+fn decompress_options(args: &Args) -> DecompressOptions {
+    DecompressOptions {
+        unsafe_entries: args.on_unsafe_entry,
+        conflicts: args.on_conflict,
+        limits: Limits {
+            max_total_bytes: args.max_output_size,
+            max_entry_bytes: args.max_entry_size,
+            max_ratio: args.max_ratio,
+            max_entries: args.max_entries,
+            max_depth: args.max_depth,
+        },
+        nested: NestedOptions {
+            enabled: args.recursive,
+            max_depth: args.recursive_depth,
+            include: args.recursive_include.clone(),
+            delete_archives: args.delete_nested,
+        },
+        destination: args.destination,
+        strip_components: args.strip_components,
+        restore_name: args.restore_name,
+        source: source_options(args),
+        xattrs: args.xattrs,
+        permissions: PermissionOptions {
+            numeric_owner: args.numeric_owner,
+            uid_map: args.uid_map.clone(),
+            gid_map: args.gid_map.clone(),
+            no_same_permissions: args.no_same_permissions,
+            umask: args.umask,
+            special_bits: args.keep_special_bits,
+        },
+        special_files: args.special_files,
+        allow_devices: args.allow_devices,
+        transforms: transforms(args),
+    }
+}
+
+fn transforms(args: &Args) -> Vec<Transform> {
+    Transform::chain(
+        args.strip_prefix.as_deref(),
+        &args.transform,
+        args.prefix.as_deref(),
+    )
+}
+
+fn source_options(args: &Args) -> SourceOptions {
+    SourceOptions {
+        remove: args.remove_source,
+        preserve_metadata: args.preserve_metadata,
+    }
+}
+
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+fn print_layers(layers: &[FileType]) {
+    let names: Vec<&str> = layers
+        .iter()
+        .map(|layer| enums::get_file_type_string(*layer))
+        .collect();
+    println!("Layers: {}", names.join(", "));
+}
+
 fn exit_with_error(err: service::MagicPackError) -> ! {
     eprintln!("Error: {}", err);
     std::process::exit(1);
@@ -5,6 +5,10 @@ use bzip2;
 use bzip2::read::BzDecoder;
 use bzip2::write::BzEncoder;
 
+use crate::modules::error::ArchiveError;
+use crate::modules::limits::Budget;
+use crate::modules::options::DecompressOptions;
+
 pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     let bz2_file = File::create(dst_path).expect("bz2 create failed");
     let mut enc = BzEncoder::new(bz2_file, bzip2::Compression::default());
@@ -16,14 +20,21 @@ pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     enc.finish().expect("bz2 open failed");
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<(), ArchiveError> {
     let bz2_file = File::open(src_path).expect("bz2 open failed");
-    let dec = BzDecoder::new(bz2_file);
-    let mut reader = std::io::BufReader::new(dec);
-    let mut content = Vec::new();
-    reader.read_to_end(&mut content).expect("bz2 unpack failed");
-    let mut dst_file = File::create(dst_path).expect("bz2 unpack failed");
-    dst_file
-        .write_all(content.as_slice())
-        .expect("bz2 unpack failed");
+    let mut dec = BzDecoder::new(bz2_file);
+    let mut dst_file = File::create(dst_path).expect("bz2 create dst failed");
+    let mut budget = Budget::new(&options.limits, src_path);
+    budget.add_entry()?;
+    budget.copy("bz2 unpack", &mut dec, &mut dst_file)?;
+    Ok(())
+}
+
+// The decoded stream, for reading the output back after compressing.
+pub fn decoder(file: File) -> Box<dyn Read> {
+    Box::new(BzDecoder::new(file))
 }
@@ -1,29 +1,90 @@
+use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io;
+use std::io::Read;
 
+use filetime::FileTime;
 use flate2;
 use flate2::read::GzDecoder;
-use flate2::write::GzEncoder;
+use flate2::GzBuilder;
 
-pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+use crate::modules::error::ArchiveError;
+use crate::modules::limits::Budget;
+use crate::modules::options::{DecompressOptions, GzipOptions};
+
+// The original name and modification time a gzip header carries (FNAME and
+// MTIME), when the compressor recorded them.
+#[derive(Debug, Default)]
+pub struct Stored {
+    pub name: Option<OsString>,
+    pub mtime: Option<FileTime>,
+}
+
+pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path, options: &GzipOptions) {
     let gz_file = File::create(dst_path).expect("gz create failed");
-    let mut enc = GzEncoder::new(gz_file, flate2::Compression::default());
+    // Like gzip -n, leaving both fields empty keeps the output a function of
+    // the content alone.
+    let mut builder = GzBuilder::new().mtime(0);
+    if options.store_name {
+        let metadata = fs::metadata(src_path).expect("gz stat failed");
+        let mtime = FileTime::from_last_modification_time(&metadata).unix_seconds();
+        builder = builder.mtime(u32::try_from(mtime.max(0)).unwrap_or(u32::MAX));
+        if let Some(name) = src_path.file_name() {
+            builder = builder.filename(name_bytes(name));
+        }
+    }
+    let mut enc = builder.write(gz_file, flate2::Compression::default());
 
     let mut src_file = File::open(src_path).expect("gz open failed");
-    let mut content = Vec::new();
-    src_file.read_to_end(&mut content).expect("gz open failed");
-    enc.write_all(content.as_slice()).expect("gz open failed");
-    enc.finish().expect("gz open failed");
+    io::copy(&mut src_file, &mut enc).expect("gz write failed");
+    enc.finish().expect("gz finish failed");
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<Stored, ArchiveError> {
     let gz_file = File::open(src_path).expect("gz open failed");
-    let dec = GzDecoder::new(gz_file);
-    let mut reader = std::io::BufReader::new(dec);
-    let mut content = Vec::new();
-    reader.read_to_end(&mut content).expect("gz unpack failed");
-    let mut dst_file = File::create(dst_path).expect("gz unpack failed");
-    dst_file
-        .write_all(content.as_slice())
-        .expect("gz unpack failed");
+    let mut dec = GzDecoder::new(gz_file);
+    let mut dst_file = File::create(dst_path).expect("gz create dst failed");
+    let mut budget = Budget::new(&options.limits, src_path);
+    budget.add_entry()?;
+    budget.copy("gz unpack", &mut dec, &mut dst_file)?;
+    Ok(match dec.header() {
+        Some(header) => Stored {
+            name: header.filename().map(name_from_bytes),
+            mtime: (header.mtime() != 0)
+                .then(|| FileTime::from_unix_time(i64::from(header.mtime()), 0)),
+        },
+        None => Stored::default(),
+    })
+}
+
+// The decoded stream, for reading the output back after compressing.
+pub fn decoder(file: File) -> Box<dyn Read> {
+    Box::new(GzDecoder::new(file))
+}
+
+#[cfg(unix)]
+fn name_bytes(name: &std::ffi::OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn name_bytes(name: &std::ffi::OsStr) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn name_from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes.to_vec())
+}
+
+#[cfg(not(unix))]
+fn name_from_bytes(bytes: &[u8]) -> OsString {
+    OsString::from(String::from_utf8_lossy(bytes).into_owned())
 }
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::DirEntry;
+
+use crate::contents::enums::{LinkPolicy, SpecialFilePolicy};
+use crate::modules::error::SpecialKind;
+
+// What a walked entry goes into the archive as.
+pub enum Kind {
+    Directory,
+    File,
+    // a symbolic link, with the target it points at
+    Symlink(PathBuf),
+    // another name for a file already in the archive under this name
+    Hardlink(PathBuf),
+    // a FIFO, device node or socket
+    Special(SpecialKind),
+}
+
+pub struct Links {
+    policy: LinkPolicy,
+    // archive names of multiply-linked files by device and inode, for the
+    // formats that can point a later name back at the first one
+    seen: Option<HashMap<(u64, u64), PathBuf>>,
+}
+
+impl Links {
+    pub fn new(policy: LinkPolicy, dedup_hardlinks: bool) -> Links {
+        Links {
+            policy,
+            seen: dedup_hardlinks.then(HashMap::new),
+        }
+    }
+
+    // What the entry goes in as, with the metadata it is stored with; None
+    // when the link policy leaves it out. The input root itself is always
+    // taken for what it points at. Files come back as File, for hardlink()
+    // to tell apart once they are known to go in.
+    pub fn kind(&self, entry: &DirEntry) -> io::Result<Option<(Kind, fs::Metadata)>> {
+        if entry.depth() > 0 && entry.path_is_symlink() {
+            match self.policy {
+                LinkPolicy::Store => {
+                    let target = fs::read_link(entry.path())?;
+                    let metadata = fs::symlink_metadata(entry.path())?;
+                    return Ok(Some((Kind::Symlink(target), metadata)));
+                }
+                LinkPolicy::Skip => return Ok(None),
+                LinkPolicy::Follow => {}
+            }
+        }
+        let metadata = fs::metadata(entry.path())?;
+        let kind = if metadata.is_dir() {
+            Kind::Directory
+        } else if let Some(kind) = special_kind(&metadata) {
+            Kind::Special(kind)
+        } else {
+            Kind::File
+        };
+        Ok(Some((kind, metadata)))
+    }
+
+    // A Hardlink to the first name of a file seen under another one before,
+    // File otherwise. Only called once the file is known to go in, so a
+    // later name never points at one that was left out.
+    pub fn hardlink(&mut self, metadata: &fs::Metadata, name: &Path) -> Kind {
+        let (seen, id) = match (self.seen.as_mut(), file_id(metadata)) {
+            (Some(seen), Some(id)) => (seen, id),
+            _ => return Kind::File,
+        };
+        if let Some(first) = seen.get(&id) {
+            return Kind::Hardlink(first.clone());
+        }
+        seen.insert(id, name.to_path_buf());
+        Kind::File
+    }
+}
+
+// Whether a special file goes into the archive. The ones left out are
+// reported in `warnings`; sockets can't be stored in any format, and zip and
+// 7z (`storable` false) can't store special files at all.
+pub fn store_special(
+    kind: SpecialKind,
+    name: &Path,
+    policy: SpecialFilePolicy,
+    storable: bool,
+    warnings: &mut Vec<String>,
+) -> io::Result<bool> {
+    let why = match policy {
+        SpecialFilePolicy::Fail => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is a {}", name.display(), kind),
+            ))
+        }
+        SpecialFilePolicy::Skip => None,
+        SpecialFilePolicy::Store if kind == SpecialKind::Socket => {
+            Some("sockets can't be archived")
+        }
+        SpecialFilePolicy::Store if !storable => Some("the format has no entry for it"),
+        SpecialFilePolicy::Store => return Ok(true),
+    };
+    warnings.push(match why {
+        Some(why) => format!("skipped {} {}: {}", kind, name.display(), why),
+        None => format!("skipped {} {}", kind, name.display()),
+    });
+    Ok(false)
+}
+
+#[cfg(unix)]
+fn special_kind(metadata: &fs::Metadata) -> Option<SpecialKind> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = metadata.file_type();
+    if file_type.is_fifo() {
+        Some(SpecialKind::Fifo)
+    } else if file_type.is_char_device() {
+        Some(SpecialKind::CharDevice)
+    } else if file_type.is_block_device() {
+        Some(SpecialKind::BlockDevice)
+    } else if file_type.is_socket() {
+        Some(SpecialKind::Socket)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn special_kind(_metadata: &fs::Metadata) -> Option<SpecialKind> {
+    None
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -1,6 +1,10 @@
 use std::fs::File;
 use std::io::{Read, Write};
 
+use crate::modules::error::ArchiveError;
+use crate::modules::limits::Budget;
+use crate::modules::options::DecompressOptions;
+
 pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     let dst_file = File::create(dst_path).expect("lz4 create failed");
     let mut enc = lz4_flex::frame::FrameEncoder::new(dst_file);
@@ -12,11 +16,21 @@ pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     enc.finish().expect("lz4 finish failed");
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<(), ArchiveError> {
     let src_file = File::open(src_path).expect("lz4 open failed");
     let mut dec = lz4_flex::frame::FrameDecoder::new(src_file);
-    let mut content = Vec::new();
-    dec.read_to_end(&mut content).expect("lz4 read failed");
     let mut dst_file = File::create(dst_path).expect("lz4 create dst failed");
-    dst_file.write_all(&content).expect("lz4 write dst failed");
+    let mut budget = Budget::new(&options.limits, src_path);
+    budget.add_entry()?;
+    budget.copy("lz4 unpack", &mut dec, &mut dst_file)?;
+    Ok(())
+}
+
+// The decoded stream, for reading the output back after compressing.
+pub fn decoder(file: File) -> Box<dyn Read> {
+    Box::new(lz4_flex::frame::FrameDecoder::new(file))
 }
@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use tar::{EntryType, PaxExtensions};
+
+use crate::modules::staging::Staged;
+
+use super::sparse;
+use super::tar::{path_bytes, path_from_bytes, pax_sparse};
+use super::update::key;
+
+const BLOCK: u64 = 512;
+
+// One tar member as it sits in the archive: every header block in front of
+// the data, extension headers and their records included, so it can be
+// copied to another archive without being rebuilt.
+pub struct Member {
+    // from a PAX path record or a GNU long name when there is one, and from
+    // the GNU.sparse.name record for a PAX 1.0 sparse file, whose header
+    // only has a placeholder
+    pub name: PathBuf,
+    pub entry_type: EntryType,
+    // what a link points at, from a PAX linkpath record or a GNU long link
+    // name when there is one
+    pub link: Option<PathBuf>,
+    // PAX records other than the path and link path
+    pub records: Vec<(String, Vec<u8>)>,
+    // of the data, without the padding
+    size: u64,
+    blocks: Vec<u8>,
+    // where the member's own header starts in `blocks`
+    header_at: usize,
+}
+
+impl Member {
+    // The member's own header, after its extension headers.
+    pub fn header(&self) -> &tar::Header {
+        tar::Header::from_byte_slice(&self.blocks[self.header_at..self.header_at + BLOCK as usize])
+    }
+
+    // What follows the header before the data: the rest of a GNU sparse map.
+    pub fn sparse_map(&self) -> &[u8] {
+        &self.blocks[self.header_at + BLOCK as usize..]
+    }
+}
+
+// Reads a tar stream member by member. Only headers are parsed; the data of
+// a member is skipped unless it is copied before asking for the next one.
+pub struct Members<R> {
+    reader: R,
+    // data of the last member not read yet
+    remaining: u64,
+    // bytes consumed so far
+    offset: u64,
+}
+
+impl<R: Read> Members<R> {
+    pub fn new(reader: R) -> Self {
+        Members {
+            reader,
+            remaining: 0,
+            offset: 0,
+        }
+    }
+
+    // Where the end-of-archive blocks start once next() returned None.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn next(&mut self) -> io::Result<Option<Member>> {
+        let remaining = self.remaining;
+        self.copy_data(&mut io::sink(), remaining)?;
+        let mut blocks = Vec::new();
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut pax_path = None;
+        let mut pax_link = None;
+        let mut pax_size = None;
+        let mut records = Vec::new();
+        loop {
+            let start = blocks.len();
+            if !self.read_block(&mut blocks)? {
+                if start != 0 {
+                    return Err(corrupt("extension header without a member after it"));
+                }
+                return Ok(None);
+            }
+            let header = tar::Header::from_byte_slice(&blocks[start..]);
+            let size = header.entry_size()?;
+            match header.entry_type() {
+                EntryType::GNULongName | EntryType::GNULongLink | EntryType::XHeader => {
+                    let data_start = blocks.len();
+                    let entry_type = header.entry_type();
+                    self.read_exact_into(&mut blocks, padded(size))?;
+                    let data = &blocks[data_start..data_start + size as usize];
+                    if entry_type == EntryType::XHeader {
+                        for extension in PaxExtensions::new(data) {
+                            let extension = extension?;
+                            match extension.key() {
+                                Ok("path") => {
+                                    pax_path = Some(path_from_bytes(extension.value_bytes()))
+                                }
+                                Ok("linkpath") => {
+                                    pax_link = Some(path_from_bytes(extension.value_bytes()))
+                                }
+                                Ok(key) => {
+                                    if key == "size" {
+                                        pax_size =
+                                            extension.value().ok().and_then(|v| v.parse().ok());
+                                    }
+                                    records
+                                        .push((key.to_string(), extension.value_bytes().to_vec()));
+                                }
+                                Err(_) => {}
+                            }
+                        }
+                    } else {
+                        let end = data
+                            .iter()
+                            .position(|&byte| byte == 0)
+                            .unwrap_or(data.len());
+                        let name = Some(path_from_bytes(&data[..end]));
+                        if entry_type == EntryType::GNULongName {
+                            long_name = name;
+                        } else {
+                            long_link = name;
+                        }
+                    }
+                }
+                entry_type => {
+                    let name = match (pax_sparse(&records), pax_path.or(long_name)) {
+                        (Some((name, _)), _) | (None, Some(name)) => name,
+                        (None, None) => header.path()?.into_owned(),
+                    };
+                    let link = match pax_link.or(long_link) {
+                        Some(link) => Some(link),
+                        None => header.link_name()?.map(|link| link.into_owned()),
+                    };
+                    let extended = entry_type == EntryType::GNUSparse
+                        && header.as_gnu().is_some_and(|gnu| gnu.is_extended());
+                    if extended {
+                        // more sparse map blocks, each flagging the next
+                        loop {
+                            let start = blocks.len();
+                            if !self.read_block(&mut blocks)? {
+                                return Err(corrupt("sparse map cut short"));
+                            }
+                            if blocks[start + 504] == 0 {
+                                break;
+                            }
+                        }
+                    }
+                    let size = pax_size.unwrap_or(size);
+                    self.remaining = padded(size);
+                    return Ok(Some(Member {
+                        name,
+                        entry_type,
+                        link,
+                        records,
+                        size,
+                        blocks,
+                        header_at: start,
+                    }));
+                }
+            }
+        }
+    }
+
+    // Writes the member last returned by next() to `writer` unchanged.
+    pub fn copy<W: Write>(&mut self, member: &Member, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&member.blocks)?;
+        let remaining = self.remaining;
+        self.copy_data(writer, remaining)
+    }
+
+    // Writes just the data of the member last returned by next() to `writer`.
+    pub fn data<W: Write>(&mut self, member: &Member, writer: &mut W) -> io::Result<()> {
+        self.copy_data(writer, member.size)?;
+        let remaining = self.remaining;
+        self.copy_data(&mut io::sink(), remaining)
+    }
+
+    fn copy_data<W: Write>(&mut self, writer: &mut W, len: u64) -> io::Result<()> {
+        let copied = io::copy(&mut (&mut self.reader).take(len), writer)?;
+        self.offset += copied;
+        self.remaining -= copied;
+        if copied != len {
+            return Err(corrupt("member data cut short"));
+        }
+        Ok(())
+    }
+
+    // One header block onto `blocks`; false at the end of the archive, which
+    // is either two zero blocks or, for some writers, just the end of the
+    // stream.
+    fn read_block(&mut self, blocks: &mut Vec<u8>) -> io::Result<bool> {
+        let mut block = [0u8; BLOCK as usize];
+        let mut filled = 0;
+        while filled < block.len() {
+            match self.reader.read(&mut block[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(corrupt("header block cut short")),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if block.iter().all(|&byte| byte == 0) {
+            return Ok(false);
+        }
+        let header = tar::Header::from_byte_slice(&block);
+        let sum: u32 = block[..148]
+            .iter()
+            .chain(&block[156..])
+            .map(|&byte| byte as u32)
+            .sum::<u32>()
+            + 8 * 32;
+        if header.cksum()? != sum {
+            return Err(corrupt("header checksum mismatch"));
+        }
+        self.offset += BLOCK;
+        blocks.extend_from_slice(&block);
+        Ok(true)
+    }
+
+    fn read_exact_into(&mut self, blocks: &mut Vec<u8>, len: u64) -> io::Result<()> {
+        let start = blocks.len();
+        (&mut self.reader).take(len).read_to_end(blocks)?;
+        if (blocks.len() - start) as u64 != len {
+            return Err(corrupt("extension header cut short"));
+        }
+        self.offset += len;
+        Ok(())
+    }
+}
+
+// Names of the members that hardlinks an update keeps point at, followed
+// through the links it drops on the way. Only headers are read, so telling
+// which dropped members have to be spooled costs a pass over the stream
+// rather than a copy of each of them.
+pub fn kept_link_targets<R: Read>(
+    old: R,
+    mut keeps: impl FnMut(&Path) -> bool,
+) -> io::Result<HashSet<PathBuf>> {
+    let mut members = Members::new(old);
+    // dropped links by name, and the member they end at
+    let mut dropped_links: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut targets = HashSet::new();
+    while let Some(member) = members.next()? {
+        let name = key(&member.name);
+        let target = match (member.entry_type, &member.link) {
+            (EntryType::Link, Some(target)) => {
+                let target = key(target);
+                Some(dropped_links.get(&target).cloned().unwrap_or(target))
+            }
+            _ => None,
+        };
+        dropped_links.remove(&name);
+        match target {
+            Some(target) if keeps(&member.name) => {
+                targets.insert(target);
+            }
+            Some(target) => {
+                dropped_links.insert(name, target);
+            }
+            None => {}
+        }
+    }
+    Ok(targets)
+}
+
+// Members an update drops, for the hardlinks it keeps that point at them.
+// Links only point back, so each dropped member a kept link needs is spooled
+// next to the archive as it streams past. The first kept link to it is
+// stored as that member under the link's own name and the later ones point
+// there instead.
+pub struct Orphans {
+    archive_path: PathBuf,
+    // from kept_link_targets()
+    needed: HashSet<PathBuf>,
+    dropped: HashMap<PathBuf, Orphan>,
+}
+
+enum Orphan {
+    // not claimed by a link yet
+    Spooled(Member, Staged),
+    // stored under this name, or a dropped link to it
+    StoredAs(PathBuf),
+    // a dropped link to the spooled member of this name
+    SameAs(PathBuf),
+}
+
+impl Orphans {
+    pub fn new(archive_path: &Path, needed: HashSet<PathBuf>) -> Self {
+        Orphans {
+            archive_path: archive_path.to_path_buf(),
+            needed,
+            dropped: HashMap::new(),
+        }
+    }
+
+    // Takes in the member last returned by next() that the update leaves
+    // out. Directories can't be linked to, and members no kept link needs
+    // are forgotten.
+    pub fn leave_out<R: Read>(
+        &mut self,
+        members: &mut Members<R>,
+        member: Member,
+    ) -> io::Result<()> {
+        let name = key(&member.name);
+        let orphan = match (member.entry_type, &member.link) {
+            (EntryType::Directory, _) => return Ok(()),
+            (EntryType::Link, Some(target)) => {
+                let target_key = key(target);
+                match self.dropped.get(&target_key) {
+                    Some(Orphan::Spooled(..)) => Orphan::SameAs(target_key),
+                    Some(Orphan::SameAs(first)) => Orphan::SameAs(first.clone()),
+                    Some(Orphan::StoredAs(stored)) => Orphan::StoredAs(stored.clone()),
+                    None => Orphan::StoredAs(target.clone()),
+                }
+            }
+            _ if !self.needed.contains(&name) => {
+                self.dropped.remove(&name);
+                return Ok(());
+            }
+            _ => {
+                let spool = Staged::next_to(&self.archive_path);
+                members.data(&member, &mut File::create(spool.path())?)?;
+                Orphan::Spooled(member, spool)
+            }
+        };
+        self.dropped.insert(name, orphan);
+        Ok(())
+    }
+
+    // Copies the member last returned by next() that the update keeps, as
+    // it is unless it is a hardlink to a dropped member.
+    pub fn keep<R: Read, W: Write>(
+        &mut self,
+        members: &mut Members<R>,
+        member: Member,
+        builder: &mut tar::Builder<W>,
+    ) -> io::Result<()> {
+        let mut target = match (member.entry_type, &member.link) {
+            (EntryType::Link, Some(target)) => key(target),
+            _ => return members.copy(&member, builder.get_mut()),
+        };
+        if let Some(Orphan::SameAs(first)) = self.dropped.get(&target) {
+            target = first.clone();
+        }
+        match self.dropped.remove(&target) {
+            Some(Orphan::Spooled(orphan, spool)) => {
+                let link = match orphan.entry_type {
+                    EntryType::Symlink => orphan.link.as_deref(),
+                    _ => None,
+                };
+                append_as(
+                    builder,
+                    &orphan,
+                    &member.name,
+                    link,
+                    File::open(spool.path())?,
+                )?;
+                self.dropped.insert(target, Orphan::StoredAs(member.name));
+            }
+            Some(Orphan::StoredAs(stored)) => {
+                append_as(builder, &member, &member.name, Some(&stored), io::empty())?;
+                self.dropped.insert(target, Orphan::StoredAs(stored));
+            }
+            Some(orphan) => {
+                self.dropped.insert(target, orphan);
+                members.copy(&member, builder.get_mut())?;
+            }
+            None => members.copy(&member, builder.get_mut())?,
+        }
+        Ok(())
+    }
+}
+
+// `member` rebuilt under another name, with its PAX records, pointing at
+// `link` for links and holding `data` otherwise. A PAX sparse file stays one,
+// its GNU.sparse.name record and placeholder following the new name.
+fn append_as<W: Write, D: Read>(
+    builder: &mut tar::Builder<W>,
+    member: &Member,
+    name: &Path,
+    link: Option<&Path>,
+    data: D,
+) -> io::Result<()> {
+    let name_bytes = path_bytes(name);
+    let mut stored = name.to_path_buf();
+    let mut records = Vec::new();
+    for (key, value) in &member.records {
+        if key == sparse::NAME {
+            stored = sparse::placeholder_name(name);
+            records.push((key.as_str(), name_bytes.as_slice()));
+        } else {
+            records.push((key.as_str(), value.as_slice()));
+        }
+    }
+    builder.append_pax_extensions(records)?;
+    let mut header = member.header().clone();
+    match link {
+        Some(link) => builder.append_link(&mut header, name, link),
+        None => builder.append_data(
+            &mut header,
+            stored,
+            io::Cursor::new(member.sparse_map()).chain(data),
+        ),
+    }
+}
+
+fn padded(size: u64) -> u64 {
+    size.div_ceil(BLOCK) * BLOCK
+}
+
+fn corrupt(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("tar archive: {}", why))
+}
@@ -1,13 +1,19 @@
 pub mod bz2;
 pub mod gz;
+pub(crate) mod links;
 pub mod lz4;
+mod members;
+mod reproducible;
 pub mod sevenz;
+pub(crate) mod source;
+mod sparse;
 pub mod tar;
 pub mod tar_bz2;
 pub mod tar_gz;
 pub mod tar_lz4;
 pub mod tar_xz;
 pub mod tar_zst;
+pub mod update;
 pub mod xz;
 pub mod zip;
 pub mod zst;
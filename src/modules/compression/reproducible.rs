@@ -0,0 +1,53 @@
+use std::fs::Metadata;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filetime::FileTime;
+use walkdir::WalkDir;
+
+use crate::modules::options::ReproducibleOptions;
+
+// Directory order is whatever the filesystem hands out, so reproducible
+// archives list entries by name instead.
+pub fn walk(src_path: &Path, options: &ReproducibleOptions) -> WalkDir {
+    let walkdir = WalkDir::new(src_path);
+    if options.enabled {
+        walkdir.sort_by_file_name()
+    } else {
+        walkdir
+    }
+}
+
+// Whole seconds, no later than SOURCE_DATE_EPOCH when one is given.
+pub fn mtime(metadata: &Metadata, options: &ReproducibleOptions) -> i64 {
+    let mtime = FileTime::from_last_modification_time(metadata).unix_seconds();
+    match options.source_date_epoch {
+        Some(epoch) => mtime.min(epoch),
+        None => mtime,
+    }
+}
+
+pub fn system_time(seconds: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64)
+}
+
+// The umask-free mode tar's deterministic headers use: executable bit of the
+// owner carried over, everything else 0644.
+#[cfg(unix)]
+pub fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.is_dir() || metadata.permissions().mode() & 0o100 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(not(unix))]
+pub fn mode(metadata: &Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else {
+        0o644
+    }
+}
@@ -1,49 +1,328 @@
-use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
+use std::io;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
-use sevenz_rust::{SevenZArchiveEntry, SevenZWriter};
-use walkdir::WalkDir;
+use filetime::FileTime;
+use sevenz_rust::lzma::LZMA2Options;
+use sevenz_rust::{Password, SevenZArchiveEntry, SevenZReader, SevenZWriter, SourceReader};
 
-fn archive_path(src_root: &Path, entry_path: &Path) -> PathBuf {
-    let base: Option<OsString> = src_root.file_name().map(|s| s.to_os_string());
-    if entry_path == src_root {
-        if let Some(base) = base {
-            return PathBuf::from(base);
+use crate::modules::error::{ArchiveError, SpecialKind};
+use crate::modules::extract::{EntryMeta, Extractor};
+use crate::modules::options::{
+    CompressOptions, DecompressOptions, ReproducibleOptions, SevenZOptions,
+};
+
+use super::links::{self, Kind};
+use super::reproducible;
+use super::source::{SourceEntry, SourceTree};
+
+// The LZMA2 property byte can only describe 2^n and 3 * 2^(n-1) dictionary sizes,
+// so round up to the next one of those or the decoder gets a too small window.
+fn lzma2_dict_size(requested: u32) -> u32 {
+    let mut size: u32 = 4096;
+    while size < requested {
+        let half_step = size + size / 2;
+        if half_step >= requested {
+            return half_step;
+        }
+        size *= 2;
+    }
+    size
+}
+
+fn lzma2_options(options: &SevenZOptions) -> LZMA2Options {
+    let mut lzma2 = LZMA2Options::with_preset(options.preset);
+    if let Some(dict_size) = options.dict_size {
+        lzma2.dict_size = lzma2_dict_size(dict_size);
+    }
+    lzma2
+}
+
+// Same ordering as 7-Zip's `-mqs`: extension first so similar files share a block.
+fn solid_sort_key(name: &str) -> (String, String) {
+    let extension = Path::new(name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    (extension, name.to_string())
+}
+
+struct SolidFile {
+    path: PathBuf,
+    name: String,
+    metadata: fs::Metadata,
+}
+
+fn solid_blocks(files: Vec<SolidFile>, block_size: Option<u64>) -> Vec<Vec<SolidFile>> {
+    let mut blocks = Vec::new();
+    let mut block = Vec::new();
+    let mut block_bytes = 0u64;
+    for file in files {
+        if let Some(limit) = block_size {
+            if !block.is_empty() && block_bytes + file.metadata.len() > limit {
+                blocks.push(std::mem::take(&mut block));
+                block_bytes = 0;
+            }
+        }
+        block_bytes += file.metadata.len();
+        block.push(file);
+    }
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+// Opens the file on first read and closes it at EOF, so a large solid block
+// doesn't hold a descriptor for every member at once.
+struct LazyFile {
+    path: PathBuf,
+    file: Option<File>,
+    done: bool,
+}
+
+impl Read for LazyFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        if self.file.is_none() {
+            self.file = Some(File::open(&self.path)?);
+        }
+        let n = self.file.as_mut().unwrap().read(buf)?;
+        if n == 0 {
+            self.file = None;
+            self.done = true;
         }
+        Ok(n)
     }
-    match entry_path.strip_prefix(src_root) {
-        Ok(rel) => match base {
-            Some(base) => PathBuf::from(base).join(rel),
-            None => rel.to_path_buf(),
-        },
-        Err(_) => entry_path.to_path_buf(),
+}
+
+// SevenZArchiveEntry::from_path also records creation and access times,
+// which a reproducible archive leaves out.
+fn archive_entry(
+    path: &Path,
+    name: String,
+    metadata: &fs::Metadata,
+    options: &ReproducibleOptions,
+) -> SevenZArchiveEntry {
+    let mut entry = SevenZArchiveEntry::from_path(path, name);
+    if options.enabled {
+        entry.has_creation_date = false;
+        entry.has_access_date = false;
+        entry.last_modified_date =
+            reproducible::system_time(reproducible::mtime(metadata, options))
+                .try_into()
+                .expect("7z entry mtime failed");
+        entry.has_last_modified_date = true;
     }
+    entry
+}
+
+// The encoding p7zip reads back: S_IFLNK in the upper attribute bits and the
+// link target as the entry's data.
+fn symlink_entry(
+    name: String,
+    metadata: &fs::Metadata,
+    target: &Path,
+    options: &ReproducibleOptions,
+) -> (SevenZArchiveEntry, Vec<u8>) {
+    let mut entry = SevenZArchiveEntry::new();
+    entry.name = name;
+    entry.has_windows_attributes = true;
+    entry.windows_attributes = FILE_ATTRIBUTE_UNIX_EXTENSION | (0o120777 << 16);
+    let mtime = if options.enabled {
+        reproducible::system_time(reproducible::mtime(metadata, options))
+    } else {
+        metadata.modified().expect("7z symlink mtime failed")
+    };
+    entry.last_modified_date = mtime.try_into().expect("7z entry mtime failed");
+    entry.has_last_modified_date = true;
+    (entry, target.to_string_lossy().into_owned().into_bytes())
 }
 
-pub fn compress(src_path: &Path, dst_path: &Path) {
+pub fn compress(
+    src_path: &Path,
+    dst_path: &Path,
+    compress_options: &CompressOptions,
+) -> Vec<String> {
+    let options = &compress_options.sevenz;
+    let reproducible = &compress_options.reproducible;
     let dst_file = File::create(dst_path).expect("7z create failed");
     let mut writer = SevenZWriter::new(dst_file).expect("7z writer init failed");
+    writer.set_content_methods(vec![lzma2_options(options).into()]);
 
-    for entry in WalkDir::new(src_path).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        let name = archive_path(src_path, path).to_string_lossy().to_string();
-
-        if path.is_dir() {
-            writer
-                .push_archive_entry::<File>(SevenZArchiveEntry::from_path(path, name), None)
-                .expect("7z add dir failed");
-        } else {
-            let file = File::open(path).expect("7z open source file failed");
-            writer
-                .push_archive_entry(SevenZArchiveEntry::from_path(path, name), Some(file))
-                .expect("7z add file failed");
+    let mut solid_files = Vec::new();
+    // 7z has no hardlink entry, so every name gets its own copy.
+    let mut tree = SourceTree::new(src_path, compress_options, false)
+        .expect("7z read exclude patterns failed");
+    let mut warnings = Vec::new();
+    for entry in tree.by_ref() {
+        let SourceEntry {
+            path,
+            name,
+            kind,
+            metadata,
+        } = entry.unwrap_or_else(|err| panic!("7z walk failed: {}", err));
+        let name = name.to_string_lossy().to_string();
+        match kind {
+            Kind::Symlink(target) => {
+                let (entry, data) = symlink_entry(name, &metadata, &target, reproducible);
+                writer
+                    .push_archive_entry(entry, Some(data.as_slice()))
+                    .expect("7z add symlink failed");
+            }
+            Kind::Directory => {
+                let entry = archive_entry(&path, name, &metadata, reproducible);
+                writer
+                    .push_archive_entry::<File>(entry, None)
+                    .expect("7z add dir failed");
+            }
+            Kind::File | Kind::Hardlink(_) if options.solid => {
+                if metadata.len() == 0 {
+                    let entry = archive_entry(&path, name, &metadata, reproducible);
+                    writer
+                        .push_archive_entry::<File>(entry, None)
+                        .expect("7z add file failed");
+                } else {
+                    solid_files.push(SolidFile {
+                        path,
+                        name,
+                        metadata,
+                    });
+                }
+            }
+            Kind::File | Kind::Hardlink(_) => {
+                let file = File::open(&path).expect("7z open source file failed");
+                writer
+                    .push_archive_entry(
+                        archive_entry(&path, name, &metadata, reproducible),
+                        Some(file),
+                    )
+                    .expect("7z add file failed");
+            }
+            Kind::Special(kind) => {
+                links::store_special(
+                    kind,
+                    Path::new(&name),
+                    compress_options.special_files,
+                    false,
+                    &mut warnings,
+                )
+                .unwrap_or_else(|err| panic!("7z add special file failed: {}", err));
+            }
         }
     }
+    warnings.extend(tree.finish());
+
+    if options.sort_by_extension {
+        solid_files.sort_by_cached_key(|file| solid_sort_key(&file.name));
+    }
+    for block in solid_blocks(solid_files, options.solid_block_size) {
+        let mut entries = Vec::with_capacity(block.len());
+        let mut readers = Vec::with_capacity(block.len());
+        for file in block {
+            entries.push(archive_entry(
+                &file.path,
+                file.name,
+                &file.metadata,
+                reproducible,
+            ));
+            readers.push(SourceReader::new(LazyFile {
+                path: file.path,
+                file: None,
+                done: false,
+            }));
+        }
+        writer
+            .push_archive_entries(entries, readers.into())
+            .expect("7z add solid block failed");
+    }
 
     writer.finish().expect("7z finish failed");
+    warnings
+}
+
+// p7zip keeps the unix mode in the upper 16 attribute bits behind this flag.
+const FILE_ATTRIBUTE_UNIX_EXTENSION: u32 = 0x8000;
+
+fn is_symlink(entry: &SevenZArchiveEntry) -> bool {
+    let attributes = entry.windows_attributes();
+    attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0 && (attributes >> 16) & 0o170000 == 0o120000
 }
 
-pub fn decompress(src_path: &Path, dst_path: &Path) {
-    sevenz_rust::decompress_file(src_path, dst_path).expect("7z decompress failed");
+fn special_kind(entry: &SevenZArchiveEntry) -> Option<SpecialKind> {
+    let attributes = entry.windows_attributes();
+    if attributes & FILE_ATTRIBUTE_UNIX_EXTENSION == 0 {
+        return None;
+    }
+    SpecialKind::from_mode(attributes >> 16)
+}
+
+fn entry_meta(entry: &SevenZArchiveEntry) -> EntryMeta {
+    let attributes = entry.windows_attributes();
+    EntryMeta {
+        mode: (attributes & FILE_ATTRIBUTE_UNIX_EXTENSION != 0).then_some(attributes >> 16),
+        mtime: entry
+            .has_last_modified_date
+            .then(|| FileTime::from_system_time(entry.last_modified_date().into())),
+        ..Default::default()
+    }
+}
+
+fn extract_entry(
+    extractor: &mut Extractor,
+    entry: &SevenZArchiveEntry,
+    reader: &mut dyn Read,
+) -> Result<(), ArchiveError> {
+    let name = PathBuf::from(entry.name());
+    if is_symlink(entry) {
+        let mut target = String::new();
+        reader
+            .read_to_string(&mut target)
+            .expect("7z read symlink target failed");
+        extractor.symlink(&name, Path::new(&target))
+    } else if let Some(kind) = special_kind(entry) {
+        // solid blocks still have to be read past whatever data it has
+        io::copy(reader, &mut io::sink()).expect("7z skip special file failed");
+        extractor.special(&name, kind, None, entry_meta(entry))
+    } else if entry.is_directory() {
+        extractor.directory(&name, entry_meta(entry))
+    } else {
+        extractor.file(&name, reader, entry_meta(entry))
+    }
+}
+
+pub fn decompress(
+    src_path: &Path,
+    dst_path: &Path,
+    options: &DecompressOptions,
+) -> Result<Vec<String>, ArchiveError> {
+    let mut reader = SevenZReader::open(src_path, Password::empty()).expect("7z open failed");
+    let mut extractor =
+        Extractor::new(src_path, dst_path, options).expect("7z open dst dir failed");
+
+    let mut failure = None;
+    reader
+        .for_each_entries(|entry, entry_reader| {
+            // false only ends the current block, so the entries of later
+            // blocks still come by and are passed over
+            if failure.is_some() {
+                return Ok(false);
+            }
+            match extract_entry(&mut extractor, entry, entry_reader) {
+                Ok(()) => Ok(true),
+                Err(err) => {
+                    failure = Some(err);
+                    Ok(false)
+                }
+            }
+        })
+        .expect("7z decompress failed");
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(extractor.finish()),
+    }
 }
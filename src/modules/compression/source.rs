@@ -0,0 +1,177 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::contents::enums::{LinkPolicy, UnreadablePolicy};
+use crate::modules::filter::Filter;
+use crate::modules::options::CompressOptions;
+use crate::modules::transform::{self, Transform};
+
+use super::links::{Kind, Links};
+use super::reproducible;
+
+// One entry below a compression input, as every container sees it.
+pub struct SourceEntry {
+    // where it is on disk
+    pub path: PathBuf,
+    // the name it is stored under, transform rules applied
+    pub name: PathBuf,
+    pub kind: Kind,
+    // of the link itself for symlinks, of what it points at otherwise
+    pub metadata: fs::Metadata,
+}
+
+// Walks a compression input for tar, zip and 7z alike: the same entries in
+// the same order under the same names. Filters, the link policy and the
+// transform rules are applied on the way. Entries that can't be read are
+// left out and reported by finish(), or end the walk with an error under the
+// strict policy.
+pub struct SourceTree<'a> {
+    root: PathBuf,
+    walker: walkdir::IntoIter,
+    filter: Filter<'a>,
+    links: Links,
+    transforms: &'a [Transform],
+    unreadable: UnreadablePolicy,
+    warnings: Vec<String>,
+}
+
+impl<'a> SourceTree<'a> {
+    // Formats with a hardlink entry pass dedup_hardlinks, so a file with
+    // several names below the input is stored once and pointed at after.
+    pub fn new(
+        src_path: &Path,
+        options: &'a CompressOptions,
+        dedup_hardlinks: bool,
+    ) -> io::Result<SourceTree<'a>> {
+        Ok(SourceTree {
+            root: src_path.to_path_buf(),
+            walker: walk(src_path, options).into_iter(),
+            filter: Filter::new(src_path, &options.filter)?,
+            links: Links::new(options.links, dedup_hardlinks),
+            transforms: &options.transforms,
+            unreadable: options.unreadable,
+            warnings: Vec::new(),
+        })
+    }
+
+    // What was left out because it couldn't be read.
+    pub fn finish(self) -> Vec<String> {
+        self.warnings
+    }
+
+    // Err under the strict policy, a warning otherwise.
+    fn unreadable(&mut self, path: Option<&Path>, err: impl fmt::Display) -> io::Result<()> {
+        let path = match path {
+            Some(path) => path.display().to_string(),
+            None => "an entry".to_string(),
+        };
+        match self.unreadable {
+            UnreadablePolicy::Skip => {
+                self.warnings.push(format!("skipped {}: {}", path, err));
+                Ok(())
+            }
+            UnreadablePolicy::Fail => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("can't read {}: {}", path, err),
+            )),
+        }
+    }
+}
+
+// Errors are the ones that make the whole archive wrong, such as a name the
+// transform rules move out of the archive or, under the strict policy, a file
+// that can't be read.
+impl Iterator for SourceTree<'_> {
+    type Item = io::Result<SourceEntry>;
+
+    fn next(&mut self) -> Option<io::Result<SourceEntry>> {
+        loop {
+            let entry = match self.walker.next()? {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let path = err.path().map(Path::to_path_buf);
+                    let reason = match err.io_error() {
+                        Some(io_err) => io_err.to_string(),
+                        None => err.to_string(),
+                    };
+                    match self.unreadable(path.as_deref(), reason) {
+                        Ok(()) => continue,
+                        Err(err) => return Some(Err(err)),
+                    }
+                }
+            };
+            // A directory that is left out is not descended into either.
+            if !self.filter.allows(&entry) {
+                if entry.file_type().is_dir() {
+                    self.walker.skip_current_dir();
+                }
+                continue;
+            }
+            let name = match transform::pack_name(
+                self.transforms,
+                &archive_path(&self.root, entry.path()),
+            ) {
+                Ok(Some(name)) if !name.as_os_str().is_empty() => name,
+                // the input root when it has no name of its own, or a name
+                // the rules took away
+                Ok(_) => continue,
+                Err(err) => return Some(Err(io::Error::new(io::ErrorKind::InvalidInput, err))),
+            };
+            // Files are opened once up front, so one we can't read is
+            // reported here like one we can't stat, and only one that can be
+            // read becomes the name later hardlinks point at.
+            let found = self.links.kind(&entry).and_then(|found| match found {
+                Some((Kind::File, _)) => File::open(entry.path()).map(|_| found),
+                _ => Ok(found),
+            });
+            let (kind, metadata) = match found {
+                Ok(Some((Kind::File, metadata))) => {
+                    (self.links.hardlink(&metadata, &name), metadata)
+                }
+                Ok(Some(found)) => found,
+                Ok(None) => continue,
+                Err(err) => match self.unreadable(Some(entry.path()), err) {
+                    Ok(()) => continue,
+                    Err(err) => return Some(Err(err)),
+                },
+            };
+            return Some(Ok(SourceEntry {
+                path: entry.into_path(),
+                name,
+                kind,
+                metadata,
+            }));
+        }
+    }
+}
+
+// Following links lets WalkDir descend into linked directories; a link back
+// to one of its own ancestors is reported as an error and left out instead of
+// being walked forever.
+fn walk(src_path: &Path, options: &CompressOptions) -> WalkDir {
+    reproducible::walk(src_path, &options.reproducible)
+        .follow_links(options.links == LinkPolicy::Follow)
+}
+
+// The input's own name followed by the path below it.
+fn archive_path(src_root: &Path, entry_path: &Path) -> PathBuf {
+    let base: Option<OsString> = src_root.file_name().map(|s| s.to_os_string());
+    if entry_path == src_root {
+        if let Some(base) = base {
+            return PathBuf::from(base);
+        }
+    }
+    match entry_path.strip_prefix(src_root) {
+        Ok(rel) => match base {
+            Some(base) => PathBuf::from(base).join(rel),
+            None => rel.to_path_buf(),
+        },
+        Err(_) => entry_path.to_path_buf(),
+    }
+}
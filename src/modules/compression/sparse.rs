@@ -0,0 +1,238 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// Holes in a file as the filesystem reports them, and the PAX 1.0 sparse
+// layout GNU tar uses for them: GNU.sparse.* records, a decimal map of
+// (offset, length) pairs at the start of the entry data, then the data
+// regions back to back.
+pub const MAJOR: &str = "GNU.sparse.major";
+pub const MINOR: &str = "GNU.sparse.minor";
+pub const NAME: &str = "GNU.sparse.name";
+pub const REAL_SIZE: &str = "GNU.sparse.realsize";
+
+const BLOCK_SIZE: usize = 512;
+// longest decimal u64 plus its newline
+const MAX_LINE: usize = 21;
+
+// The data regions of a file as (offset, length), found with SEEK_DATA and
+// SEEK_HOLE. A file ending in a hole gets a last (size, 0) region so the
+// map alone says how long the file is. None when the file has no holes, or
+// the platform or filesystem can't tell. Leaves the file at its start.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub fn data_regions(file: &File, size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    use std::os::unix::io::AsRawFd;
+
+    // None past the last data region (ENXIO)
+    let seek = |offset: u64, whence: libc::c_int| -> io::Result<Option<u64>> {
+        match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+            -1 => match io::Error::last_os_error() {
+                err if err.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+                err => Err(err),
+            },
+            position => Ok(Some(position as u64)),
+        }
+    };
+
+    let mut regions = Vec::new();
+    let mut offset = 0;
+    while offset < size {
+        let start = match seek(offset, libc::SEEK_DATA) {
+            Ok(Some(start)) if start < size => start,
+            Ok(_) => break,
+            Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(size).min(size);
+        if end <= start {
+            break;
+        }
+        regions.push((start, end - start));
+        offset = end;
+    }
+    // the probing moved the cursor, and the caller reads from the start
+    let mut file = file;
+    file.rewind()?;
+    if size == 0 || regions == [(0, size)] {
+        return Ok(None);
+    }
+    if regions
+        .last()
+        .is_none_or(|(offset, length)| offset + length < size)
+    {
+        regions.push((size, 0));
+    }
+    Ok(Some(regions))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub fn data_regions(_file: &File, _size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    Ok(None)
+}
+
+// Where GNU tar puts the header name of a PAX 1.0 sparse entry; readers that
+// know the format take GNU.sparse.name instead.
+pub fn placeholder_name(name: &Path) -> PathBuf {
+    let placeholder = Path::new("GNUSparseFile.0").join(name.file_name().unwrap_or_default());
+    match name.parent() {
+        Some(parent) => parent.join(placeholder),
+        None => placeholder,
+    }
+}
+
+// The map at the start of the entry data, padded to a whole block.
+pub fn encode_map(regions: &[(u64, u64)]) -> Vec<u8> {
+    let mut map = format!("{}\n", regions.len());
+    for (offset, length) in regions {
+        map.push_str(&format!("{}\n{}\n", offset, length));
+    }
+    let mut map = map.into_bytes();
+    map.resize(map.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    map
+}
+
+// Reads the map back from the start of the entry data, leaving the reader at
+// the first data region.
+pub fn read_map(reader: &mut dyn Read) -> io::Result<Vec<(u64, u64)>> {
+    let mut consumed = 0;
+    let mut number = || -> io::Result<u64> {
+        let mut line = Vec::new();
+        let mut byte = [0u8];
+        loop {
+            reader.read_exact(&mut byte)?;
+            consumed += 1;
+            match byte[0] {
+                b'\n' => break,
+                digit if digit.is_ascii_digit() && line.len() < MAX_LINE => line.push(digit),
+                _ => return Err(bad_map()),
+            }
+        }
+        std::str::from_utf8(&line)
+            .ok()
+            .and_then(|line| line.parse().ok())
+            .ok_or_else(bad_map)
+    };
+    let count = number()?;
+    let mut regions = Vec::new();
+    for _ in 0..count {
+        regions.push((number()?, number()?));
+    }
+    let padding = (BLOCK_SIZE - consumed % BLOCK_SIZE) % BLOCK_SIZE;
+    io::copy(&mut reader.take(padding as u64), &mut io::sink())?;
+    Ok(regions)
+}
+
+fn bad_map() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed sparse map")
+}
+
+// The data regions of a file, back to back, as they go into the archive.
+pub struct Packed {
+    file: File,
+    regions: Vec<(u64, u64)>,
+    next: usize,
+    remaining: u64,
+}
+
+impl Packed {
+    pub fn new(file: File, regions: Vec<(u64, u64)>) -> Packed {
+        Packed {
+            file,
+            regions,
+            next: 0,
+            remaining: 0,
+        }
+    }
+}
+
+impl Read for Packed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining == 0 {
+            let (offset, length) = match self.regions.get(self.next) {
+                Some(region) => *region,
+                None => return Ok(0),
+            };
+            self.next += 1;
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.remaining = length;
+        }
+        let len = self.remaining.min(buf.len() as u64) as usize;
+        let n = self.file.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file shrank while it was being packed",
+            ));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+// The file a sparse entry stands for: zeros in the holes, the entry's data
+// in the regions, up to the real size.
+pub struct Expanded<R> {
+    data: R,
+    regions: Vec<(u64, u64)>,
+    next: usize,
+    position: u64,
+    size: u64,
+}
+
+impl<R: Read> Expanded<R> {
+    // Regions have to come in order, without overlapping, inside the file.
+    pub fn new(data: R, regions: Vec<(u64, u64)>, size: u64) -> io::Result<Expanded<R>> {
+        let mut end = 0u64;
+        for (offset, length) in &regions {
+            if *offset < end {
+                return Err(bad_map());
+            }
+            end = offset.checked_add(*length).ok_or_else(bad_map)?;
+        }
+        if end > size {
+            return Err(bad_map());
+        }
+        Ok(Expanded {
+            data,
+            regions,
+            next: 0,
+            position: 0,
+            size,
+        })
+    }
+}
+
+impl<R: Read> Read for Expanded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position >= self.size || buf.is_empty() {
+                return Ok(0);
+            }
+            let (offset, length) = self
+                .regions
+                .get(self.next)
+                .copied()
+                .unwrap_or((self.size, 0));
+            if self.position < offset {
+                let len = (offset - self.position).min(buf.len() as u64) as usize;
+                buf[..len].fill(0);
+                self.position += len as u64;
+                return Ok(len);
+            }
+            if self.position < offset + length {
+                let len = (offset + length - self.position).min(buf.len() as u64) as usize;
+                let n = self.data.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "sparse entry ends before its map does",
+                    ));
+                }
+                self.position += n as u64;
+                return Ok(n);
+            }
+            self.next += 1;
+        }
+    }
+}
@@ -1,62 +1,688 @@
-use std::ffi::OsString;
+use std::fs;
 use std::fs::File;
-use std::io::{Seek, Write};
+use std::io;
+use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 
-use walkdir::{DirEntry, WalkDir};
+use filetime::FileTime;
 
 use tar;
-use tar::Archive;
+use tar::{Archive, EntryType};
 
-use crate::utils::is_safe_path;
+use crate::contents::enums::TarFormat;
+use crate::modules::error::{ArchiveError, SpecialKind};
+use crate::modules::extract::{EntryMeta, Extractor};
+use crate::modules::options::{CompressOptions, DecompressOptions};
+use crate::modules::staging::InPlace;
+use crate::modules::xattr;
 
-fn archive_path(src_root: &Path, entry_path: &Path) -> PathBuf {
-    let base: Option<OsString> = src_root.file_name().map(|s| s.to_os_string());
-    if entry_path == src_root {
-        if let Some(base) = base {
-            return PathBuf::from(base);
+use super::links::{self, Kind};
+use super::members::{kept_link_targets, Members, Orphans};
+use super::reproducible;
+use super::source::{SourceEntry, SourceTree};
+use super::sparse;
+use super::update::Update;
+
+// Largest values the octal fields of a ustar header hold.
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+const USTAR_MAX_ID: u64 = 0o7777777;
+const USTAR_MAX_MTIME: i64 = 0o77777777777;
+
+// Shared by every tar.* backend: walks src_path and appends each entry under
+// its name inside the archive. Returns what was left out along the way.
+pub(crate) fn pack<W: Write>(
+    builder: &mut tar::Builder<W>,
+    src_path: &Path,
+    options: &CompressOptions,
+    label: &str,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut tree = SourceTree::new(src_path, options, true)
+        .unwrap_or_else(|err| panic!("{} read exclude patterns failed: {}", label, err));
+    for entry in tree.by_ref() {
+        let entry = entry.unwrap_or_else(|err| panic!("{} walk failed: {}", label, err));
+        append_source(builder, entry, options, &mut warnings)
+            .unwrap_or_else(|err| panic!("{} append failed: {}", label, err));
+    }
+    warnings.extend(tree.finish());
+    warnings
+}
+
+// Shared by the tar.* backends that rewrite: streams the old archive into the
+// new one, copying the members the update keeps as they are, then appends
+// the new entries. Kept hardlinks to members that go are rewritten so they
+// still hold what they did; `open` reads the old archive from the start, once
+// to find those and once to copy.
+pub(crate) fn rewrite<R: Read, W: Write>(
+    open: impl Fn() -> R,
+    archive_path: &Path,
+    builder: &mut tar::Builder<W>,
+    mut update: Update,
+    options: &CompressOptions,
+    label: &str,
+) -> Vec<String> {
+    let needed = kept_link_targets(open(), |name| update.keeps(name))
+        .unwrap_or_else(|err| panic!("{} read entry failed: {}", label, err));
+    let mut members = Members::new(open());
+    let mut orphans = Orphans::new(archive_path, needed);
+    let next = |members: &mut Members<R>| {
+        members
+            .next()
+            .unwrap_or_else(|err| panic!("{} read entry failed: {}", label, err))
+    };
+    while let Some(member) = next(&mut members) {
+        if update.keeps(&member.name) {
+            orphans
+                .keep(&mut members, member, builder)
+                .unwrap_or_else(|err| panic!("{} copy entry failed: {}", label, err));
+        } else {
+            orphans
+                .leave_out(&mut members, member)
+                .unwrap_or_else(|err| panic!("{} spool entry failed: {}", label, err));
+        }
+    }
+    append_update(builder, update, options, label)
+}
+
+fn append_update<W: Write>(
+    builder: &mut tar::Builder<W>,
+    mut update: Update,
+    options: &CompressOptions,
+    label: &str,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for entry in std::mem::take(&mut update.entries) {
+        append_source(builder, entry, options, &mut warnings)
+            .unwrap_or_else(|err| panic!("{} append failed: {}", label, err));
+    }
+    warnings.extend(update.finish());
+    warnings
+}
+
+fn append_source<W: Write>(
+    builder: &mut tar::Builder<W>,
+    entry: SourceEntry,
+    options: &CompressOptions,
+    warnings: &mut Vec<String>,
+) -> io::Result<()> {
+    let SourceEntry {
+        path,
+        name,
+        kind,
+        metadata,
+    } = entry;
+    match kind {
+        Kind::Symlink(target) => append_link(
+            builder,
+            &name,
+            &metadata,
+            EntryType::Symlink,
+            &target,
+            options,
+        ),
+        Kind::Hardlink(first) => {
+            append_link(builder, &name, &metadata, EntryType::Link, &first, options)
+        }
+        Kind::Special(kind) => {
+            match links::store_special(kind, &name, options.special_files, true, warnings) {
+                Ok(true) => append_entry(builder, &path, &name, &metadata, options),
+                Ok(false) => Ok(()),
+                Err(err) => Err(err),
+            }
+        }
+        Kind::Directory | Kind::File => append_entry(builder, &path, &name, &metadata, options),
+    }
+}
+
+fn append_entry<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    name: &Path,
+    metadata: &fs::Metadata,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    let xattrs = if options.xattrs {
+        xattr_records(path)?
+    } else {
+        Vec::new()
+    };
+    // The tar crate finds holes with SEEK_DATA/SEEK_HOLE itself and writes
+    // old GNU sparse entries for them. It names special files after their
+    // source path though, so those take the header route below.
+    let special = !metadata.is_file() && !metadata.is_dir();
+    if options.tar_format == TarFormat::Gnu && !options.reproducible.enabled && !special {
+        append_records(builder, &xattrs)?;
+        return builder.append_path_with_name(path, name);
+    }
+    let mut header = new_header(metadata, options);
+    if options.reproducible.enabled {
+        // no umask bits, so two checkouts of the same tree agree
+        header.set_mode(reproducible::mode(metadata));
+    }
+    if !metadata.is_file() {
+        set_device(&mut header, metadata)?;
+        return match options.tar_format {
+            TarFormat::Gnu => {
+                append_records(builder, &xattrs)?;
+                builder.append_data(&mut header, name, io::empty())
+            }
+            TarFormat::Ustar | TarFormat::Pax => {
+                standard_header(builder, &mut header, metadata, name, None, xattrs, options)?;
+                builder.append(&header, io::empty())
+            }
+        };
+    }
+    let file = File::open(path)?;
+    match options.tar_format {
+        TarFormat::Gnu => {
+            append_records(builder, &xattrs)?;
+            builder.append_data(&mut header, name, file)
+        }
+        TarFormat::Ustar => {
+            standard_header(builder, &mut header, metadata, name, None, xattrs, options)?;
+            builder.append(&header, file)
+        }
+        // Holes depend on the filesystem as much as on the content, so
+        // reproducible archives store them as zeros.
+        TarFormat::Pax if options.reproducible.enabled => {
+            standard_header(builder, &mut header, metadata, name, None, xattrs, options)?;
+            builder.append(&header, file)
+        }
+        TarFormat::Pax => append_sparse(builder, header, metadata, name, file, xattrs, options),
+    }
+}
+
+// A file with holes becomes a PAX 1.0 sparse entry: the real name and size
+// in GNU.sparse records, and only the data regions in the archive, behind a
+// map of where they go.
+fn append_sparse<W: Write>(
+    builder: &mut tar::Builder<W>,
+    mut header: tar::Header,
+    metadata: &fs::Metadata,
+    name: &Path,
+    file: File,
+    xattrs: Vec<(String, Vec<u8>)>,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    let regions = match sparse::data_regions(&file, metadata.len())? {
+        Some(regions) => regions,
+        None => {
+            standard_header(builder, &mut header, metadata, name, None, xattrs, options)?;
+            return builder.append(&header, file);
+        }
+    };
+    let map = sparse::encode_map(&regions);
+    let stored: u64 = regions.iter().map(|(_, length)| length).sum();
+    header.set_size(map.len() as u64 + stored);
+    let mut records = vec![
+        (sparse::MAJOR.to_string(), b"1".to_vec()),
+        (sparse::MINOR.to_string(), b"0".to_vec()),
+        (sparse::NAME.to_string(), path_bytes(name)),
+        (
+            sparse::REAL_SIZE.to_string(),
+            metadata.len().to_string().into_bytes(),
+        ),
+    ];
+    records.extend(xattrs);
+    let placeholder = sparse::placeholder_name(name);
+    standard_header(
+        builder,
+        &mut header,
+        metadata,
+        &placeholder,
+        None,
+        records,
+        options,
+    )?;
+    builder.append(
+        &header,
+        io::Cursor::new(map).chain(sparse::Packed::new(file, regions)),
+    )
+}
+
+// Symlink and hardlink entries carry the name they point at instead of data.
+fn append_link<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &Path,
+    metadata: &fs::Metadata,
+    entry_type: EntryType,
+    target: &Path,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    let mut header = new_header(metadata, options);
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    match options.tar_format {
+        TarFormat::Gnu => builder.append_link(&mut header, name, target),
+        TarFormat::Ustar | TarFormat::Pax => {
+            let link = Some(target);
+            standard_header(
+                builder,
+                &mut header,
+                metadata,
+                name,
+                link,
+                Vec::new(),
+                options,
+            )?;
+            builder.append(&header, io::empty())
+        }
+    }
+}
+
+// A header in the chosen format filled from metadata; reproducible archives
+// get no owners and the clamped mtime.
+fn new_header(metadata: &fs::Metadata, options: &CompressOptions) -> tar::Header {
+    let mut header = match options.tar_format {
+        TarFormat::Gnu => tar::Header::new_gnu(),
+        TarFormat::Ustar | TarFormat::Pax => tar::Header::new_ustar(),
+    };
+    let reproducible = &options.reproducible;
+    if reproducible.enabled {
+        header.set_metadata_in_mode(metadata, tar::HeaderMode::Deterministic);
+        header.set_mtime(reproducible::mtime(metadata, reproducible).max(0) as u64);
+    } else {
+        header.set_metadata(metadata);
+    }
+    header
+}
+
+// set_metadata leaves device numbers at zero; the tar crate only fills them
+// in for the special files it appends itself.
+#[cfg(unix)]
+fn set_device(header: &mut tar::Header, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    let file_type = metadata.file_type();
+    if file_type.is_char_device() || file_type.is_block_device() {
+        let rdev = metadata.rdev() as libc::dev_t;
+        header.set_device_major(libc::major(rdev) as u32)?;
+        header.set_device_minor(libc::minor(rdev) as u32)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_device(_header: &mut tar::Header, _metadata: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+// Finishes a ustar header, checking every field against what ustar can hold.
+// Strict ustar refuses the entry when something doesn't fit; PAX moves it
+// into an extended header, appended here in front of the entry.
+fn standard_header<W: Write>(
+    builder: &mut tar::Builder<W>,
+    header: &mut tar::Header,
+    metadata: &fs::Metadata,
+    name: &Path,
+    link: Option<&Path>,
+    xattrs: Vec<(String, Vec<u8>)>,
+    options: &CompressOptions,
+) -> io::Result<()> {
+    let pax = options.tar_format == TarFormat::Pax;
+    let mut records: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut overflow = |key: &str, value: Vec<u8>, why: &str| {
+        if pax {
+            records.push((key.to_string(), value));
+            Ok(())
+        } else {
+            Err(unrepresentable(name, why))
+        }
+    };
+
+    if header.set_path(name).is_err() {
+        overflow(
+            "path",
+            path_bytes(name),
+            "the name is longer than 100 bytes and can't be split into 155 + 100 at a slash",
+        )?;
+        truncate_into(&mut header.as_old_mut().name, &path_bytes(name));
+    }
+    if let Some(target) = link {
+        if header.set_link_name(target).is_err() {
+            overflow(
+                "linkpath",
+                path_bytes(target),
+                "the link target is longer than 100 bytes",
+            )?;
+            truncate_into(&mut header.as_old_mut().linkname, &path_bytes(target));
+        }
+    }
+    let size = header.size()?;
+    if size > USTAR_MAX_SIZE {
+        overflow(
+            "size",
+            size.to_string().into_bytes(),
+            "it is 8 GiB or larger",
+        )?;
+        header.set_size(0);
+    }
+    for (key, id) in [("uid", header.uid()?), ("gid", header.gid()?)] {
+        if id > USTAR_MAX_ID {
+            overflow(
+                key,
+                id.to_string().into_bytes(),
+                &format!("its {} {} is too large", key, id),
+            )?;
+            match key {
+                "uid" => header.set_uid(0),
+                _ => header.set_gid(0),
+            }
+        }
+    }
+    if !options.reproducible.enabled {
+        let mtime = FileTime::from_last_modification_time(metadata);
+        let seconds = mtime.unix_seconds();
+        if !(0..=USTAR_MAX_MTIME).contains(&seconds) {
+            overflow(
+                "mtime",
+                pax_time(mtime).into_bytes(),
+                "its mtime is before 1970 or after 2242",
+            )?;
+            header.set_mtime(seconds.clamp(0, USTAR_MAX_MTIME) as u64);
+        } else if pax && mtime.nanoseconds() != 0 {
+            records.push(("mtime".to_string(), pax_time(mtime).into_bytes()));
         }
     }
-    match entry_path.strip_prefix(src_root) {
-        Ok(rel) => match base {
-            Some(base) => PathBuf::from(base).join(rel),
-            None => rel.to_path_buf(),
-        },
-        Err(_) => entry_path.to_path_buf(),
+    if !xattrs.is_empty() {
+        if !pax {
+            return Err(unrepresentable(
+                name,
+                "extended attributes need PAX records",
+            ));
+        }
+        records.extend(xattrs);
     }
+
+    append_records(builder, &records)?;
+    header.set_cksum();
+    Ok(())
 }
 
-fn tar_dir<T>(it: &mut dyn Iterator<Item = DirEntry>, tar_file: T, src_root: &Path)
-where
-    T: Write + Seek,
-{
-    let mut tar_builder = tar::Builder::new(tar_file);
-    for entry in it {
-        let path = entry.path();
-        let name = archive_path(src_root, path);
-        tar_builder
-            .append_path_with_name(path, &name)
-            .expect("tar append failed");
+fn unrepresentable(name: &Path, why: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} cannot be stored as ustar: {}", name.display(), why),
+    )
+}
+
+// Extended attributes as SCHILY.xattr records, the way GNU tar --xattrs and
+// bsdtar write them.
+fn xattr_records(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(xattr::list(path)?
+        .into_iter()
+        .map(|(name, value)| (format!("{}{}", xattr::PAX_PREFIX, name), value))
+        .collect())
+}
+
+// One PAX extended header covering the next entry, if there is anything to
+// put in it.
+fn append_records<W: Write>(
+    builder: &mut tar::Builder<W>,
+    records: &[(String, Vec<u8>)],
+) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
     }
+    builder.append_pax_extensions(
+        records
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_slice())),
+    )
 }
 
-pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+// A PAX time: seconds since the epoch with up to nine decimals. Before the
+// epoch the whole value is negative, so -1.5 is half a second before -1.
+fn pax_time(time: FileTime) -> String {
+    let (seconds, nanos) = (time.unix_seconds(), time.nanoseconds());
+    if nanos == 0 {
+        return seconds.to_string();
+    }
+    let (sign, seconds, nanos) = if seconds < 0 {
+        ("-", -(seconds + 1), 1_000_000_000 - nanos)
+    } else {
+        ("", seconds, nanos)
+    };
+    let fraction = format!("{:09}", nanos);
+    format!("{}{}.{}", sign, seconds, fraction.trim_end_matches('0'))
+}
+
+fn parse_pax_time(value: &[u8]) -> Option<FileTime> {
+    let value = std::str::from_utf8(value).ok()?;
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value),
+    };
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let seconds: i64 = seconds.parse().ok()?;
+    if !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let digits: String = fraction
+        .chars()
+        .chain("000000000".chars())
+        .take(9)
+        .collect();
+    let nanos: u32 = digits.parse().ok()?;
+    Some(match (negative, nanos) {
+        (false, _) => FileTime::from_unix_time(seconds, nanos),
+        (true, 0) => FileTime::from_unix_time(-seconds, 0),
+        (true, _) => FileTime::from_unix_time(-seconds - 1, 1_000_000_000 - nanos),
+    })
+}
+
+// What fits of a name PAX carries in full, so readers without PAX support
+// still get something recognisable.
+fn truncate_into(field: &mut [u8], bytes: &[u8]) {
+    let len = bytes.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&bytes[..len]);
+}
+
+#[cfg(unix)]
+pub(super) fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub(super) fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+#[cfg(unix)]
+pub(super) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+pub(super) fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+pub fn compress(src_path: &Path, dst_path: &Path, options: &CompressOptions) -> Vec<String> {
     let tar_file = File::create(dst_path).expect("tar create failed");
-    let walkdir = WalkDir::new(src_path);
-    let it = walkdir.into_iter();
-    tar_dir(&mut it.filter_map(|e| e.ok()), tar_file, src_path);
+    let mut builder = tar::Builder::new(tar_file);
+    let warnings = pack(&mut builder, src_path, options, "tar");
+    builder.finish().expect("tar finish failed");
+    warnings
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
-    std::fs::create_dir_all(dst_path).expect("tar create dst dir failed");
-    let tar_file = File::open(src_path).expect("tar open failed");
-    let mut archive = Archive::new(tar_file);
-    for entry in archive.entries().expect("tar entries failed") {
-        let mut entry = entry.expect("tar entry failed");
-        let entry_path = entry.path().expect("tar entry path failed");
-        if !is_safe_path(&entry_path) {
-            panic!("tar entry path traversal detected");
+// tar -r: when the update keeps every member, its entries go over the
+// end-of-archive blocks of the archive itself. Otherwise nothing is written
+// and the update comes back for a rewrite.
+pub fn append(
+    archive_path: &Path,
+    mut update: Update,
+    options: &CompressOptions,
+) -> Result<Vec<String>, Update> {
+    let tar_file = File::open(archive_path).expect("tar open failed");
+    let mut members = Members::new(BufReader::new(tar_file));
+    while let Some(member) = members
+        .next()
+        .unwrap_or_else(|err| panic!("tar read entry failed: {}", err))
+    {
+        if !update.keeps(&member.name) {
+            return Err(update);
+        }
+    }
+    let end = members.offset();
+
+    // The old end-of-archive blocks come back if the append doesn't finish.
+    let len = fs::metadata(archive_path).expect("tar stat failed").len();
+    let mut appended =
+        InPlace::open(archive_path, end, len.max(end + 1024)).expect("tar open for append failed");
+    let mut builder = tar::Builder::new(&mut appended);
+    let warnings = append_update(&mut builder, update, options, "tar");
+    builder.finish().expect("tar finish failed");
+    drop(builder);
+    appended.finish().expect("tar truncate failed");
+    Ok(warnings)
+}
+
+pub fn update(
+    archive_path: &Path,
+    dst_path: &Path,
+    update: Update,
+    options: &CompressOptions,
+) -> Vec<String> {
+    let open = || BufReader::new(File::open(archive_path).expect("tar open failed"));
+    let dst_file = File::create(dst_path).expect("tar create failed");
+    let mut builder = tar::Builder::new(dst_file);
+    let warnings = rewrite(open, archive_path, &mut builder, update, options, "tar");
+    builder.finish().expect("tar finish failed");
+    warnings
+}
+
+// Shared by every tar.* backend: entries are written through the Extractor
+// instead of the tar crate so they get the same safety checks as zip and 7z.
+pub(crate) fn unpack<R: Read>(
+    mut archive: Archive<R>,
+    src_path: &Path,
+    dst_path: &Path,
+    options: &DecompressOptions,
+    label: &str,
+) -> Result<Vec<String>, ArchiveError> {
+    let mut extractor = Extractor::new(src_path, dst_path, options)
+        .unwrap_or_else(|err| panic!("{} open dst dir failed: {}", label, err));
+    let entries = archive
+        .entries()
+        .unwrap_or_else(|err| panic!("{} entries failed: {}", label, err));
+    for entry in entries {
+        let mut entry = entry.unwrap_or_else(|err| panic!("{} entry failed: {}", label, err));
+        let entry_path = entry
+            .path()
+            .unwrap_or_else(|err| panic!("{} entry path failed: {}", label, err))
+            .into_owned();
+        let link_name = entry
+            .link_name()
+            .unwrap_or_else(|err| panic!("{} entry link name failed: {}", label, err))
+            .map(|name| name.into_owned());
+        let records = pax_records(&mut entry)
+            .unwrap_or_else(|err| panic!("{} entry pax headers failed: {}", label, err));
+        let header = entry.header();
+        let pax_mtime = records
+            .iter()
+            .find(|(key, _)| key == "mtime")
+            .and_then(|(_, value)| parse_pax_time(value));
+        let pax_sparse = pax_sparse(&records);
+        let meta = EntryMeta {
+            mode: header.mode().ok(),
+            mtime: pax_mtime.or_else(|| {
+                header
+                    .mtime()
+                    .ok()
+                    .map(|mtime| FileTime::from_unix_time(mtime as i64, 0))
+            }),
+            xattrs: if options.xattrs {
+                records
+                    .into_iter()
+                    .filter_map(|(key, value)| {
+                        key.strip_prefix(xattr::PAX_PREFIX)
+                            .map(|name| (name.to_string(), value))
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            sparse: header.entry_type() == tar::EntryType::GNUSparse || pax_sparse.is_some(),
+            owner: header.uid().ok().zip(header.gid().ok()),
+        };
+
+        match (header.entry_type(), link_name) {
+            (tar::EntryType::Directory, _) => extractor.directory(&entry_path, meta)?,
+            (tar::EntryType::Symlink, Some(target)) => extractor.symlink(&entry_path, &target)?,
+            (tar::EntryType::Link, Some(target)) => extractor.hardlink(&entry_path, &target)?,
+            (tar::EntryType::Fifo, _) => {
+                extractor.special(&entry_path, SpecialKind::Fifo, None, meta)?
+            }
+            (tar::EntryType::Char, _) | (tar::EntryType::Block, _) => {
+                let kind = if header.entry_type() == tar::EntryType::Char {
+                    SpecialKind::CharDevice
+                } else {
+                    SpecialKind::BlockDevice
+                };
+                let device = match (header.device_major(), header.device_minor()) {
+                    (Ok(Some(major)), Ok(Some(minor))) => Some((major, minor)),
+                    _ => None,
+                };
+                extractor.special(&entry_path, kind, device, meta)?
+            }
+            (tar::EntryType::XGlobalHeader, _) => {}
+            _ => match pax_sparse {
+                Some((name, size)) => {
+                    let regions = sparse::read_map(&mut entry)
+                        .unwrap_or_else(|err| panic!("{} sparse map failed: {}", label, err));
+                    let mut expanded = sparse::Expanded::new(&mut entry, regions, size)
+                        .unwrap_or_else(|err| panic!("{} sparse map failed: {}", label, err));
+                    extractor.file(&name, &mut expanded, meta)?
+                }
+                None => extractor.file(&entry_path, &mut entry, meta)?,
+            },
+        }
+    }
+    Ok(extractor.finish())
+}
+
+// The records of the PAX header in front of an entry; path, linkpath, size,
+// uid and gid are already applied by the tar crate.
+fn pax_records<R: Read>(entry: &mut tar::Entry<R>) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut records = Vec::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Ok(key) = extension.key() {
+                records.push((key.to_string(), extension.value_bytes().to_vec()));
+            }
         }
-        entry.unpack_in(dst_path).expect("tar unpack failed");
     }
+    Ok(records)
+}
+
+// The real name and size of a PAX 1.0 sparse entry; the header only has a
+// placeholder name and the size of the map plus the data regions.
+pub(super) fn pax_sparse(records: &[(String, Vec<u8>)]) -> Option<(PathBuf, u64)> {
+    let value = |key: &str| {
+        records
+            .iter()
+            .find(|(found, _)| found == key)
+            .map(|(_, value)| value.as_slice())
+    };
+    if value(sparse::MAJOR) != Some(b"1") || value(sparse::MINOR) != Some(b"0") {
+        return None;
+    }
+    let size = std::str::from_utf8(value(sparse::REAL_SIZE)?)
+        .ok()?
+        .parse()
+        .ok()?;
+    Some((path_from_bytes(value(sparse::NAME)?), size))
+}
+
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<Vec<String>, ArchiveError> {
+    let tar_file = File::open(src_path).expect("tar open failed");
+    unpack(Archive::new(tar_file), src_path, dst_path, options, "tar")
 }
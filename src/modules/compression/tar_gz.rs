@@ -1,66 +1,59 @@
-use std::ffi::OsString;
 use std::fs::File;
-use std::io::{Seek, Write};
-use std::path::{Path, PathBuf};
 
 use flate2;
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use tar;
 use tar::Archive;
-use walkdir::{DirEntry, WalkDir};
 
-use crate::utils::is_safe_path;
+use crate::modules::error::ArchiveError;
+use crate::modules::options::{CompressOptions, DecompressOptions};
 
-fn archive_path(src_root: &Path, entry_path: &Path) -> PathBuf {
-    let base: Option<OsString> = src_root.file_name().map(|s| s.to_os_string());
-    if entry_path == src_root {
-        if let Some(base) = base {
-            return PathBuf::from(base);
-        }
-    }
-    match entry_path.strip_prefix(src_root) {
-        Ok(rel) => match base {
-            Some(base) => PathBuf::from(base).join(rel),
-            None => rel.to_path_buf(),
-        },
-        Err(_) => entry_path.to_path_buf(),
-    }
-}
-
-fn tar_gz_dir<T>(it: &mut dyn Iterator<Item = DirEntry>, tar_gz_file: T, src_root: &Path)
-where
-    T: Write + Seek,
-{
-    let enc = GzEncoder::new(tar_gz_file, flate2::Compression::default());
-    let mut tar_gz_builder = tar::Builder::new(enc);
-    for entry in it {
-        let path = entry.path();
-        let name = archive_path(src_root, path);
-        tar_gz_builder
-            .append_path_with_name(path, &name)
-            .expect("tar.gz append failed");
-    }
-}
+use super::update::Update;
 
-pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
-    let tar_gz_file = File::create(dst_path).expect("tar.gz create failed");
-    let walkdir = WalkDir::new(src_path);
-    let it = walkdir.into_iter();
-    tar_gz_dir(&mut it.filter_map(|e| e.ok()), tar_gz_file, src_path);
+pub fn compress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &CompressOptions,
+) -> Vec<String> {
+    let dst_file = File::create(dst_path).expect("tar.gz create failed");
+    let enc = GzEncoder::new(dst_file, flate2::Compression::default());
+    let mut builder = tar::Builder::new(enc);
+    let warnings = super::tar::pack(&mut builder, src_path, options, "tar.gz");
+    builder
+        .into_inner()
+        .expect("tar.gz finish failed")
+        .finish()
+        .expect("gz finish failed");
+    warnings
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
-    std::fs::create_dir_all(dst_path).expect("tar.gz create dst dir failed");
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<Vec<String>, ArchiveError> {
     let tar_gz_file = File::open(src_path).expect("tar.gz open failed");
     let dec = GzDecoder::new(tar_gz_file);
-    let mut archive = Archive::new(dec);
-    for entry in archive.entries().expect("tar.gz entries failed") {
-        let mut entry = entry.expect("tar.gz entry failed");
-        let entry_path = entry.path().expect("tar.gz entry path failed");
-        if !is_safe_path(&entry_path) {
-            panic!("tar.gz entry path traversal detected");
-        }
-        entry.unpack_in(dst_path).expect("tar.gz unpack failed");
-    }
+    super::tar::unpack(Archive::new(dec), src_path, dst_path, options, "tar.gz")
+}
+
+// Streams the old archive through the decoder into a new one.
+pub fn update(
+    archive_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    update: Update,
+    options: &CompressOptions,
+) -> Vec<String> {
+    let open = || GzDecoder::new(File::open(archive_path).expect("tar.gz open failed"));
+    let dst_file = File::create(dst_path).expect("tar.gz create failed");
+    let enc = GzEncoder::new(dst_file, flate2::Compression::default());
+    let mut builder = tar::Builder::new(enc);
+    let warnings = super::tar::rewrite(open, archive_path, &mut builder, update, options, "tar.gz");
+    builder
+        .into_inner()
+        .expect("tar.gz finish failed")
+        .finish()
+        .expect("gz finish failed");
+    warnings
 }
@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::modules::options::CompressOptions;
+
+use super::source::{SourceEntry, SourceTree};
+
+// What an update does to an archive. The new entries are walked up front, so
+// the ones they replace are known while the old archive streams past.
+pub struct Update {
+    pub entries: Vec<SourceEntry>,
+    // names of the new entries
+    replaced: HashSet<PathBuf>,
+    // names whose entries go, along with everything below them, and whether
+    // any did
+    delete: Vec<(PathBuf, bool)>,
+    warnings: Vec<String>,
+}
+
+impl Update {
+    // Every path in `add` is walked like a compression input, so its entries
+    // get the names compress would give them; `dedup_hardlinks` as for
+    // SourceTree, so tar stores a second name for a file as a link. The archive itself is left out when it is
+    // below one of them, like tar does.
+    pub fn new(
+        archive_path: &Path,
+        add: &[PathBuf],
+        delete: &[PathBuf],
+        options: &CompressOptions,
+        dedup_hardlinks: bool,
+        label: &str,
+    ) -> Update {
+        let archive = fs::canonicalize(archive_path).ok();
+        let mut entries = Vec::new();
+        let mut warnings = Vec::new();
+        for src_path in add {
+            let mut tree = SourceTree::new(src_path, options, dedup_hardlinks)
+                .unwrap_or_else(|err| panic!("{} read exclude patterns failed: {}", label, err));
+            for entry in tree.by_ref() {
+                let entry = entry.unwrap_or_else(|err| panic!("{} walk failed: {}", label, err));
+                if entry.path.file_name() == archive_path.file_name()
+                    && archive.is_some()
+                    && fs::canonicalize(&entry.path).ok() == archive
+                {
+                    warnings.push(format!(
+                        "skipped {}: it is the archive being updated",
+                        entry.path.display()
+                    ));
+                    continue;
+                }
+                entries.push(entry);
+            }
+            warnings.extend(tree.finish());
+        }
+        Update {
+            replaced: entries.iter().map(|entry| key(&entry.name)).collect(),
+            entries,
+            delete: delete.iter().map(|name| (key(name), false)).collect(),
+            warnings,
+        }
+    }
+
+    // Whether an entry already in the archive stays; false when a new entry
+    // replaces it or it is deleted.
+    pub fn keeps(&mut self, name: &Path) -> bool {
+        let name = key(name);
+        let mut keep = !self.replaced.contains(&name);
+        for (deleted, matched) in &mut self.delete {
+            if name.starts_with(deleted.as_path()) {
+                *matched = true;
+                keep = false;
+            }
+        }
+        keep
+    }
+
+    // What was left out of the new entries, and delete names nothing matched.
+    pub fn finish(self) -> Vec<String> {
+        let mut warnings = self.warnings;
+        for (name, matched) in self.delete {
+            if !matched {
+                warnings.push(format!("no entry {} to delete", name.display()));
+            }
+        }
+        warnings
+    }
+}
+
+// Names compare by their normal components, so `./src/a.txt` from another
+// tar and `src/a.txt` from the walk are the same entry, and so are `dir/`
+// and `dir`.
+pub(super) fn key(name: &Path) -> PathBuf {
+    name.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
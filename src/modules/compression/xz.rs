@@ -4,6 +4,10 @@ use std::io::{Read, Write};
 use xz2::read::XzDecoder;
 use xz2::write::XzEncoder;
 
+use crate::modules::error::ArchiveError;
+use crate::modules::limits::Budget;
+use crate::modules::options::DecompressOptions;
+
 pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     let xz_file = File::create(dst_path).expect("xz create failed");
     let mut enc = XzEncoder::new(xz_file, 6);
@@ -15,14 +19,21 @@ pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     enc.finish().expect("xz finish failed");
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<(), ArchiveError> {
     let xz_file = File::open(src_path).expect("xz open failed");
-    let dec = XzDecoder::new(xz_file);
-    let mut reader = std::io::BufReader::new(dec);
-    let mut content = Vec::new();
-    reader.read_to_end(&mut content).expect("xz unpack failed");
+    let mut dec = XzDecoder::new(xz_file);
     let mut dst_file = File::create(dst_path).expect("xz create dst failed");
-    dst_file
-        .write_all(content.as_slice())
-        .expect("xz write dst failed");
+    let mut budget = Budget::new(&options.limits, src_path);
+    budget.add_entry()?;
+    budget.copy("xz unpack", &mut dec, &mut dst_file)?;
+    Ok(())
+}
+
+// The decoded stream, for reading the output back after compressing.
+pub fn decoder(file: File) -> Box<dyn Read> {
+    Box::new(XzDecoder::new(file))
 }
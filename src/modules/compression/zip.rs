@@ -1,100 +1,338 @@
-use std::ffi::OsString;
-use std::fs;
 use std::fs::File;
-use std::io;
 use std::io::BufReader;
 use std::io::{Read, Seek, Write};
 use std::path::{Path, PathBuf};
 
-use walkdir::{DirEntry, WalkDir};
+use filetime::FileTime;
+use time::OffsetDateTime;
 use zip;
 use zip::write::FileOptions;
 
-fn archive_path(src_root: &Path, entry_path: &Path) -> PathBuf {
-    let base: Option<OsString> = src_root.file_name().map(|s| s.to_os_string());
-    if entry_path == src_root {
-        if let Some(base) = base {
-            return PathBuf::from(base);
-        }
-    }
-    match entry_path.strip_prefix(src_root) {
-        Ok(rel) => match base {
-            Some(base) => PathBuf::from(base).join(rel),
-            None => rel.to_path_buf(),
-        },
-        Err(_) => entry_path.to_path_buf(),
-    }
-}
+use crate::modules::error::{ArchiveError, SpecialKind};
+use crate::modules::extract::{EntryMeta, Extractor};
+use crate::modules::options::{CompressOptions, DecompressOptions};
+
+use super::links::{self, Kind};
+use super::reproducible;
+use super::source::{SourceEntry, SourceTree};
+use super::update::Update;
 
 fn zip_dir<T>(
-    it: &mut dyn Iterator<Item = DirEntry>,
+    tree: &mut SourceTree,
     writer: T,
     method: zip::CompressionMethod,
-    src_root: &Path,
-) -> zip::result::ZipResult<()>
+    compress_options: &CompressOptions,
+) -> zip::result::ZipResult<Vec<String>>
 where
     T: Write + Seek,
 {
     let mut zip = zip::ZipWriter::new(writer);
-    let options = FileOptions::default()
-        .compression_method(method)
-        .unix_permissions(0o755);
+    let mut warnings = Vec::new();
 
     let mut buffer = Vec::new();
-    for entry in it {
-        let path = entry.path();
-        let name = archive_path(src_root, path);
+    for entry in tree {
+        let entry = entry.unwrap_or_else(|err| panic!("zip walk failed: {}", err));
+        add_entry(
+            &mut zip,
+            entry,
+            method,
+            compress_options,
+            &mut buffer,
+            &mut warnings,
+        );
+    }
+    zip.finish().expect("zip compress failed");
+    Result::Ok(warnings)
+}
+
+fn add_entry<T>(
+    zip: &mut zip::ZipWriter<T>,
+    entry: SourceEntry,
+    method: zip::CompressionMethod,
+    compress_options: &CompressOptions,
+    buffer: &mut Vec<u8>,
+    warnings: &mut Vec<String>,
+) where
+    T: Write + Seek,
+{
+    let reproducible = &compress_options.reproducible;
+    let SourceEntry {
+        path,
+        name,
+        kind,
+        metadata,
+    } = entry;
+    let (mode, mtime) = if reproducible.enabled {
+        (
+            reproducible::mode(&metadata),
+            reproducible::mtime(&metadata, reproducible),
+        )
+    } else {
+        (
+            entry_mode(&metadata),
+            FileTime::from_last_modification_time(&metadata).unix_seconds(),
+        )
+    };
+    // Like Info-ZIP, the DOS time is local and files carry the exact UTC
+    // time next to it. Reproducible archives keep theirs in UTC instead, so
+    // they don't depend on the timezone they were made in.
+    let dos_seconds = if reproducible.enabled {
+        mtime
+    } else {
+        mtime + utc_offset(mtime)
+    };
+    let options = FileOptions::default()
+        .compression_method(method)
+        .unix_permissions(mode)
+        .last_modified_time(dos_time(dos_seconds));
 
-        if path.is_file() {
-            zip.start_file(name.to_string_lossy().into_owned(), options)
+    match kind {
+        Kind::Symlink(target) => {
+            zip.add_symlink(
+                name.to_string_lossy().into_owned(),
+                target.to_string_lossy().into_owned(),
+                options,
+            )
+            .expect("zip add symlink failed");
+        }
+        // Zip has no hardlink entry, so every name of a file is stored as a
+        // full copy and the walk never yields one.
+        Kind::File | Kind::Hardlink(_) => {
+            zip.start_file_with_extra_data(name.to_string_lossy().into_owned(), options)
                 .expect("zip start file from path failed");
-            let mut f = File::open(path).expect("zip open compressing-file failed");
+            if let Ok(mtime) = i32::try_from(mtime) {
+                zip.write_all(&extended_timestamp(mtime))
+                    .expect("zip write extra field failed");
+            }
+            zip.end_extra_data().expect("zip end extra field failed");
+            let mut f = File::open(&path).expect("zip open compressing-file failed");
 
-            f.read_to_end(&mut buffer)
+            f.read_to_end(buffer)
                 .expect("zip read compressing-file failed");
-            zip.write_all(&buffer).expect("zip compress file failed");
+            zip.write_all(buffer).expect("zip compress file failed");
             buffer.clear();
-        } else if !path.as_os_str().is_empty() {
+        }
+        Kind::Directory => {
             zip.add_directory(name.to_string_lossy().into_owned(), options)
                 .expect("zip add dir from path failed");
         }
+        Kind::Special(kind) => {
+            links::store_special(kind, &name, compress_options.special_files, false, warnings)
+                .unwrap_or_else(|err| panic!("zip add special file failed: {}", err));
+        }
     }
-    zip.finish().expect("zip compress failed");
-    Result::Ok(())
 }
 
-pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn compress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &CompressOptions,
+) -> Vec<String> {
     let zip_file = File::create(dst_path).expect("zip create failed");
-    let walkdir = WalkDir::new(src_path);
-    let it = walkdir.into_iter();
-    zip_dir(
-        &mut it.filter_map(|e| e.ok()),
-        zip_file,
-        zip::CompressionMethod::Stored,
-        src_path,
-    )
-    .expect("zip compress dir failed");
-}
-
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+    let mut tree =
+        SourceTree::new(src_path, options, false).expect("zip read exclude patterns failed");
+    let mut warnings = zip_dir(&mut tree, zip_file, zip::CompressionMethod::Stored, options)
+        .expect("zip compress dir failed");
+    warnings.extend(tree.finish());
+    warnings
+}
+
+// Entries the update keeps are copied raw, still compressed, and the new ones
+// are added after them under a new central directory.
+pub fn update(
+    archive_path: &Path,
+    dst_path: &Path,
+    mut update: Update,
+    options: &CompressOptions,
+) -> Vec<String> {
+    let zip_file = File::open(archive_path).expect("zip open failed");
+    let mut old =
+        zip::ZipArchive::new(BufReader::new(zip_file)).expect("zip open to archive failed");
+    let dst_file = File::create(dst_path).expect("zip create failed");
+    let mut zip = zip::ZipWriter::new(dst_file);
+    zip.set_comment(String::from_utf8_lossy(old.comment()).into_owned());
+    for i in 0..old.len() {
+        let file = old.by_index_raw(i).expect("zip index not exist");
+        if update.keeps(Path::new(file.name())) {
+            zip.raw_copy_file(file).expect("zip copy entry failed");
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let mut buffer = Vec::new();
+    for entry in std::mem::take(&mut update.entries) {
+        add_entry(
+            &mut zip,
+            entry,
+            zip::CompressionMethod::Stored,
+            options,
+            &mut buffer,
+            &mut warnings,
+        );
+    }
+    zip.finish().expect("zip compress failed");
+    warnings.extend(update.finish());
+    warnings
+}
+
+// The permission bits of the entry itself.
+#[cfg(unix)]
+fn entry_mode(metadata: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn entry_mode(metadata: &std::fs::Metadata) -> u32 {
+    reproducible::mode(metadata)
+}
+
+// First and last second a DOS timestamp can hold, read as if it were UTC.
+const DOS_FIRST: i64 = 315_532_800; // 1980-01-01T00:00:00
+const DOS_LAST: i64 = 4_354_819_198; // 2107-12-31T23:59:58
+
+// The date and time fields of `seconds` since the epoch, clamped to the
+// 1980-2107 range a DOS timestamp can hold. Otherwise the zip crate stamps
+// every entry with the current time.
+fn dos_time(seconds: i64) -> zip::DateTime {
+    OffsetDateTime::from_unix_timestamp(seconds.clamp(DOS_FIRST, DOS_LAST))
+        .ok()
+        .and_then(|time| zip::DateTime::try_from(time).ok())
+        .unwrap_or_default()
+}
+
+// Unix symlinks are stored as S_IFLNK entries whose data is the link target.
+fn is_symlink(mode: Option<u32>) -> bool {
+    mode.map(|mode| mode & 0o170000 == 0o120000)
+        .unwrap_or(false)
+}
+
+// Info-ZIP's "new unix" extra field: version 1, then uid and gid, each as a
+// size byte and that many little-endian bytes.
+const UNIX_OWNER_TAG: u16 = 0x7875;
+
+// Info-ZIP's extended timestamp: a flags byte, then the times the flags name
+// as little-endian seconds since the epoch, the mtime first.
+const EXTENDED_TIMESTAMP_TAG: u16 = 0x5455;
+const EXTENDED_TIMESTAMP_MTIME: u8 = 1;
+
+// The data of the first extra field tagged `tag`.
+fn extra_field(mut extra: &[u8], tag: u16) -> Option<&[u8]> {
+    while extra.len() >= 4 {
+        let field_tag = u16::from_le_bytes([extra[0], extra[1]]);
+        let size = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + size)?;
+        if field_tag == tag {
+            return Some(data);
+        }
+        extra = &extra[4 + size..];
+    }
+    None
+}
+
+fn unix_owner(extra: &[u8]) -> Option<(u64, u64)> {
+    let data = extra_field(extra, UNIX_OWNER_TAG).filter(|data| data.first() == Some(&1))?;
+    let mut rest = &data[1..];
+    let mut id = || -> Option<u64> {
+        let (&len, tail) = rest.split_first()?;
+        let bytes = tail.get(..len as usize).filter(|bytes| bytes.len() <= 8)?;
+        rest = &tail[len as usize..];
+        Some(
+            bytes
+                .iter()
+                .rev()
+                .fold(0, |id, byte| id << 8 | *byte as u64),
+        )
+    };
+    id().zip(id())
+}
+
+fn extended_mtime(extra: &[u8]) -> Option<i64> {
+    let data = extra_field(extra, EXTENDED_TIMESTAMP_TAG)?;
+    let (&flags, rest) = data.split_first()?;
+    if flags & EXTENDED_TIMESTAMP_MTIME == 0 {
+        return None;
+    }
+    let seconds = rest.get(..4)?;
+    Some(i32::from_le_bytes([seconds[0], seconds[1], seconds[2], seconds[3]]) as i64)
+}
+
+// The extended timestamp field of a file, mtime only.
+fn extended_timestamp(mtime: i32) -> [u8; 9] {
+    let mut field = [0; 9];
+    field[..2].copy_from_slice(&EXTENDED_TIMESTAMP_TAG.to_le_bytes());
+    field[2..4].copy_from_slice(&5u16.to_le_bytes());
+    field[4] = EXTENDED_TIMESTAMP_MTIME;
+    field[5..].copy_from_slice(&mtime.to_le_bytes());
+    field
+}
+
+// The exact time from an extended timestamp field when there is one.
+// Otherwise the zoneless DOS timestamp is read as local time, which is what
+// Info-ZIP and most other writers store.
+fn entry_mtime(modified: zip::DateTime, extra: &[u8]) -> FileTime {
+    if let Some(seconds) = extended_mtime(extra) {
+        return FileTime::from_unix_time(seconds, 0);
+    }
+    // fields that don't make a date are read as the earliest DOS time
+    let local = modified
+        .to_time()
+        .map(|time| time.unix_timestamp())
+        .unwrap_or(DOS_FIRST);
+    // the offset in effect at that local time, found from a first guess
+    let offset = utc_offset(local - utc_offset(local));
+    FileTime::from_unix_time(local - offset, 0)
+}
+
+// Seconds the local timezone is ahead of UTC at `seconds` since the epoch.
+#[cfg(unix)]
+fn utc_offset(seconds: i64) -> i64 {
+    let time = seconds as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn utc_offset(_seconds: i64) -> i64 {
+    0
+}
+
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<Vec<String>, ArchiveError> {
     let zip_file = File::open(src_path).expect("zip open failed");
     let mut zip_archive =
         zip::ZipArchive::new(BufReader::new(zip_file)).expect("zip open to archive failed");
+    let mut extractor =
+        Extractor::new(src_path, dst_path, options).expect("zip open dst dir failed");
 
     for i in 0..zip_archive.len() {
         let mut file = zip_archive.by_index(i).expect("zip index not exist");
-        let outpath = &PathBuf::from(dst_path).join(file.mangled_name());
+        let name = PathBuf::from(file.name());
+        let meta = EntryMeta {
+            mode: file.unix_mode(),
+            mtime: Some(entry_mtime(file.last_modified(), file.extra_data())),
+            owner: unix_owner(file.extra_data()),
+            ..Default::default()
+        };
 
-        if (file.name()).ends_with('/') {
-            fs::create_dir_all(outpath).expect("zip create dir all failed");
+        if is_symlink(file.unix_mode()) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)
+                .expect("zip read symlink target failed");
+            extractor.symlink(&name, Path::new(&target))?;
+        } else if let Some(kind) = file.unix_mode().and_then(SpecialKind::from_mode) {
+            extractor.special(&name, kind, None, meta)?;
+        } else if file.is_dir() {
+            extractor.directory(&name, meta)?;
         } else {
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(p).expect("zip create dir all failed");
-                }
-            }
-            let mut outfile = fs::File::create(outpath).expect("zip create file failed");
-            io::copy(&mut file, &mut outfile).expect("zip file copy failed");
+            extractor.file(&name, &mut file, meta)?;
         }
     }
+    Ok(extractor.finish())
 }
@@ -1,6 +1,10 @@
 use std::fs::File;
 use std::io::{Read, Write};
 
+use crate::modules::error::ArchiveError;
+use crate::modules::limits::Budget;
+use crate::modules::options::DecompressOptions;
+
 pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     let dst_file = File::create(dst_path).expect("zst create failed");
     let mut enc = zstd::Encoder::new(dst_file, 0).expect("zst encoder failed");
@@ -12,11 +16,21 @@ pub fn compress(src_path: &std::path::Path, dst_path: &std::path::Path) {
     enc.finish().expect("zst finish failed");
 }
 
-pub fn decompress(src_path: &std::path::Path, dst_path: &std::path::Path) {
+pub fn decompress(
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<(), ArchiveError> {
     let src_file = File::open(src_path).expect("zst open failed");
     let mut dec = zstd::Decoder::new(src_file).expect("zst decoder failed");
-    let mut content = Vec::new();
-    dec.read_to_end(&mut content).expect("zst read failed");
     let mut dst_file = File::create(dst_path).expect("zst create dst failed");
-    dst_file.write_all(&content).expect("zst write dst failed");
+    let mut budget = Budget::new(&options.limits, src_path);
+    budget.add_entry()?;
+    budget.copy("zst unpack", &mut dec, &mut dst_file)?;
+    Ok(())
+}
+
+// The decoded stream, for reading the output back after compressing.
+pub fn decoder(file: File) -> Box<dyn Read> {
+    Box::new(zstd::Decoder::new(file).expect("zst decoder failed"))
 }
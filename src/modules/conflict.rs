@@ -0,0 +1,87 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+
+use crate::contents::enums::ConflictPolicy;
+
+// What is already at the name an entry wants.
+#[derive(Debug, Clone, Copy)]
+pub enum Existing {
+    Dir,
+    Other { mtime: FileTime },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    // write the entry, replacing a file or merging into a directory
+    Write,
+    // keep what is there and drop the entry
+    Skip,
+    // write the entry under the next free numbered name
+    Rename,
+    Fail,
+}
+
+// One rule set for the Extractor and for the service moving finished output
+// into place. Directories merge; a directory can't be skipped or renamed
+// without orphaning whatever the archive puts below it.
+pub fn decide(
+    policy: ConflictPolicy,
+    existing: Existing,
+    entry_is_dir: bool,
+    entry_mtime: Option<FileTime>,
+) -> Decision {
+    match (existing, entry_is_dir) {
+        (Existing::Dir, true) => Decision::Write,
+        (Existing::Other { .. }, true) => match policy {
+            ConflictPolicy::Overwrite | ConflictPolicy::Newer => Decision::Write,
+            _ => Decision::Fail,
+        },
+        (Existing::Dir, false) => match policy {
+            ConflictPolicy::Skip => Decision::Skip,
+            ConflictPolicy::Rename => Decision::Rename,
+            _ => Decision::Fail,
+        },
+        (Existing::Other { mtime }, false) => match policy {
+            ConflictPolicy::Error => Decision::Fail,
+            ConflictPolicy::Skip => Decision::Skip,
+            ConflictPolicy::Overwrite => Decision::Write,
+            ConflictPolicy::Rename => Decision::Rename,
+            ConflictPolicy::Newer => match entry_mtime {
+                Some(entry_mtime) if entry_mtime > mtime => Decision::Write,
+                _ => Decision::Skip,
+            },
+        },
+    }
+}
+
+// `report.txt` -> `report_1.txt`, `archive.tar.gz` -> `archive_1.tar.gz`,
+// the same scheme 7-Zip uses for its auto-rename.
+pub fn numbered_name(name: &OsStr, number: u32) -> OsString {
+    let name = name.to_string_lossy();
+    let split = match name.find('.') {
+        Some(0) | None => name.len(),
+        Some(index) => index,
+    };
+    let (stem, extension) = name.split_at(split);
+    OsString::from(format!("{}_{}{}", stem, number, extension))
+}
+
+// The first numbered sibling of `path` that nothing occupies yet.
+pub fn free_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default();
+    let mut number = 1;
+    loop {
+        let candidate = path.with_file_name(numbered_name(file_name, number));
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+        number += 1;
+    }
+}
+
+pub fn skip_warning(path: &Path) -> String {
+    format!("kept existing {}", path.display())
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsafeReason {
+    AbsolutePath,
+    PathTraversal,
+    SymlinkEscape,
+    HardlinkEscape,
+    SymlinkInPath,
+}
+
+impl fmt::Display for UnsafeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnsafeReason::AbsolutePath => write!(f, "absolute path"),
+            UnsafeReason::PathTraversal => write!(f, "path traversal"),
+            UnsafeReason::SymlinkEscape => write!(f, "symlink points outside the destination"),
+            UnsafeReason::HardlinkEscape => write!(f, "hardlink points outside the destination"),
+            UnsafeReason::SymlinkInPath => write!(f, "path leads through a symlink"),
+        }
+    }
+}
+
+// A file that is neither regular, a directory nor a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialKind {
+    Fifo,
+    CharDevice,
+    BlockDevice,
+    Socket,
+}
+
+impl SpecialKind {
+    // The kind a unix mode's file type bits describe, as zip and 7z record it.
+    pub fn from_mode(mode: u32) -> Option<SpecialKind> {
+        match mode & 0o170000 {
+            0o010000 => Some(SpecialKind::Fifo),
+            0o020000 => Some(SpecialKind::CharDevice),
+            0o060000 => Some(SpecialKind::BlockDevice),
+            0o140000 => Some(SpecialKind::Socket),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SpecialKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpecialKind::Fifo => write!(f, "FIFO"),
+            SpecialKind::CharDevice => write!(f, "character device"),
+            SpecialKind::BlockDevice => write!(f, "block device"),
+            SpecialKind::Socket => write!(f, "socket"),
+        }
+    }
+}
+
+// The decompression limit that was hit, with its configured maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    TotalBytes(u64),
+    EntryBytes(u64),
+    Ratio(u64),
+    Entries(u64),
+    Depth(u32),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::TotalBytes(max) => write!(f, "output is larger than {} bytes", max),
+            Limit::EntryBytes(max) => write!(f, "an entry is larger than {} bytes", max),
+            Limit::Ratio(max) => write!(f, "expansion ratio is above {}:1", max),
+            Limit::Entries(max) => write!(f, "archive has more than {} entries", max),
+            Limit::Depth(max) => write!(f, "input is nested more than {} layers deep", max),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    UnsafeEntry { path: PathBuf, reason: UnsafeReason },
+    LimitExceeded(Limit),
+    Conflict { path: PathBuf },
+    SpecialFile { path: PathBuf, kind: SpecialKind },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::UnsafeEntry { path, reason } => {
+                write!(f, "unsafe archive entry {}: {}", path.display(), reason)
+            }
+            ArchiveError::LimitExceeded(limit) => {
+                write!(f, "decompression limit exceeded: {}", limit)
+            }
+            ArchiveError::Conflict { path } => write!(f, "{} already exists", path.display()),
+            ArchiveError::SpecialFile { path, kind } => {
+                write!(f, "special file {}: {}", path.display(), kind)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+// Filesystems allocate in blocks of at least this much, so a smaller run of
+// zeros can't become a hole anyway.
+const BLOCK_SIZE: usize = 4096;
+const ZEROS: [u8; BLOCK_SIZE] = [0; BLOCK_SIZE];
+
+// Writes a sparse entry into a fresh, empty file: blocks that are all zeros
+// are skipped over instead of written, so they stay holes, and finish() sets
+// the length to cover a hole at the end.
+pub(super) struct HoleWriter<'a> {
+    file: &'a File,
+    // bytes of the entry seen so far
+    position: u64,
+    // where the file's own cursor is
+    cursor: u64,
+}
+
+impl<'a> HoleWriter<'a> {
+    pub fn new(file: &'a File) -> HoleWriter<'a> {
+        HoleWriter {
+            file,
+            position: 0,
+            cursor: 0,
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.file.set_len(self.position)
+    }
+}
+
+impl Write for HoleWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut done = 0;
+        while done < buf.len() {
+            let into_block = (self.position % BLOCK_SIZE as u64) as usize;
+            let len = (BLOCK_SIZE - into_block).min(buf.len() - done);
+            let piece = &buf[done..done + len];
+            if piece != &ZEROS[..len] {
+                let mut file = self.file;
+                if self.cursor != self.position {
+                    file.seek(SeekFrom::Start(self.position))?;
+                }
+                file.write_all(piece)?;
+                self.cursor = self.position + len as u64;
+            }
+            self.position += len as u64;
+            done += len;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use filetime::FileTime;
+
+use crate::contents::enums::{ConflictPolicy, SpecialFilePolicy, UnsafeEntryPolicy};
+use crate::modules::conflict;
+use crate::modules::conflict::Decision;
+use crate::modules::error::{ArchiveError, SpecialKind, UnsafeReason};
+use crate::modules::limits::Budget;
+use crate::modules::options::{DecompressOptions, PermissionOptions};
+use crate::modules::transform::{self, Transform};
+use crate::modules::xattr;
+
+mod holes;
+#[cfg(not(unix))]
+mod portable;
+mod symlinks;
+#[cfg(unix)]
+mod unix;
+
+use holes::HoleWriter;
+#[cfg(not(unix))]
+use portable::{chown, is_root, process_umask, set_mode, Root};
+#[cfg(unix)]
+use unix::{chown, is_root, process_umask, set_mode, Root};
+
+use symlinks::Symlinks;
+
+// Why a write below the root was refused or failed.
+enum WriteError {
+    // an existing component of the path is a symlink
+    SymlinkInPath,
+    Io(io::Error),
+}
+
+impl From<io::Error> for WriteError {
+    fn from(err: io::Error) -> Self {
+        WriteError::Io(err)
+    }
+}
+
+// What the backends know about an entry besides its name and data.
+#[derive(Debug, Clone, Default)]
+pub struct EntryMeta {
+    pub mode: Option<u32>,
+    pub mtime: Option<FileTime>,
+    // extended attributes to put back, only filled in when they were asked for
+    pub xattrs: Vec<(String, Vec<u8>)>,
+    // the archive recorded holes in this file, so runs of zeros are written
+    // back as holes
+    pub sparse: bool,
+    // uid and gid as the archive stores them
+    pub owner: Option<(u64, u64)>,
+}
+
+// Every container backend writes through this, so tar, zip and 7z reject (or
+// skip) the same hostile names and link targets. All writes are resolved
+// relative to the destination one component at a time without following
+// symlinks, so a link planted by the archive (or swapped in while we run)
+// can't redirect a later write outside the root.
+pub struct Extractor {
+    root: Root,
+    policy: UnsafeEntryPolicy,
+    conflicts: ConflictPolicy,
+    warnings: Vec<String>,
+    directories: Vec<(PathBuf, EntryMeta)>,
+    symlinks: Symlinks,
+    budget: Budget,
+    strip_components: usize,
+    transforms: Vec<Transform>,
+    permissions: PermissionOptions,
+    // bits cleared from every mode
+    umask: u32,
+    special_files: SpecialFilePolicy,
+    allow_devices: bool,
+    finished: bool,
+}
+
+impl Extractor {
+    pub fn new(src_path: &Path, root: &Path, options: &DecompressOptions) -> io::Result<Self> {
+        let mut permissions = options.permissions.clone();
+        let mut warnings = Vec::new();
+        if permissions.numeric_owner && !is_root() {
+            warnings.push("owners not restored: only root can give files away".to_string());
+            permissions.numeric_owner = false;
+        }
+        let umask = match permissions.umask {
+            Some(umask) => umask,
+            None if permissions.no_same_permissions => process_umask(),
+            None => 0,
+        };
+        Ok(Extractor {
+            root: Root::open(root)?,
+            policy: options.unsafe_entries,
+            conflicts: options.conflicts,
+            warnings,
+            directories: Vec::new(),
+            symlinks: Symlinks::default(),
+            budget: Budget::new(&options.limits, src_path),
+            strip_components: options.strip_components as usize,
+            transforms: options.transforms.clone(),
+            permissions,
+            umask,
+            special_files: options.special_files,
+            allow_devices: options.allow_devices,
+            finished: false,
+        })
+    }
+
+    pub fn directory(&mut self, name: &Path, meta: EntryMeta) -> Result<(), ArchiveError> {
+        self.budget.add_entry()?;
+        let meta = self.settle(meta, true);
+        let relative = match self.placed(name, true, meta.mtime)? {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        let created = self.root.create_dir(&relative);
+        if self.written(name, created)?.is_some() {
+            // Modes and mtimes are applied in finish(), after the children exist.
+            self.directories.push((relative, meta));
+        }
+        Ok(())
+    }
+
+    pub fn file(
+        &mut self,
+        name: &Path,
+        reader: &mut dyn Read,
+        meta: EntryMeta,
+    ) -> Result<(), ArchiveError> {
+        self.budget.add_entry()?;
+        let meta = self.settle(meta, false);
+        let file = match self.placed(name, false, meta.mtime)? {
+            Some(relative) => {
+                let created = self.root.create_file(&relative);
+                self.written(name, created)?
+            }
+            None => None,
+        };
+        let mut new_file = match file {
+            Some(new_file) => new_file,
+            // Streamed and solid formats still have to read past a skipped entry.
+            None => {
+                io::copy(reader, &mut io::sink())
+                    .unwrap_or_else(|err| panic!("skip {} failed: {}", name.display(), err));
+                return Ok(());
+            }
+        };
+        let label = format!("write {}", name.display());
+        if meta.sparse {
+            let mut holes = HoleWriter::new(&new_file.file);
+            self.budget.copy(&label, reader, &mut holes)?;
+            holes
+                .finish()
+                .unwrap_or_else(|err| panic!("{} failed: {}", label, err));
+        } else {
+            self.budget.copy(&label, reader, &mut new_file.file)?;
+        }
+        restore_metadata(&new_file.file, name, &meta, &mut self.warnings)
+            .unwrap_or_else(|err| panic!("set mode of {} failed: {}", name.display(), err));
+        if let Some(mtime) = meta.mtime {
+            filetime::set_file_handle_times(&new_file.file, None, Some(mtime))
+                .unwrap_or_else(|err| panic!("set mtime of {} failed: {}", name.display(), err));
+        }
+        // Only a complete entry takes the name; a failed copy drops the temporary.
+        let committed = self.root.commit(new_file);
+        self.written(name, committed).map(|_| ())
+    }
+
+    pub fn symlink(&mut self, name: &Path, target: &Path) -> Result<(), ArchiveError> {
+        self.budget.add_entry()?;
+        let relative = match self.relative(name)? {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        let parent = relative.parent().unwrap_or_else(|| Path::new(""));
+        if target.has_root() || normalize_lexically(&parent.join(target)).is_none() {
+            return self.refuse(name, UnsafeReason::SymlinkEscape);
+        }
+        let relative = match self.place(name, false, None)? {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        // followed through the links before it, and the ones before it
+        // followed through where it goes
+        if !self.symlinks.add(&relative, target) {
+            return self.refuse(name, UnsafeReason::SymlinkEscape);
+        }
+        let created = self.root.symlink(target, &relative);
+        self.written(name, created).map(|_| ())
+    }
+
+    // A hardlink entry whose target is another path inside the archive.
+    pub fn hardlink(&mut self, name: &Path, target: &Path) -> Result<(), ArchiveError> {
+        self.budget.add_entry()?;
+        let target = match normalize(target) {
+            Ok(target) if !target.as_os_str().is_empty() => target,
+            _ => return self.refuse(name, UnsafeReason::HardlinkEscape),
+        };
+        // The target is another entry, so it is renamed the same way.
+        let target = match self.rename(target) {
+            Ok(Some(target)) => target,
+            Err(_) => return self.refuse(name, UnsafeReason::HardlinkEscape),
+            Ok(None) => {
+                self.warn(format!(
+                    "skipped {}: link target is stripped away",
+                    name.display()
+                ));
+                return Ok(());
+            }
+        };
+        // Linking to a symlink makes another symlink with the same target,
+        // which leads somewhere else from its own directory.
+        let link_target = self.symlinks.target(&target).map(Path::to_path_buf);
+        let relative = match link_target {
+            Some(_) => self.place(name, false, None)?,
+            None => self.placed(name, false, None)?,
+        };
+        let relative = match relative {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        if let Some(link_target) = link_target {
+            if !self.symlinks.add(&relative, &link_target) {
+                return self.refuse(name, UnsafeReason::SymlinkEscape);
+            }
+        }
+        let created = self.root.hardlink(&target, &relative);
+        self.written(name, created).map(|_| ())
+    }
+
+    // A FIFO, device node or socket entry, with the device's major and minor
+    // number when the archive records one. Device nodes are only created
+    // when they are allowed and we run as root.
+    pub fn special(
+        &mut self,
+        name: &Path,
+        kind: SpecialKind,
+        device: Option<(u32, u32)>,
+        meta: EntryMeta,
+    ) -> Result<(), ArchiveError> {
+        self.budget.add_entry()?;
+        let refused = match kind {
+            SpecialKind::Fifo => None,
+            SpecialKind::Socket => Some("sockets can't be recreated"),
+            _ if device.is_none() => Some("the archive has no device number for it"),
+            _ if !self.allow_devices => Some("device nodes are not allowed"),
+            _ if !is_root() => Some("creating device nodes needs root"),
+            _ => None,
+        };
+        match (self.special_files, refused) {
+            (SpecialFilePolicy::Fail, _) => {
+                return Err(ArchiveError::SpecialFile {
+                    path: name.to_path_buf(),
+                    kind,
+                })
+            }
+            (SpecialFilePolicy::Skip, _) => {
+                self.warn(format!("skipped {} {}", kind, name.display()));
+                return Ok(());
+            }
+            (SpecialFilePolicy::Store, Some(why)) => {
+                self.warn(format!("skipped {} {}: {}", kind, name.display(), why));
+                return Ok(());
+            }
+            (SpecialFilePolicy::Store, None) => {}
+        }
+        let meta = self.settle(meta, false);
+        let relative = match self.placed(name, false, meta.mtime)? {
+            Some(relative) => relative,
+            None => return Ok(()),
+        };
+        let created = self.root.special(
+            &relative,
+            kind,
+            device.unwrap_or_default(),
+            meta.mode.unwrap_or(0o644),
+        );
+        self.written(name, created).map(|_| ())
+    }
+
+    pub fn warn(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+
+    // Restores directory modes and mtimes deepest first, then hands back the
+    // warnings collected along the way.
+    pub fn finish(mut self) -> Vec<String> {
+        self.directories.sort_by(|a, b| b.0.cmp(&a.0));
+        for (relative, meta) in &self.directories {
+            match self.root.finish_dir(relative, meta, &mut self.warnings) {
+                Ok(()) => {}
+                Err(WriteError::SymlinkInPath) => self.warnings.push(format!(
+                    "left {} alone: {}",
+                    relative.display(),
+                    UnsafeReason::SymlinkInPath
+                )),
+                Err(WriteError::Io(err)) => {
+                    panic!("restore {} failed: {}", relative.display(), err)
+                }
+            }
+        }
+        self.finished = true;
+        std::mem::take(&mut self.warnings)
+    }
+
+    // The mode and owner an entry ends up with once the permission options
+    // have had their say. Directories without a stored mode keep the one
+    // they were created with.
+    fn settle(&self, mut meta: EntryMeta, is_dir: bool) -> EntryMeta {
+        let mode = match (self.permissions.no_same_permissions, meta.mode) {
+            (true, _) if is_dir => Some(0o777),
+            (true, _) => Some(0o666),
+            (false, Some(mode)) => Some(mode),
+            (false, None) => (!is_dir).then_some(0o644),
+        };
+        let kept = if self.permissions.special_bits {
+            0o7777
+        } else {
+            0o777
+        };
+        meta.mode = mode.map(|mode| mode & kept & !self.umask);
+        meta.owner = match meta.owner {
+            Some((uid, gid)) if self.permissions.numeric_owner => Some((
+                map_id(&self.permissions.uid_map, uid),
+                map_id(&self.permissions.gid_map, gid),
+            )),
+            _ => None,
+        };
+        meta
+    }
+
+    // The entry name below the root, None when the entry is skipped, names the
+    // root itself or is stripped or transformed away entirely.
+    fn relative(&mut self, name: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+        match normalize(name).and_then(|relative| self.rename(relative)) {
+            Ok(relative) => Ok(relative),
+            Err(reason) => self.reject(name, reason),
+        }
+    }
+
+    // Drops the leading components --strip-components asks for, then runs
+    // the transform rules, whose result has to be as safe as the name was.
+    // None when nothing is left.
+    fn rename(&self, relative: PathBuf) -> Result<Option<PathBuf>, UnsafeReason> {
+        let stripped: PathBuf = relative.components().skip(self.strip_components).collect();
+        let renamed = normalize(&transform::apply(&self.transforms, &stripped))?;
+        Ok((!renamed.as_os_str().is_empty()).then_some(renamed))
+    }
+
+    // Where an entry other than a symlink goes, see place(). Replacing a
+    // symlink changes where the links resolved through it lead, so they are
+    // checked again.
+    fn placed(
+        &mut self,
+        name: &Path,
+        is_dir: bool,
+        mtime: Option<FileTime>,
+    ) -> Result<Option<PathBuf>, ArchiveError> {
+        match self.place(name, is_dir, mtime)? {
+            Some(relative) if !self.symlinks.replace(&relative) => {
+                self.reject(name, UnsafeReason::SymlinkEscape)
+            }
+            relative => Ok(relative),
+        }
+    }
+
+    // Where the entry goes once the conflict policy has looked at whatever
+    // already has its name, None when it is left out.
+    fn place(
+        &mut self,
+        name: &Path,
+        is_dir: bool,
+        mtime: Option<FileTime>,
+    ) -> Result<Option<PathBuf>, ArchiveError> {
+        let relative = match self.relative(name)? {
+            Some(relative) => relative,
+            None => return Ok(None),
+        };
+        let existing = self.root.existing(&relative);
+        let existing = match self.written(name, existing)? {
+            Some(Some(existing)) => existing,
+            Some(None) => return Ok(Some(relative)),
+            None => return Ok(None),
+        };
+        match conflict::decide(self.conflicts, existing, is_dir, mtime) {
+            Decision::Write => Ok(Some(relative)),
+            Decision::Skip => {
+                self.warn(conflict::skip_warning(name));
+                Ok(None)
+            }
+            Decision::Rename => Ok(Some(self.free_name(&relative))),
+            Decision::Fail => Err(ArchiveError::Conflict {
+                path: name.to_path_buf(),
+            }),
+        }
+    }
+
+    fn free_name(&mut self, relative: &Path) -> PathBuf {
+        let file_name = relative.file_name().unwrap_or_default();
+        let mut number = 1;
+        loop {
+            let candidate = relative.with_file_name(conflict::numbered_name(file_name, number));
+            if !matches!(self.root.existing(&candidate), Ok(Some(_))) {
+                return candidate;
+            }
+            number += 1;
+        }
+    }
+
+    fn written<T>(
+        &mut self,
+        name: &Path,
+        result: Result<T, WriteError>,
+    ) -> Result<Option<T>, ArchiveError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(WriteError::SymlinkInPath) => self.reject(name, UnsafeReason::SymlinkInPath),
+            Err(WriteError::Io(err)) => panic!("extract {} failed: {}", name.display(), err),
+        }
+    }
+
+    fn refuse(&mut self, name: &Path, reason: UnsafeReason) -> Result<(), ArchiveError> {
+        self.reject::<()>(name, reason).map(|_| ())
+    }
+
+    fn reject<T>(&mut self, name: &Path, reason: UnsafeReason) -> Result<Option<T>, ArchiveError> {
+        match self.policy {
+            UnsafeEntryPolicy::Error => Err(ArchiveError::UnsafeEntry {
+                path: name.to_path_buf(),
+                reason,
+            }),
+            UnsafeEntryPolicy::Skip => {
+                self.warn(format!(
+                    "skipped unsafe archive entry {}: {}",
+                    name.display(),
+                    reason
+                ));
+                Ok(None)
+            }
+        }
+    }
+}
+
+// An Extractor dropped before finish() means the archive failed part way (an
+// error return or a panic unwinding), so whatever it added is taken out again.
+impl Drop for Extractor {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.root.rollback();
+        }
+    }
+}
+
+fn map_id(map: &[(u32, u32)], id: u64) -> u64 {
+    map.iter()
+        .find(|(from, _)| u64::from(*from) == id)
+        .map_or(id, |(_, to)| u64::from(*to))
+}
+
+// Owner first, as chown clears setuid and setgid, then the mode, then the
+// extended attributes, whose ACLs refine the mode. An owner the filesystem
+// won't take is reported and the entry kept without it.
+fn restore_metadata(
+    file: &File,
+    name: &Path,
+    meta: &EntryMeta,
+    warnings: &mut Vec<String>,
+) -> io::Result<()> {
+    if let Some((uid, gid)) = meta.owner {
+        if let Err(err) = chown(file, uid, gid) {
+            warnings.push(format!(
+                "could not restore owner of {}: {}",
+                name.display(),
+                err
+            ));
+        }
+    }
+    if let Some(mode) = meta.mode {
+        set_mode(file, mode)?;
+    }
+    restore_xattrs(file, name, meta, warnings);
+    Ok(())
+}
+
+// An attribute the filesystem or our privileges won't take (security.* and
+// trusted.* usually need root) is reported and the entry kept without it.
+fn restore_xattrs(file: &File, name: &Path, meta: &EntryMeta, warnings: &mut Vec<String>) {
+    for (attribute, value) in &meta.xattrs {
+        if let Err(err) = xattr::set(file, attribute, value) {
+            warnings.push(format!(
+                "could not restore {} on {}: {}",
+                attribute,
+                name.display(),
+                err
+            ));
+        }
+    }
+}
+
+fn normalize(name: &Path) -> Result<PathBuf, UnsafeReason> {
+    let mut relative = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return Err(UnsafeReason::AbsolutePath),
+            Component::ParentDir => return Err(UnsafeReason::PathTraversal),
+            Component::CurDir => {}
+            Component::Normal(part) => relative.push(part),
+        }
+    }
+    Ok(relative)
+}
+
+// Resolves `..` against the preceding components, None if it climbs above the start.
+fn normalize_lexically(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => normalized.push(part),
+        }
+    }
+    Some(normalized)
+}
@@ -0,0 +1,224 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+
+use super::{EntryMeta, WriteError};
+use crate::modules::conflict::Existing;
+use crate::modules::error::SpecialKind;
+use crate::modules::staging;
+
+// Without *at() calls each component is checked with symlink_metadata before
+// it is used. That still refuses links the archive planted itself, it just
+// can't close the window against a concurrent swap.
+pub(super) struct Root {
+    path: PathBuf,
+    created_root: bool,
+    created: Vec<PathBuf>,
+}
+
+pub(super) struct NewFile {
+    pub file: File,
+    temp: PathBuf,
+    path: PathBuf,
+    relative: PathBuf,
+    committed: bool,
+}
+
+impl Drop for NewFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+impl Root {
+    pub fn open(path: &Path) -> io::Result<Root> {
+        let created_root = fs::symlink_metadata(path).is_err();
+        fs::create_dir_all(path)?;
+        Ok(Root {
+            path: path.to_path_buf(),
+            created_root,
+            created: Vec::new(),
+        })
+    }
+
+    pub fn create_dir(&mut self, relative: &Path) -> Result<(), WriteError> {
+        let path = self.parent(relative, true)?;
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => return Ok(()),
+            Ok(_) => fs::remove_file(&path)?,
+            Err(_) => {}
+        }
+        fs::create_dir(&path)?;
+        self.created.push(relative.to_path_buf());
+        Ok(())
+    }
+
+    pub fn create_file(&mut self, relative: &Path) -> Result<NewFile, WriteError> {
+        let path = self.parent(relative, true)?;
+        let temp = path.with_file_name(staging::temp_name(path.file_name().unwrap_or_default()));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        Ok(NewFile {
+            file,
+            temp,
+            path,
+            relative: relative.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    pub fn commit(&mut self, mut new_file: NewFile) -> Result<(), WriteError> {
+        let existed = remove_non_dir(&new_file.path)?;
+        fs::rename(&new_file.temp, &new_file.path)?;
+        new_file.committed = true;
+        if !existed {
+            self.created.push(std::mem::take(&mut new_file.relative));
+        }
+        Ok(())
+    }
+
+    // Without symlink support keep the target as the file content, like zip does.
+    pub fn symlink(&mut self, target: &Path, relative: &Path) -> Result<(), WriteError> {
+        let mut new_file = self.create_file(relative)?;
+        new_file
+            .file
+            .write_all(target.to_string_lossy().as_bytes())?;
+        self.commit(new_file)
+    }
+
+    pub fn hardlink(&mut self, target: &Path, relative: &Path) -> Result<(), WriteError> {
+        let target = self.parent(target, false)?;
+        let path = self.parent(relative, true)?;
+        let existed = remove_non_dir(&path)?;
+        fs::hard_link(target, path)?;
+        if !existed {
+            self.created.push(relative.to_path_buf());
+        }
+        Ok(())
+    }
+
+    pub fn special(
+        &mut self,
+        _relative: &Path,
+        _kind: SpecialKind,
+        _device: (u32, u32),
+        _mode: u32,
+    ) -> Result<(), WriteError> {
+        Err(io::Error::from(io::ErrorKind::Unsupported).into())
+    }
+
+    pub fn existing(&mut self, relative: &Path) -> Result<Option<Existing>, WriteError> {
+        let path = match self.parent(relative, false) {
+            Ok(path) => path,
+            Err(WriteError::Io(err)) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.is_dir() => Ok(Some(Existing::Dir)),
+            Ok(metadata) => Ok(Some(Existing::Other {
+                mtime: FileTime::from_last_modification_time(&metadata),
+            })),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn finish_dir(
+        &mut self,
+        relative: &Path,
+        meta: &EntryMeta,
+        warnings: &mut Vec<String>,
+    ) -> Result<(), WriteError> {
+        let path = self.parent(relative, false)?;
+        // Directories can't be opened as files everywhere, and no platform
+        // this builds for restores extended attributes anyway.
+        for (attribute, _) in &meta.xattrs {
+            warnings.push(format!(
+                "could not restore {} on {}: not supported on this platform",
+                attribute,
+                relative.display()
+            ));
+        }
+        if let Some(mtime) = meta.mtime {
+            filetime::set_file_mtime(path, mtime)?;
+        }
+        Ok(())
+    }
+
+    pub fn rollback(&mut self) {
+        while let Some(relative) = self.created.pop() {
+            let path = self.path.join(relative);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => {
+                    let _ = fs::remove_dir(&path);
+                }
+                Ok(_) => {
+                    let _ = fs::remove_file(&path);
+                }
+                Err(_) => {}
+            }
+        }
+        if self.created_root {
+            let _ = fs::remove_dir(&self.path);
+        }
+    }
+
+    fn parent(&mut self, relative: &Path, create: bool) -> Result<PathBuf, WriteError> {
+        let mut path = self.path.clone();
+        let mut prefix = PathBuf::new();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            path.push(component);
+            if components.peek().is_none() {
+                break;
+            }
+            prefix.push(component);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    return Err(WriteError::SymlinkInPath)
+                }
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound && create => {
+                    fs::create_dir(&path)?;
+                    self.created.push(prefix.clone());
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(path)
+    }
+}
+
+// Owners are never restored here (is_root says no), and modes stay whatever
+// the platform gives new files.
+pub(super) fn chown(_file: &File, _uid: u64, _gid: u64) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+pub(super) fn set_mode(_file: &File, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+pub(super) fn is_root() -> bool {
+    false
+}
+
+pub(super) fn process_umask() -> u32 {
+    0o022
+}
+
+fn remove_non_dir(path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(io::Error::from(io::ErrorKind::AlreadyExists)),
+        Ok(_) => fs::remove_file(path).map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
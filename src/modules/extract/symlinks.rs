@@ -0,0 +1,117 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::{Component, Path, PathBuf};
+
+// Linux gives up with ELOOP after this many links in one lookup.
+const MAX_HOPS: usize = 40;
+
+// The symlinks an Extractor has created so far. A target is only safe once
+// it is followed through them: `a/l -> ..` and `b -> a/l/..` each look
+// harmless on their own, yet `b` ends up above the root. Every link records
+// the paths its resolution passed, so a link created later at one of them
+// (or a link replaced by something else) re-checks the links that went
+// through it, whatever order the archive has them in.
+#[derive(Default)]
+pub(super) struct Symlinks {
+    targets: HashMap<PathBuf, PathBuf>,
+    // path below the root -> links whose resolution passed it
+    passed: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl Symlinks {
+    // Records a link at `relative`, false (recording nothing) when it or a
+    // link resolving through it would end up outside the root.
+    pub fn add(&mut self, relative: &Path, target: &Path) -> bool {
+        let replaced = self
+            .targets
+            .insert(relative.to_path_buf(), target.to_path_buf());
+        if self.recheck(relative) {
+            return true;
+        }
+        match replaced {
+            Some(replaced) => self.targets.insert(relative.to_path_buf(), replaced),
+            None => self.targets.remove(relative),
+        };
+        false
+    }
+
+    // Where the link at `relative` points, if there is one.
+    pub fn target(&self, relative: &Path) -> Option<&Path> {
+        self.targets.get(relative).map(PathBuf::as_path)
+    }
+
+    // Something that isn't a link takes the place of whatever is at
+    // `relative`; false (leaving the link recorded) when a link resolving
+    // through the old one would then end up outside the root.
+    pub fn replace(&mut self, relative: &Path) -> bool {
+        let replaced = match self.targets.remove(relative) {
+            Some(replaced) => replaced,
+            None => return true,
+        };
+        if self.recheck(relative) {
+            return true;
+        }
+        self.targets.insert(relative.to_path_buf(), replaced);
+        false
+    }
+
+    // Resolves the link at `relative`, if there is one, and every link that
+    // passed it before.
+    fn recheck(&mut self, relative: &Path) -> bool {
+        let mut links: Vec<PathBuf> = self.passed.get(relative).cloned().unwrap_or_default();
+        links.push(relative.to_path_buf());
+        // links replaced since they passed are gone
+        links.retain(|link| self.targets.contains_key(link));
+        let mut passed = Vec::new();
+        for link in &links {
+            let mut path = Vec::new();
+            if !self.resolve(link, &mut path) {
+                return false;
+            }
+            passed.push((link, path));
+        }
+        for (link, path) in passed {
+            for visited in path {
+                let links = self.passed.entry(visited).or_default();
+                if !links.contains(link) {
+                    links.push(link.clone());
+                }
+            }
+        }
+        true
+    }
+
+    // Follows the link at `link` component by component, through the links
+    // recorded so far, pushing every path it passes onto `path`. False when
+    // it climbs above the root or loops.
+    fn resolve(&self, link: &Path, path: &mut Vec<PathBuf>) -> bool {
+        let mut current = link.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut pending: VecDeque<Component> = self.targets[link].components().collect();
+        let mut hops = 0;
+        while let Some(component) = pending.pop_front() {
+            match component {
+                Component::Prefix(_) | Component::RootDir => return false,
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !current.pop() {
+                        return false;
+                    }
+                }
+                Component::Normal(part) => {
+                    current.push(part);
+                    path.push(current.clone());
+                    if let Some(target) = self.targets.get(&current) {
+                        hops += 1;
+                        if hops > MAX_HOPS {
+                            return false;
+                        }
+                        current.pop();
+                        for component in target.components().rev() {
+                            pending.push_front(component);
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,365 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use filetime::FileTime;
+
+use super::{EntryMeta, WriteError};
+use crate::modules::conflict::Existing;
+use crate::modules::error::SpecialKind;
+use crate::modules::staging;
+
+const DIR_FLAGS: libc::c_int =
+    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+const FILE_FLAGS: libc::c_int =
+    libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+
+// The destination directory, held open so every write is an *at() call
+// relative to it instead of a path the kernel resolves from scratch.
+pub(super) struct Root {
+    fd: OwnedFd,
+    // the destination itself, when this run created it
+    created_root: Option<PathBuf>,
+    // everything this run added below the root, in creation order
+    created: Vec<PathBuf>,
+}
+
+// A file entry being written under a temporary name next to its final one.
+// commit() renames it into place; dropping it removes the temporary.
+pub(super) struct NewFile {
+    pub file: File,
+    dir: OwnedFd,
+    temp: CString,
+    name: CString,
+    relative: PathBuf,
+    committed: bool,
+}
+
+impl Drop for NewFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = unlink_at(&self.dir, &self.temp);
+        }
+    }
+}
+
+impl Root {
+    pub fn open(path: &Path) -> io::Result<Root> {
+        let created_root = fs::symlink_metadata(path)
+            .is_err()
+            .then(|| path.to_path_buf());
+        fs::create_dir_all(path)?;
+        let dir = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_CLOEXEC)
+            .open(path)?;
+        Ok(Root {
+            fd: dir.into(),
+            created_root,
+            created: Vec::new(),
+        })
+    }
+
+    pub fn create_dir(&mut self, relative: &Path) -> Result<(), WriteError> {
+        let (dir, name) = self.parent(relative, true)?;
+        match make_dir_at(&dir, &name) {
+            Err(err) if err.raw_os_error() == Some(libc::EEXIST) => {
+                if file_type_at(&dir, &name)? == Some(libc::S_IFDIR) {
+                    return Ok(());
+                }
+                unlink_at(&dir, &name)?;
+                make_dir_at(&dir, &name)?;
+            }
+            result => result?,
+        }
+        self.created.push(relative.to_path_buf());
+        Ok(())
+    }
+
+    // The temporary is private until restore_metadata gives it its mode.
+    pub fn create_file(&mut self, relative: &Path) -> Result<NewFile, WriteError> {
+        let (dir, name) = self.parent(relative, true)?;
+        let temp = c_name(&staging::temp_name(OsStr::from_bytes(name.as_bytes())))?;
+        let fd = cvt(unsafe {
+            libc::openat(
+                dir.as_raw_fd(),
+                temp.as_ptr(),
+                FILE_FLAGS,
+                0o600 as libc::c_uint,
+            )
+        })?;
+        Ok(NewFile {
+            file: File::from(unsafe { OwnedFd::from_raw_fd(fd) }),
+            dir,
+            temp,
+            name,
+            relative: relative.to_path_buf(),
+            committed: false,
+        })
+    }
+
+    // renameat replaces a file or symlink at the final name in one step, so
+    // readers see either the old entry or the complete new one.
+    pub fn commit(&mut self, mut new_file: NewFile) -> Result<(), WriteError> {
+        let existed = match file_type_at(&new_file.dir, &new_file.name)? {
+            Some(libc::S_IFDIR) => return Err(io::Error::from_raw_os_error(libc::EISDIR).into()),
+            existed => existed.is_some(),
+        };
+        let dir = new_file.dir.as_raw_fd();
+        cvt(unsafe { libc::renameat(dir, new_file.temp.as_ptr(), dir, new_file.name.as_ptr()) })?;
+        new_file.committed = true;
+        if !existed {
+            self.created.push(std::mem::take(&mut new_file.relative));
+        }
+        Ok(())
+    }
+
+    pub fn symlink(&mut self, target: &Path, relative: &Path) -> Result<(), WriteError> {
+        let (dir, name) = self.parent(relative, true)?;
+        let existed = remove_non_dir(&dir, &name)?;
+        let target = c_name(target.as_os_str())?;
+        cvt(unsafe { libc::symlinkat(target.as_ptr(), dir.as_raw_fd(), name.as_ptr()) })?;
+        self.record(relative, existed);
+        Ok(())
+    }
+
+    pub fn hardlink(&mut self, target: &Path, relative: &Path) -> Result<(), WriteError> {
+        let (target_dir, target_name) = self.parent(target, false)?;
+        let (dir, name) = self.parent(relative, true)?;
+        let existed = remove_non_dir(&dir, &name)?;
+        // No AT_SYMLINK_FOLLOW: linking to a symlink links the symlink itself.
+        cvt(unsafe {
+            libc::linkat(
+                target_dir.as_raw_fd(),
+                target_name.as_ptr(),
+                dir.as_raw_fd(),
+                name.as_ptr(),
+                0,
+            )
+        })?;
+        self.record(relative, existed);
+        Ok(())
+    }
+
+    pub fn special(
+        &mut self,
+        relative: &Path,
+        kind: SpecialKind,
+        (major, minor): (u32, u32),
+        mode: u32,
+    ) -> Result<(), WriteError> {
+        let file_type = match kind {
+            SpecialKind::Fifo => libc::S_IFIFO,
+            SpecialKind::CharDevice => libc::S_IFCHR,
+            SpecialKind::BlockDevice => libc::S_IFBLK,
+            SpecialKind::Socket => libc::S_IFSOCK,
+        };
+        let (dir, name) = self.parent(relative, true)?;
+        let existed = remove_non_dir(&dir, &name)?;
+        let mode = file_type | mode as libc::mode_t;
+        let device = libc::makedev(major as _, minor as _);
+        cvt(unsafe { libc::mknodat(dir.as_raw_fd(), name.as_ptr(), mode, device) })?;
+        self.record(relative, existed);
+        Ok(())
+    }
+
+    pub fn existing(&mut self, relative: &Path) -> Result<Option<Existing>, WriteError> {
+        let (dir, name) = match self.parent(relative, false) {
+            Ok(found) => found,
+            Err(WriteError::Io(err)) if err.raw_os_error() == Some(libc::ENOENT) => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        // time_t is only 32 bits on some targets.
+        #[allow(clippy::unnecessary_cast)]
+        Ok(stat_at(&dir, &name)?.map(|stat| {
+            if stat.st_mode & libc::S_IFMT == libc::S_IFDIR {
+                Existing::Dir
+            } else {
+                Existing::Other {
+                    mtime: FileTime::from_unix_time(
+                        stat.st_mtime as i64,
+                        stat.st_mtime_nsec as u32,
+                    ),
+                }
+            }
+        }))
+    }
+
+    pub fn finish_dir(
+        &mut self,
+        relative: &Path,
+        meta: &EntryMeta,
+        warnings: &mut Vec<String>,
+    ) -> Result<(), WriteError> {
+        let (dir, name) = self.parent(relative, false)?;
+        let dir = File::from(open_dir_at(&dir, &name)?);
+        super::restore_metadata(&dir, relative, meta, warnings)?;
+        if let Some(mtime) = meta.mtime {
+            filetime::set_file_handle_times(&dir, None, Some(mtime))?;
+        }
+        Ok(())
+    }
+
+    // Removes what this run added, newest first, so an aborted extraction
+    // leaves the destination as it found it (bar files it overwrote).
+    pub fn rollback(&mut self) {
+        while let Some(relative) = self.created.pop() {
+            if let Ok((dir, name)) = self.parent(&relative, false) {
+                let flags = match file_type_at(&dir, &name) {
+                    Ok(Some(libc::S_IFDIR)) => libc::AT_REMOVEDIR,
+                    _ => 0,
+                };
+                unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) };
+            }
+        }
+        if let Some(root) = self.created_root.take() {
+            let _ = fs::remove_dir(root);
+        }
+    }
+
+    fn record(&mut self, relative: &Path, existed: bool) {
+        if !existed {
+            self.created.push(relative.to_path_buf());
+        }
+    }
+
+    // Opens every directory on the way to `relative` with O_NOFOLLOW, creating
+    // missing ones when asked, and returns it with the final name.
+    fn parent(&mut self, relative: &Path, create: bool) -> Result<(OwnedFd, CString), WriteError> {
+        let mut components = relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => Ok(name),
+                _ => Err(io::Error::from(io::ErrorKind::InvalidInput)),
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let last = components
+            .pop()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+
+        let mut dir = self.fd.try_clone()?;
+        let mut prefix = PathBuf::new();
+        for component in components {
+            let name = c_name(component)?;
+            prefix.push(component);
+            if create {
+                match make_dir_at(&dir, &name) {
+                    Ok(()) => self.created.push(prefix.clone()),
+                    Err(err) if err.raw_os_error() == Some(libc::EEXIST) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            dir = open_dir_at(&dir, &name)?;
+        }
+        Ok((dir, c_name(last)?))
+    }
+}
+
+pub(super) fn chown(file: &File, uid: u64, gid: u64) -> io::Result<()> {
+    let out_of_range = |id: u64| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("id {} is out of range", id),
+        )
+    };
+    let uid = u32::try_from(uid).map_err(|_| out_of_range(uid))?;
+    let gid = u32::try_from(gid).map_err(|_| out_of_range(gid))?;
+    std::os::unix::fs::fchown(file, Some(uid), Some(gid))
+}
+
+pub(super) fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    file.set_permissions(fs::Permissions::from_mode(mode))
+}
+
+pub(super) fn is_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+// Linux reports the umask in /proc without changing it; elsewhere it can only
+// be read by setting it and putting it back.
+pub(super) fn process_umask() -> u32 {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    if let Some(umask) = fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            let line = status.lines().find(|line| line.starts_with("Umask:"))?;
+            u32::from_str_radix(line["Umask:".len()..].trim(), 8).ok()
+        })
+    {
+        return umask;
+    }
+    let umask = unsafe { libc::umask(0o022) };
+    unsafe { libc::umask(umask) };
+    umask as u32
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn c_name(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))
+}
+
+fn open_dir_at(dir: &OwnedFd, name: &CStr) -> Result<OwnedFd, WriteError> {
+    match cvt(unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), DIR_FLAGS) }) {
+        Ok(fd) => Ok(unsafe { OwnedFd::from_raw_fd(fd) }),
+        // O_NOFOLLOW reports a symlink as ELOOP (or ENOTDIR on some systems).
+        Err(err) => match file_type_at(dir, name)? {
+            Some(libc::S_IFLNK) => Err(WriteError::SymlinkInPath),
+            _ => Err(err.into()),
+        },
+    }
+}
+
+fn make_dir_at(dir: &OwnedFd, name: &CStr) -> io::Result<()> {
+    cvt(unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) }).map(|_| ())
+}
+
+fn unlink_at(dir: &OwnedFd, name: &CStr) -> io::Result<()> {
+    cvt(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), 0) }).map(|_| ())
+}
+
+fn file_type_at(dir: &OwnedFd, name: &CStr) -> io::Result<Option<libc::mode_t>> {
+    Ok(stat_at(dir, name)?.map(|stat| stat.st_mode & libc::S_IFMT))
+}
+
+fn stat_at(dir: &OwnedFd, name: &CStr) -> io::Result<Option<libc::stat>> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    let ret = unsafe {
+        libc::fstatat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            stat.as_mut_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    match cvt(ret) {
+        Ok(_) => Ok(Some(unsafe { stat.assume_init() })),
+        Err(err) if err.raw_os_error() == Some(libc::ENOENT) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+// Clears the way for a new entry: files and symlinks are replaced, an
+// existing directory is an error like it is for tar. Returns whether
+// something was there.
+fn remove_non_dir(dir: &OwnedFd, name: &CStr) -> io::Result<bool> {
+    match file_type_at(dir, name)? {
+        None => Ok(false),
+        Some(libc::S_IFDIR) => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        Some(_) => unlink_at(dir, name).map(|_| true),
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use walkdir::DirEntry;
+
+use crate::modules::options::FilterOptions;
+use crate::modules::pattern::{self, Pattern};
+
+// Bookkeeping directories of the version control systems tar's
+// --exclude-vcs knows about.
+const VCS_DIRS: [&str; 7] = [".git", ".hg", ".svn", ".bzr", "_darcs", "CVS", "RCS"];
+
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+// Decides which walked entries make it into an archive. Meant for
+// WalkDir::filter_entry, so a rejected directory is never descended into.
+pub struct Filter<'a> {
+    root: PathBuf,
+    options: &'a FilterOptions,
+    exclude: Vec<Pattern>,
+    include: Vec<Pattern>,
+    // ignore files by the directory (relative to root) they were found in
+    ignores: HashMap<PathBuf, Gitignore>,
+}
+
+impl<'a> Filter<'a> {
+    pub fn new(root: &Path, options: &'a FilterOptions) -> io::Result<Filter<'a>> {
+        let mut exclude = options.exclude.clone();
+        for path in &options.exclude_from {
+            exclude.extend(read_patterns(path)?);
+        }
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidInput, err);
+        Ok(Filter {
+            root: root.to_path_buf(),
+            options,
+            exclude: pattern::compile(&exclude).map_err(invalid)?,
+            include: pattern::compile(&options.include).map_err(invalid)?,
+            ignores: HashMap::new(),
+        })
+    }
+
+    pub fn allows(&mut self, entry: &DirEntry) -> bool {
+        let relative = match entry.path().strip_prefix(&self.root) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => return true,
+        };
+        let is_dir = entry.file_type().is_dir();
+        if entry.depth() > 0 && !self.keeps(&relative, is_dir) {
+            return false;
+        }
+        if is_dir && self.options.gitignore {
+            self.load_ignores(entry.path(), relative);
+        }
+        true
+    }
+
+    fn keeps(&self, relative: &Path, is_dir: bool) -> bool {
+        let name = relative
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+        if self.options.exclude_hidden && name.starts_with('.') {
+            return false;
+        }
+        if self.options.exclude_vcs && is_dir && VCS_DIRS.contains(&name.as_ref()) {
+            return false;
+        }
+        if self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.matches_path(relative, is_dir))
+        {
+            return false;
+        }
+        if self.options.gitignore && self.ignored(relative, is_dir) {
+            return false;
+        }
+        is_dir
+            || self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.matches_path(relative, is_dir))
+    }
+
+    // Rules of deeper ignore files come later and win, as in git.
+    fn ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for dir in relative
+            .ancestors()
+            .skip(1)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            let gitignore = match self.ignores.get(dir) {
+                Some(gitignore) => gitignore,
+                None => continue,
+            };
+            let inside = relative.strip_prefix(dir).unwrap_or(relative);
+            match gitignore.matched(inside, is_dir) {
+                Match::Ignore(_) => ignored = true,
+                Match::Whitelist(_) => ignored = false,
+                Match::None => {}
+            }
+        }
+        ignored
+    }
+
+    // Lines that don't parse are left out, as git does.
+    fn load_ignores(&mut self, dir: &Path, relative: PathBuf) {
+        let mut builder = GitignoreBuilder::new(dir);
+        for name in IGNORE_FILES {
+            if let Ok(contents) = fs::read_to_string(dir.join(name)) {
+                for line in contents.lines() {
+                    let _ = builder.add_line(None, line);
+                }
+            }
+        }
+        if let Ok(gitignore) = builder.build() {
+            if !gitignore.is_empty() {
+                self.ignores.insert(relative, gitignore);
+            }
+        }
+    }
+}
+
+// One pattern per line; blank lines and `#` comments are skipped. A line
+// that doesn't parse is reported by its number rather than its text.
+pub fn read_patterns(path: &Path) -> io::Result<Vec<String>> {
+    let mut patterns = Vec::new();
+    for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(err) = Pattern::new(line) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("line {}: {}", index + 1, err.kind()),
+            ));
+        }
+        patterns.push(line.to_string());
+    }
+    Ok(patterns)
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use walkdir::WalkDir;
+
+use crate::modules::error::{ArchiveError, Limit};
+use crate::modules::options::Limits;
+
+// Tiny inputs legitimately expand a lot (a few bytes of gzip for a page of
+// zeros), so the ratio only counts once the output is past this size.
+const RATIO_GRACE_BYTES: u64 = 1 << 20;
+
+const COPY_BUFFER_SIZE: usize = 64 << 10;
+
+// Running totals for one layer, checked as data is written instead of after
+// the fact so a bomb stops at the limit rather than when the disk is full.
+pub struct Budget {
+    limits: Limits,
+    input_size: u64,
+    total_bytes: u64,
+    entries: u64,
+}
+
+impl Budget {
+    pub fn new(limits: &Limits, src_path: &Path) -> Self {
+        Budget {
+            limits: *limits,
+            input_size: fs::metadata(src_path).map(|m| m.len()).unwrap_or(0),
+            total_bytes: 0,
+            entries: 0,
+        }
+    }
+
+    pub fn add_entry(&mut self) -> Result<(), ArchiveError> {
+        self.entries += 1;
+        match self.limits.max_entries {
+            Some(max) if self.entries > max => {
+                Err(ArchiveError::LimitExceeded(Limit::Entries(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // io::copy that charges every chunk against the limits; `label` names the
+    // operation in the panic message for a plain read or write failure.
+    pub fn copy(
+        &mut self,
+        label: &str,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<u64, ArchiveError> {
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut entry_bytes = 0u64;
+        loop {
+            let n = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(entry_bytes),
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => panic!("{} failed: {}", label, err),
+            };
+            entry_bytes += n as u64;
+            self.total_bytes += n as u64;
+            self.check(entry_bytes)?;
+            writer
+                .write_all(&buffer[..n])
+                .unwrap_or_else(|err| panic!("{} failed: {}", label, err));
+        }
+    }
+
+    fn check(&self, entry_bytes: u64) -> Result<(), ArchiveError> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_entry_bytes.filter(|max| entry_bytes > *max) {
+            return Err(ArchiveError::LimitExceeded(Limit::EntryBytes(max)));
+        }
+        if let Some(max) = limits.max_total_bytes.filter(|max| self.total_bytes > *max) {
+            return Err(ArchiveError::LimitExceeded(Limit::TotalBytes(max)));
+        }
+        if let Some(max) = limits.max_ratio {
+            let allowed = self.input_size.max(1).saturating_mul(max);
+            if self.total_bytes > RATIO_GRACE_BYTES && self.total_bytes > allowed {
+                return Err(ArchiveError::LimitExceeded(Limit::Ratio(max)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// What a recursive extraction has written so far, the outer archive and
+// every nested one unpacked since. Each nested archive only gets what is
+// left of the byte and entry limits, so all of them together stay within
+// the limits the request was given.
+#[derive(Default)]
+pub struct Spent {
+    bytes: u64,
+    entries: u64,
+}
+
+impl Spent {
+    // Adds up everything below `dir`.
+    pub fn add_tree(&mut self, dir: &Path) -> io::Result<()> {
+        for entry in WalkDir::new(dir).min_depth(1) {
+            let entry = entry?;
+            self.entries += 1;
+            if entry.file_type().is_file() {
+                self.bytes += entry.metadata()?.len();
+            }
+        }
+        Ok(())
+    }
+
+    // The limits for the next nested archive, or the one already used up.
+    pub fn remaining(&self, limits: &Limits) -> Result<Limits, Limit> {
+        if let Some(max) = limits.max_total_bytes.filter(|max| self.bytes > *max) {
+            return Err(Limit::TotalBytes(max));
+        }
+        if let Some(max) = limits.max_entries.filter(|max| self.entries > *max) {
+            return Err(Limit::Entries(max));
+        }
+        Ok(Limits {
+            max_total_bytes: limits.max_total_bytes.map(|max| max - self.bytes),
+            max_entries: limits.max_entries.map(|max| max - self.entries),
+            ..*limits
+        })
+    }
+
+    // A limit a nested archive broke with what was left, as the one the
+    // request set.
+    pub fn overall(limit: Limit, limits: &Limits) -> Limit {
+        match (limit, limits.max_total_bytes, limits.max_entries) {
+            (Limit::TotalBytes(_), Some(max), _) => Limit::TotalBytes(max),
+            (Limit::Entries(_), _, Some(max)) => Limit::Entries(max),
+            (limit, _, _) => limit,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use filetime::FileTime;
+
+// Gives dst the permissions of src, its owner when running as root (nobody
+// else may give a file away), and its access and modification times unless
+// `times` is off.
+pub fn copy(src: &Path, dst: &Path, times: bool) -> io::Result<()> {
+    let metadata = fs::metadata(src)?;
+    // chown drops setuid/setgid, so it goes before the mode.
+    #[cfg(unix)]
+    copy_owner(&metadata, dst)?;
+    fs::set_permissions(dst, metadata.permissions())?;
+    if times {
+        filetime::set_file_times(
+            dst,
+            FileTime::from_last_access_time(&metadata),
+            FileTime::from_last_modification_time(&metadata),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_owner(metadata: &fs::Metadata, dst: &Path) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    if unsafe { libc::geteuid() } != 0 {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(dst, Some(metadata.uid()), Some(metadata.gid()))
+}
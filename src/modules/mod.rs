@@ -1,16 +1,43 @@
-mod compression;
+pub(crate) mod compression;
+pub mod conflict;
+pub mod error;
+pub mod extract;
+pub(crate) mod filter;
+pub(crate) mod limits;
+pub(crate) mod metadata;
+pub mod options;
+pub mod pattern;
+pub(crate) mod staging;
+pub mod transform;
+pub(crate) mod xattr;
 
+use std::fs;
 use std::fs::File;
-use std::io::{Error, ErrorKind, Read, Seek};
+use std::io::{Error, ErrorKind, Read};
+use std::path::PathBuf;
+
+use filetime::FileTime;
 
 use crate::contents::enums;
+use crate::modules::compression::gz::Stored;
+use crate::modules::compression::update::Update;
+use crate::modules::conflict::{Decision, Existing};
+use crate::modules::error::ArchiveError;
+use crate::modules::options::{CompressOptions, DecompressOptions};
+use crate::modules::staging::Staged;
 
-fn find_subsequence(source: &[u8], target: &[u8]) -> Option<usize> {
-    source
-        .windows(target.len())
-        .position(|window| window == target)
+#[derive(Debug, Default)]
+pub struct Report {
+    pub warnings: Vec<String>,
+    // where the output ended up: dst_path, unless a stored name or the
+    // conflict policy picked another one
+    pub output_path: PathBuf,
 }
 
+const TAR_MAGIC: &[u8] = b"ustar";
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC_END: usize = TAR_MAGIC_OFFSET + 5;
+
 pub fn get_file_type(file_path: &std::path::PathBuf) -> Result<enums::FileType, std::io::Error> {
     struct CompressMagic {
         magic_number: &'static [u8],
@@ -56,34 +83,21 @@ pub fn get_file_type(file_path: &std::path::PathBuf) -> Result<enums::FileType,
         },
     ];
 
-    let compress_magic_include_list = [CompressMagic {
-        magic_number: &[0x75, 0x73, 0x74, 0x61, 0x72],
-        length: 5,
-        file_type: enums::FileType::Tar,
-    }];
-
-    let mut startswith_buffer = [0u8; 4];
-    let mut file = File::open(file_path).expect("File open failed");
-    match file.read_exact(&mut startswith_buffer) {
-        Ok(_) => (),
-        Err(_) => return Err(Error::from(ErrorKind::Unsupported)),
-    };
+    let mut header = Vec::with_capacity(TAR_MAGIC_END);
+    File::open(file_path)?
+        .take(TAR_MAGIC_END as u64)
+        .read_to_end(&mut header)?;
 
     for compress_magic in compress_magic_startswith_list.iter() {
-        if startswith_buffer.get(..compress_magic.length).unwrap() == compress_magic.magic_number {
+        if header.get(..compress_magic.length) == Some(compress_magic.magic_number) {
             return Ok(compress_magic.file_type);
         }
     }
 
-    file.rewind().expect("Seek 0 failed");
-    let mut include_vec = Vec::new();
-    file.read_to_end(&mut include_vec)
-        .expect("Read file failed");
-
-    for compress_magic in compress_magic_include_list.iter() {
-        if find_subsequence(&include_vec, compress_magic.magic_number).is_some() {
-            return Ok(compress_magic.file_type);
-        }
+    // POSIX ("ustar\0") and GNU ("ustar ") headers both carry it here, in the
+    // first block; plain data that merely contains the word is no tar.
+    if header.get(TAR_MAGIC_OFFSET..) == Some(TAR_MAGIC) {
+        return Ok(enums::FileType::Tar);
     }
 
     Err(Error::from(ErrorKind::Unsupported))
@@ -94,47 +108,151 @@ pub fn compress(
     src_path: &std::path::Path,
     dst_path: &std::path::Path,
 ) {
-    match file_type {
-        enums::FileType::Zip => {
-            compression::zip::compress(src_path, dst_path);
-        }
-        enums::FileType::Tar => {
-            compression::tar::compress(src_path, dst_path);
-        }
+    compress_with_options(file_type, src_path, dst_path, &CompressOptions::default());
+}
+
+// Returns what a container left out, such as special files it can't store.
+pub fn compress_with_options(
+    file_type: enums::FileType,
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &CompressOptions,
+) -> Vec<String> {
+    // Nothing appears at dst_path until the archive is complete.
+    let staged = Staged::next_to(dst_path);
+    let warnings = match file_type {
+        enums::FileType::Zip => compression::zip::compress(src_path, staged.path(), options),
+        enums::FileType::Tar => compression::tar::compress(src_path, staged.path(), options),
         enums::FileType::Bz2 => {
-            compression::bz2::compress(src_path, dst_path);
+            compression::bz2::compress(src_path, staged.path());
+            Vec::new()
         }
         enums::FileType::Gz => {
-            compression::gz::compress(src_path, dst_path);
+            compression::gz::compress(src_path, staged.path(), &options.gzip);
+            Vec::new()
+        }
+        enums::FileType::Tarbz2 => compression::tar_bz2::compress(src_path, staged.path(), options),
+        enums::FileType::Targz => compression::tar_gz::compress(src_path, staged.path(), options),
+        enums::FileType::SevenZ => compression::sevenz::compress(src_path, staged.path(), options),
+        enums::FileType::Xz => {
+            compression::xz::compress(src_path, staged.path());
+            Vec::new()
+        }
+        enums::FileType::Tarxz => compression::tar_xz::compress(src_path, staged.path(), options),
+        enums::FileType::Zst => {
+            compression::zst::compress(src_path, staged.path());
+            Vec::new()
+        }
+        enums::FileType::Tarzst => compression::tar_zst::compress(src_path, staged.path(), options),
+        enums::FileType::Lz4 => {
+            compression::lz4::compress(src_path, staged.path());
+            Vec::new()
+        }
+        enums::FileType::Tarlz4 => compression::tar_lz4::compress(src_path, staged.path(), options),
+    };
+    persist(staged, dst_path);
+    warnings
+}
+
+// Adds what is below every path in `add` to an existing tar, tar.* or zip
+// archive, replacing entries of the same name, and drops the entries named
+// in `delete` with everything below them. Returns what was left out, like
+// compress_with_options.
+pub fn update_with_options(
+    file_type: enums::FileType,
+    archive_path: &std::path::Path,
+    add: &[PathBuf],
+    delete: &[PathBuf],
+    options: &CompressOptions,
+) -> Vec<String> {
+    let label = enums::get_file_type_string(file_type);
+    let dedup_hardlinks = file_type != enums::FileType::Zip;
+    let update = Update::new(archive_path, add, delete, options, dedup_hardlinks, label);
+    let update = match file_type {
+        enums::FileType::Tar => match compression::tar::append(archive_path, update, options) {
+            Ok(warnings) => return warnings,
+            Err(update) => update,
+        },
+        _ => update,
+    };
+    // Anything else is rewritten next to the archive and moved over it.
+    let staged = Staged::next_to(archive_path);
+    let warnings = match file_type {
+        enums::FileType::Tar => {
+            compression::tar::update(archive_path, staged.path(), update, options)
         }
         enums::FileType::Tarbz2 => {
-            compression::tar_bz2::compress(src_path, dst_path);
+            compression::tar_bz2::update(archive_path, staged.path(), update, options)
         }
         enums::FileType::Targz => {
-            compression::tar_gz::compress(src_path, dst_path);
+            compression::tar_gz::update(archive_path, staged.path(), update, options)
         }
-        enums::FileType::SevenZ => {
-            compression::sevenz::compress(src_path, dst_path);
+        enums::FileType::Tarxz => {
+            compression::tar_xz::update(archive_path, staged.path(), update, options)
         }
-        enums::FileType::Xz => {
-            compression::xz::compress(src_path, dst_path);
+        enums::FileType::Tarzst => {
+            compression::tar_zst::update(archive_path, staged.path(), update, options)
         }
-        enums::FileType::Tarxz => {
-            compression::tar_xz::compress(src_path, dst_path);
+        enums::FileType::Tarlz4 => {
+            compression::tar_lz4::update(archive_path, staged.path(), update, options)
         }
-        enums::FileType::Zst => {
-            compression::zst::compress(src_path, dst_path);
+        enums::FileType::Zip => {
+            compression::zip::update(archive_path, staged.path(), update, options)
         }
-        enums::FileType::Tarzst => {
-            compression::tar_zst::compress(src_path, dst_path);
+        _ => panic!("{} archives can't be updated", label),
+    };
+    let permissions = fs::metadata(archive_path)
+        .map(|metadata| metadata.permissions())
+        .unwrap_or_else(|err| panic!("stat {} failed: {}", archive_path.display(), err));
+    fs::set_permissions(staged.path(), permissions)
+        .unwrap_or_else(|err| panic!("{} set permissions failed: {}", label, err));
+    persist(staged, archive_path);
+    warnings
+}
+
+// Decodes `compressed` and compares it with `original` byte for byte, the
+// check before a source file is removed. Only single-stream formats can be
+// read back like this; anything else reports Unsupported.
+pub fn verify_stream(
+    file_type: enums::FileType,
+    compressed: &std::path::Path,
+    original: &std::path::Path,
+) -> Result<bool, Error> {
+    let file = File::open(compressed)?;
+    let mut decoded = match file_type {
+        enums::FileType::Gz => compression::gz::decoder(file),
+        enums::FileType::Bz2 => compression::bz2::decoder(file),
+        enums::FileType::Xz => compression::xz::decoder(file),
+        enums::FileType::Zst => compression::zst::decoder(file),
+        enums::FileType::Lz4 => compression::lz4::decoder(file),
+        _ => return Err(Error::from(ErrorKind::Unsupported)),
+    };
+    let mut original = File::open(original)?;
+    let mut expected = vec![0u8; 64 << 10];
+    let mut actual = vec![0u8; 64 << 10];
+    loop {
+        let n = fill(&mut original, &mut expected)?;
+        if fill(&mut *decoded, &mut actual[..n.max(1)])? != n || expected[..n] != actual[..n] {
+            return Ok(false);
         }
-        enums::FileType::Lz4 => {
-            compression::lz4::compress(src_path, dst_path);
+        if n == 0 {
+            return Ok(true);
         }
-        enums::FileType::Tarlz4 => {
-            compression::tar_lz4::compress(src_path, dst_path);
+    }
+}
+
+// read() until the buffer is full or the stream ends.
+fn fill(reader: &mut dyn Read, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
         }
     }
+    Ok(filled)
 }
 
 pub fn decompress(
@@ -142,45 +260,149 @@ pub fn decompress(
     src_path: &std::path::Path,
     dst_path: &std::path::Path,
 ) {
+    if let Err(err) =
+        decompress_with_options(file_type, src_path, dst_path, &DecompressOptions::default())
+    {
+        panic!("{}", err);
+    }
+}
+
+pub fn decompress_with_options(
+    file_type: enums::FileType,
+    src_path: &std::path::Path,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+) -> Result<Report, ArchiveError> {
+    let mut report = Report {
+        warnings: Vec::new(),
+        output_path: dst_path.to_path_buf(),
+    };
     match file_type {
         enums::FileType::Zip => {
-            compression::zip::decompress(src_path, dst_path);
+            report.warnings = compression::zip::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Tar => {
-            compression::tar::decompress(src_path, dst_path);
+            report.warnings = compression::tar::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Tarbz2 => {
-            compression::tar_bz2::decompress(src_path, dst_path);
+            report.warnings = compression::tar_bz2::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Targz => {
-            compression::tar_gz::decompress(src_path, dst_path);
+            report.warnings = compression::tar_gz::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Bz2 => {
-            compression::bz2::decompress(src_path, dst_path);
+            let staged = Staged::next_to(dst_path);
+            compression::bz2::decompress(src_path, staged.path(), options)?;
+            place_stream(staged, dst_path, options, &mut report)?;
         }
         enums::FileType::Gz => {
-            compression::gz::decompress(src_path, dst_path);
+            let staged = Staged::next_to(dst_path);
+            let stored = compression::gz::decompress(src_path, staged.path(), options)?;
+            let dst_path = if options.restore_name {
+                restore_stored(&staged, dst_path, stored, &mut report.warnings)
+            } else {
+                dst_path.to_path_buf()
+            };
+            place_stream(staged, &dst_path, options, &mut report)?;
         }
         enums::FileType::SevenZ => {
-            compression::sevenz::decompress(src_path, dst_path);
+            report.warnings = compression::sevenz::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Xz => {
-            compression::xz::decompress(src_path, dst_path);
+            let staged = Staged::next_to(dst_path);
+            compression::xz::decompress(src_path, staged.path(), options)?;
+            place_stream(staged, dst_path, options, &mut report)?;
         }
         enums::FileType::Tarxz => {
-            compression::tar_xz::decompress(src_path, dst_path);
+            report.warnings = compression::tar_xz::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Zst => {
-            compression::zst::decompress(src_path, dst_path);
+            let staged = Staged::next_to(dst_path);
+            compression::zst::decompress(src_path, staged.path(), options)?;
+            place_stream(staged, dst_path, options, &mut report)?;
         }
         enums::FileType::Tarzst => {
-            compression::tar_zst::decompress(src_path, dst_path);
+            report.warnings = compression::tar_zst::decompress(src_path, dst_path, options)?
         }
         enums::FileType::Lz4 => {
-            compression::lz4::decompress(src_path, dst_path);
+            let staged = Staged::next_to(dst_path);
+            compression::lz4::decompress(src_path, staged.path(), options)?;
+            place_stream(staged, dst_path, options, &mut report)?;
         }
         enums::FileType::Tarlz4 => {
-            compression::tar_lz4::decompress(src_path, dst_path);
+            report.warnings = compression::tar_lz4::decompress(src_path, dst_path, options)?
         }
     }
+    Ok(report)
+}
+
+// gzip -N: the stored mtime goes on the output and the stored name replaces
+// the file name of dst_path. Only the last component of the stored name is
+// used, so it can't point the output anywhere else.
+fn restore_stored(
+    staged: &Staged,
+    dst_path: &std::path::Path,
+    stored: Stored,
+    warnings: &mut Vec<String>,
+) -> PathBuf {
+    if let Some(mtime) = stored.mtime {
+        filetime::set_file_mtime(staged.path(), mtime)
+            .unwrap_or_else(|err| panic!("set mtime of {} failed: {}", dst_path.display(), err));
+    }
+    let name = match stored.name {
+        Some(name) => name,
+        None => return dst_path.to_path_buf(),
+    };
+    match std::path::Path::new(&name).file_name() {
+        Some(file_name) => dst_path.with_file_name(file_name),
+        None => {
+            warnings.push(format!("ignored stored name {:?}", name));
+            dst_path.to_path_buf()
+        }
+    }
+}
+
+// Moves a finished single-stream output to dst_path under the conflict
+// policy and records where it went. Containers go through the Extractor,
+// which decides per entry.
+fn place_stream(
+    staged: Staged,
+    dst_path: &std::path::Path,
+    options: &DecompressOptions,
+    report: &mut Report,
+) -> Result<(), ArchiveError> {
+    report.output_path = dst_path.to_path_buf();
+    let existing = match fs::symlink_metadata(dst_path) {
+        Ok(metadata) if metadata.is_dir() => Existing::Dir,
+        Ok(metadata) => Existing::Other {
+            mtime: FileTime::from_last_modification_time(&metadata),
+        },
+        Err(_) => {
+            persist(staged, dst_path);
+            return Ok(());
+        }
+    };
+    let mtime = fs::metadata(staged.path())
+        .map(|metadata| FileTime::from_last_modification_time(&metadata))
+        .ok();
+    match conflict::decide(options.conflicts, existing, false, mtime) {
+        Decision::Write => persist(staged, dst_path),
+        Decision::Skip => report.warnings.push(conflict::skip_warning(dst_path)),
+        Decision::Rename => {
+            report.output_path = conflict::free_path(dst_path);
+            persist(staged, &report.output_path);
+        }
+        Decision::Fail => {
+            return Err(ArchiveError::Conflict {
+                path: dst_path.to_path_buf(),
+            })
+        }
+    }
+    Ok(())
+}
+
+fn persist(staged: Staged, dst_path: &std::path::Path) {
+    staged
+        .persist(dst_path)
+        .unwrap_or_else(|err| panic!("move {} into place failed: {}", dst_path.display(), err));
 }
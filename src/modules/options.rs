@@ -0,0 +1,238 @@
+use std::path::PathBuf;
+
+use crate::contents::enums::{
+    ConflictPolicy, Destination, DirectoryMode, LinkPolicy, SpecialFilePolicy, TarFormat,
+    UnreadablePolicy, UnsafeEntryPolicy,
+};
+use crate::modules::transform::Transform;
+
+#[derive(Debug, Clone, Default)]
+pub struct CompressOptions {
+    pub sevenz: SevenZOptions,
+    pub gzip: GzipOptions,
+    pub directories: DirectoryMode,
+    pub source: SourceOptions,
+    pub reproducible: ReproducibleOptions,
+    pub filter: FilterOptions,
+    // symlinks below a directory input; hardlinked files are stored once in
+    // tar (as hardlink entries) and as a full copy per name in zip and 7z,
+    // which have no hardlink entry
+    pub links: LinkPolicy,
+    // store extended attributes (POSIX ACLs and file capabilities included)
+    // as PAX SCHILY.xattr records; tar formats only
+    pub xattrs: bool,
+    // header format of the tar.* variants and of single-stream formats on a
+    // directory
+    pub tar_format: TarFormat,
+    // FIFOs, device nodes and sockets below a directory input
+    pub special_files: SpecialFilePolicy,
+    // rename entries as they are stored, rule by rule; entries renamed to
+    // nothing are left out
+    pub transforms: Vec<Transform>,
+    // files and directories below a directory input that can't be read
+    pub unreadable: UnreadablePolicy,
+}
+
+// Which files under a directory input get packed. Patterns are the globs of
+// modules::pattern: `*` and `?` within one name, `**` across directories,
+// `[...]` classes and `\` escapes, matched against the name, or against the
+// path below the input directory when they contain a `/`. A trailing `/`
+// only matches directories.
+#[derive(Debug, Clone, Default)]
+pub struct FilterOptions {
+    // files have to match one of these, all files when empty
+    pub include: Vec<String>,
+    // files and directories matching any of these are left out
+    pub exclude: Vec<String>,
+    // files with more exclude patterns, one per line
+    pub exclude_from: Vec<PathBuf>,
+    // honour .gitignore and .ignore files found in the tree
+    pub gitignore: bool,
+    // leave out .git, .hg, .svn and other version control directories
+    pub exclude_vcs: bool,
+    // leave out everything whose name starts with a dot
+    pub exclude_hidden: bool,
+}
+
+// Archives that come out byte for byte the same every time the same tree is
+// packed, for tar.*, zip and 7z.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReproducibleOptions {
+    // sort entries by name, zero owners, normalise modes to 0644/0755 and
+    // leave out every timestamp but the mtime
+    pub enabled: bool,
+    // latest mtime an entry may carry, newer ones are clamped down to it
+    pub source_date_epoch: Option<i64>,
+}
+
+impl ReproducibleOptions {
+    // SOURCE_DATE_EPOCH as set by build systems following
+    // reproducible-builds.org, None when unset or not a number.
+    pub fn source_date_epoch_from_env() -> Option<i64> {
+        std::env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()
+    }
+}
+
+// What happens to the input file once a single-stream format has written
+// its output, like gzip and gunzip do by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceOptions {
+    // delete the input after the output has been checked
+    pub remove: bool,
+    // give the output the input's permissions, owner (as root) and times
+    pub preserve_metadata: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct GzipOptions {
+    // record the source file name and mtime in the header; off is gzip -n
+    pub store_name: bool,
+}
+
+impl Default for GzipOptions {
+    fn default() -> Self {
+        GzipOptions { store_name: true }
+    }
+}
+
+// 7z entries are always plain LZMA2: sevenz-rust has no BCJ/x86 or delta
+// encoder, so those filters can't be asked for.
+#[derive(Debug, Clone)]
+pub struct SevenZOptions {
+    // pack file contents into shared solid blocks instead of one stream per file
+    pub solid: bool,
+    // upper bound of uncompressed bytes per solid block, unlimited when None
+    pub solid_block_size: Option<u64>,
+    // LZMA2 preset, 0 (fastest) to 9 (best)
+    pub preset: u32,
+    // LZMA2 dictionary size in bytes, the preset default when None
+    pub dict_size: Option<u32>,
+    // order files by extension before grouping them into solid blocks
+    pub sort_by_extension: bool,
+}
+
+impl Default for SevenZOptions {
+    fn default() -> Self {
+        SevenZOptions {
+            solid: false,
+            solid_block_size: None,
+            preset: 6,
+            dict_size: None,
+            sort_by_extension: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecompressOptions {
+    // what to do with entries that would land outside the destination
+    pub unsafe_entries: UnsafeEntryPolicy,
+    // what to do when an output path already exists
+    pub conflicts: ConflictPolicy,
+    pub limits: Limits,
+    pub nested: NestedOptions,
+    pub destination: Destination,
+    // leading path components dropped from every entry name, like tar's
+    // --strip-components; entries with nothing left are left out
+    pub strip_components: u32,
+    // name a single-stream output after the name stored in its header, and
+    // give it the stored mtime, like gzip -N
+    pub restore_name: bool,
+    pub source: SourceOptions,
+    // put back extended attributes stored in tar PAX records; ones that can't
+    // be set are reported as warnings
+    pub xattrs: bool,
+    pub permissions: PermissionOptions,
+    // FIFO and device node entries
+    pub special_files: SpecialFilePolicy,
+    // let the store policy create device nodes, which also takes root
+    pub allow_devices: bool,
+    // rename entries as they are extracted, after strip_components; entries
+    // renamed to nothing are left out and unsafe results are unsafe entries
+    pub transforms: Vec<Transform>,
+}
+
+// Owners and permission bits of extracted files and directories. By default
+// stored modes are taken as they are, minus setuid, setgid and sticky, and
+// everything belongs to whoever runs the extraction.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionOptions {
+    // give entries the numeric uid/gid the archive stores (tar, and zip with
+    // Info-ZIP's unix extra field); only root may give files away
+    pub numeric_owner: bool,
+    // stored id -> local id, applied before restoring owners
+    pub uid_map: Vec<(u32, u32)>,
+    pub gid_map: Vec<(u32, u32)>,
+    // leave stored modes aside: files get 0666 and directories 0777, less the
+    // umask, like tar --no-same-permissions
+    pub no_same_permissions: bool,
+    // bits cleared from every mode; with no_same_permissions the process
+    // umask is used when None
+    pub umask: Option<u32>,
+    // keep setuid, setgid and sticky bits
+    pub special_bits: bool,
+}
+
+impl PermissionOptions {
+    // A FROM:TO pair of numeric ids.
+    pub fn parse_id_map(raw: &str) -> Result<(u32, u32), String> {
+        let (from, to) = raw
+            .split_once(':')
+            .ok_or_else(|| format!("invalid id mapping, expected FROM:TO: {}", raw))?;
+        match (from.trim().parse(), to.trim().parse()) {
+            (Ok(from), Ok(to)) => Ok((from, to)),
+            _ => Err(format!("invalid id mapping, expected FROM:TO: {}", raw)),
+        }
+    }
+
+    // An octal umask such as 022 or 0027.
+    pub fn parse_umask(raw: &str) -> Result<u32, String> {
+        match u32::from_str_radix(raw.trim(), 8) {
+            Ok(umask) if umask <= 0o7777 => Ok(umask),
+            _ => Err(format!("invalid umask, expected octal up to 7777: {}", raw)),
+        }
+    }
+}
+
+// Decompression bomb guards, each unlimited when None. Byte, ratio and entry
+// limits apply to every layer on its own, depth to the number of layers.
+// Nested archives unpacked by a recursive extraction share the byte and
+// entry limits with the outer archive and each other.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    // bytes written by one layer, all entries together
+    pub max_total_bytes: Option<u64>,
+    // bytes written for any single entry
+    pub max_entry_bytes: Option<u64>,
+    // output bytes per input byte
+    pub max_ratio: Option<u64>,
+    // entries in one archive
+    pub max_entries: Option<u64>,
+    // formats unwrapped in one request
+    pub max_depth: Option<u32>,
+}
+
+// Archives found inside extracted output, unpacked in place into a sibling
+// `<name>.d/` directory.
+#[derive(Debug, Clone)]
+pub struct NestedOptions {
+    pub enabled: bool,
+    // how many archive-in-archive levels to follow below the outer one
+    pub max_depth: u32,
+    // globs (as for FilterOptions) a member has to match to be unpacked, all
+    // members when empty
+    pub include: Vec<String>,
+    // remove an inner archive once it has been unpacked
+    pub delete_archives: bool,
+}
+
+impl Default for NestedOptions {
+    fn default() -> Self {
+        NestedOptions {
+            enabled: false,
+            max_depth: 4,
+            include: Vec::new(),
+            delete_archives: false,
+        }
+    }
+}
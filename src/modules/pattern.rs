@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobMatcher};
+
+// Shell-style wildcards read the way .gitignore reads them: `*` and `?` stop
+// at `/`, `**` crosses any number of directories, `[...]` is a character
+// class and `\` escapes. A pattern without a `/` is tried against the file
+// name alone, one with a `/` against the whole relative path, and a trailing
+// `/` only matches directories.
+pub struct Pattern {
+    matcher: GlobMatcher,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Pattern, globset::Error> {
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(rest) if !rest.is_empty() => (rest, true),
+            _ => (pattern, false),
+        };
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .backslash_escape(true)
+            .build()?;
+        Ok(Pattern {
+            matcher: glob.compile_matcher(),
+            anchored: pattern.contains('/'),
+            dir_only,
+        })
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        self.matcher.is_match(text)
+    }
+
+    pub fn matches_path(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.matches(&path.to_string_lossy().replace('\\', "/"))
+        } else {
+            match path.file_name() {
+                Some(name) => self.matches(&name.to_string_lossy()),
+                None => false,
+            }
+        }
+    }
+}
+
+// Every pattern compiled, or the first one that doesn't parse.
+pub fn compile(patterns: &[String]) -> Result<Vec<Pattern>, globset::Error> {
+    patterns
+        .iter()
+        .map(|pattern| Pattern::new(pattern))
+        .collect()
+}
+
+// One-off forms of the above for a file; a pattern that doesn't parse
+// matches nothing.
+pub fn matches_path(pattern: &str, path: &Path) -> bool {
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_path(path, false))
+}
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(text))
+}
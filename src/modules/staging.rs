@@ -0,0 +1,192 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+// Paths of the Staged outputs neither persisted nor dropped yet, for the
+// signal handler to remove.
+static LIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// Files being written to in place and how to put them back, for the signal
+// handler. Every write to them holds this lock.
+static IN_PLACE: Mutex<Vec<Restore>> = Mutex::new(Vec::new());
+
+// Hidden, unique per process and call, so concurrent runs next to the same
+// destination never pick the same name.
+pub fn temp_name(name: &OsStr) -> OsString {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    OsString::from(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        process::id(),
+        id
+    ))
+}
+
+// Output being written under a temporary sibling of its destination. Nothing
+// shows up at the destination until persist(); dropping it instead (an error
+// return or a panic unwinding) removes whatever was written, and so does
+// SIGINT, SIGTERM or SIGHUP once remove_on_signal() is installed.
+pub struct Staged {
+    path: PathBuf,
+    done: bool,
+}
+
+impl Staged {
+    pub fn next_to(dst: &Path) -> Self {
+        let name = dst.file_name().unwrap_or_else(|| OsStr::new("output"));
+        let path = dst.with_file_name(temp_name(name));
+        live().push(path.clone());
+        Staged { path, done: false }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn persist(mut self, dst: &Path) -> io::Result<()> {
+        fs::rename(&self.path, dst)?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        let mut live = live();
+        if let Some(index) = live.iter().position(|path| *path == self.path) {
+            live.swap_remove(index);
+        }
+        if !self.done {
+            remove(&self.path);
+        }
+    }
+}
+
+// The bytes of an existing file past `keep`, changed in place. Until
+// finish(), dropping it or SIGINT, SIGTERM or SIGHUP (once remove_on_signal()
+// is installed) cuts the file back to `keep` bytes and zero-fills it to
+// `restored_len`.
+pub struct InPlace {
+    file: File,
+    path: PathBuf,
+    done: bool,
+}
+
+struct Restore {
+    path: PathBuf,
+    keep: u64,
+    restored_len: u64,
+}
+
+impl Restore {
+    fn apply(&self) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        file.set_len(self.keep)?;
+        file.set_len(self.restored_len.max(self.keep))
+    }
+}
+
+impl InPlace {
+    // Writing starts at `keep`.
+    pub fn open(path: &Path, keep: u64, restored_len: u64) -> io::Result<InPlace> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(keep))?;
+        in_place().push(Restore {
+            path: path.to_path_buf(),
+            keep,
+            restored_len,
+        });
+        Ok(InPlace {
+            file,
+            path: path.to_path_buf(),
+            done: false,
+        })
+    }
+
+    // Cuts off whatever followed the last write and keeps the result.
+    pub fn finish(mut self) -> io::Result<()> {
+        let _lock = in_place();
+        let end = self.file.stream_position()?;
+        self.file.set_len(end)?;
+        self.done = true;
+        Ok(())
+    }
+}
+
+impl Write for InPlace {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _lock = in_place();
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _lock = in_place();
+        self.file.flush()
+    }
+}
+
+impl Drop for InPlace {
+    fn drop(&mut self) {
+        let mut in_place = in_place();
+        if let Some(index) = in_place
+            .iter()
+            .position(|restore| restore.path == self.path)
+        {
+            let restore = in_place.swap_remove(index);
+            if !self.done {
+                let _ = restore.apply();
+            }
+        }
+    }
+}
+
+// Removes every staged output still in progress and puts back every file
+// changed in place when the process gets SIGINT, SIGTERM or SIGHUP, then
+// exits with 130 like an interrupted shell command. Destructors don't run on
+// a signal, so without it a cancelled run leaves its hidden temporaries
+// behind. The locks are held until exit, so no new output is staged and no
+// more is written in place in between.
+pub fn remove_on_signal() -> io::Result<()> {
+    ctrlc::set_handler(|| {
+        let live = live();
+        for path in live.iter() {
+            remove(path);
+        }
+        let in_place = in_place();
+        for restore in in_place.iter() {
+            let _ = restore.apply();
+        }
+        process::exit(130);
+    })
+    .map_err(io::Error::other)
+}
+
+fn live() -> MutexGuard<'static, Vec<PathBuf>> {
+    LIVE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn in_place() -> MutexGuard<'static, Vec<Restore>> {
+    IN_PLACE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn remove(path: &Path) {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            let _ = fs::remove_dir_all(path);
+        }
+        Ok(_) => {
+            let _ = fs::remove_file(path);
+        }
+        Err(_) => {}
+    }
+}
@@ -0,0 +1,202 @@
+use std::path::{Component, Path, PathBuf};
+
+use regex::{Regex, RegexBuilder};
+
+// One rule renaming archive entries, applied to the `/`-separated name an
+// entry is packed or unpacked under.
+#[derive(Debug, Clone)]
+pub enum Transform {
+    // take a leading directory off names below it; the directory itself is
+    // left out
+    StripPrefix(String),
+    // put every name below this directory
+    Prefix(String),
+    // a sed expression like tar --transform: s/REGEX/REPLACEMENT/FLAGS
+    Substitute(Substitution),
+}
+
+impl Transform {
+    // A sed substitution such as `s,^build/out,dist,`. Any character after the
+    // `s` delimits the parts; REGEX is the regex crate's syntax, which covers
+    // sed's extended one and matches in linear time, REPLACEMENT may use `&`
+    // and `\1`..`\9`, and FLAGS are `g` (every match) and `i` (ignore case).
+    pub fn parse(raw: &str) -> Result<Transform, String> {
+        let invalid = |why: &str| format!("invalid transform {}: {}", raw, why);
+        let mut chars = raw.chars();
+        if chars.next() != Some('s') {
+            return Err(invalid("expected s/REGEX/REPLACEMENT/FLAGS"));
+        }
+        let delimiter = match chars.next() {
+            Some(c) if c != '\\' && !c.is_alphanumeric() => c,
+            _ => return Err(invalid("expected a delimiter after s")),
+        };
+        let parts = split_unescaped(chars.as_str(), delimiter);
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern, replacement, flags] => (pattern, replacement, flags),
+            _ => return Err(invalid("expected s/REGEX/REPLACEMENT/FLAGS")),
+        };
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                _ => return Err(invalid(&format!("unknown flag {}", flag))),
+            }
+        }
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|err| invalid(&err.to_string()))?;
+        let replacement = replacement_template(replacement, regex.captures_len() - 1)
+            .map_err(|why| invalid(&why))?;
+        Ok(Transform::Substitute(Substitution {
+            regex,
+            replacement,
+            global,
+        }))
+    }
+
+    // The rules a strip prefix, sed expressions and a prefix make, in the
+    // order they are applied.
+    pub fn chain(
+        strip_prefix: Option<&str>,
+        substitutions: &[Transform],
+        prefix: Option<&str>,
+    ) -> Vec<Transform> {
+        let mut transforms = Vec::new();
+        if let Some(strip_prefix) = strip_prefix {
+            transforms.push(Transform::StripPrefix(trim_slashes(strip_prefix)));
+        }
+        transforms.extend(substitutions.iter().cloned());
+        if let Some(prefix) = prefix {
+            transforms.push(Transform::Prefix(trim_slashes(prefix)));
+        }
+        transforms
+    }
+
+    fn apply(&self, name: String) -> String {
+        match self {
+            Transform::StripPrefix(dir) if name == *dir => String::new(),
+            Transform::StripPrefix(dir) => match name.strip_prefix(dir.as_str()) {
+                Some(rest) if !dir.is_empty() && rest.starts_with('/') => rest[1..].to_string(),
+                _ => name,
+            },
+            Transform::Prefix(dir) if dir.is_empty() || name.is_empty() => name,
+            Transform::Prefix(dir) => format!("{}/{}", dir, name),
+            Transform::Substitute(substitution) => substitution.apply(&name),
+        }
+    }
+}
+
+// Runs every rule over the name in order. Empty and `.` components are
+// dropped from the result, so a rule that removes everything leaves an
+// empty path.
+pub fn apply(transforms: &[Transform], name: &Path) -> PathBuf {
+    if transforms.is_empty() {
+        return name.to_path_buf();
+    }
+    let name = name.to_string_lossy().replace('\\', "/");
+    let renamed = transforms
+        .iter()
+        .fold(name, |name, transform| transform.apply(name));
+    renamed
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect()
+}
+
+// The name an entry is packed under, None when the rules leave nothing of
+// it. A result that is absolute or climbs out of the archive is refused.
+pub fn pack_name(transforms: &[Transform], name: &Path) -> Result<Option<PathBuf>, String> {
+    if transforms.is_empty() {
+        return Ok(Some(name.to_path_buf()));
+    }
+    let renamed = apply(transforms, name);
+    let escapes = renamed
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)));
+    if escapes || renamed.has_root() {
+        return Err(format!(
+            "{} would be stored as {}",
+            name.display(),
+            renamed.display()
+        ));
+    }
+    Ok((!renamed.as_os_str().is_empty()).then_some(renamed))
+}
+
+fn trim_slashes(dir: &str) -> String {
+    dir.trim_matches('/').to_string()
+}
+
+// Splits on the delimiter. Escapes are left in place: the regex reads an
+// escaped punctuation character as itself and the replacement reads `\x` as
+// a literal x, an escaped delimiter included.
+fn split_unescaped(raw: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().expect("parts is never empty");
+        match c {
+            '\\' => {
+                part.push('\\');
+                part.extend(chars.next());
+            }
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
+
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    regex: Regex,
+    // the replacement in the regex crate's `${N}` template syntax
+    replacement: String,
+    // every match instead of the first
+    global: bool,
+}
+
+impl Substitution {
+    // Like sed, an empty match right after the previous match is passed over.
+    fn apply(&self, name: &str) -> String {
+        let limit = if self.global { 0 } else { 1 };
+        self.regex
+            .replacen(name, limit, self.replacement.as_str())
+            .into_owned()
+    }
+}
+
+// Turns a sed replacement into the regex crate's template syntax: `&` and
+// `\1`..`\9` become `${0}`..`${9}`, every other `\x` is a literal x and a
+// literal `$` is doubled.
+fn replacement_template(raw: &str, groups: usize) -> Result<String, String> {
+    let mut template = String::new();
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '&' => None,
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    let group = digit as usize - '0' as usize;
+                    if group > groups {
+                        return Err(format!("no group {} in the regex", group));
+                    }
+                    template.push_str(&format!("${{{}}}", group));
+                    continue;
+                }
+                Some(escaped) => Some(escaped),
+                None => return Err("trailing backslash".to_string()),
+            },
+            c => Some(c),
+        };
+        match literal {
+            None => template.push_str("${0}"),
+            Some('$') => template.push_str("$$"),
+            Some(c) => template.push(c),
+        }
+    }
+    Ok(template)
+}
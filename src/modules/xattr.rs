@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Extended attributes as tar keeps them in PAX `SCHILY.xattr.<name>` records.
+// POSIX ACLs (system.posix_acl_*) and file capabilities (security.capability)
+// are extended attributes too, so they travel the same way. Only Linux is
+// supported; elsewhere nothing is listed and nothing can be set.
+pub const PAX_PREFIX: &str = "SCHILY.xattr.";
+
+// Name and value of every attribute `path` carries, sorted by name. Names
+// that aren't UTF-8 can't be PAX keys and are left out.
+#[cfg(target_os = "linux")]
+pub fn list(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let names = match read_buffer(|buffer, size| unsafe {
+        libc::listxattr(path.as_ptr(), buffer as *mut libc::c_char, size)
+    }) {
+        Ok(names) => names,
+        // a filesystem without attributes has none to store
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut attributes = Vec::new();
+    for name in names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+    {
+        let name_str = match std::str::from_utf8(name) {
+            Ok(name) => name.to_string(),
+            Err(_) => continue,
+        };
+        let name = CString::new(name).expect("xattr name has no interior nul");
+        let value = match read_buffer(|buffer, size| unsafe {
+            libc::getxattr(path.as_ptr(), name.as_ptr(), buffer, size)
+        }) {
+            Ok(value) => value,
+            // removed between listing and reading
+            Err(err) if err.raw_os_error() == Some(libc::ENODATA) => continue,
+            Err(err) => return Err(err),
+        };
+        attributes.push((name_str, value));
+    }
+    attributes.sort();
+    Ok(attributes)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn list(_path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+#[cfg(target_os = "linux")]
+pub fn set(file: &File, name: &str, value: &[u8]) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::fd::AsRawFd;
+
+    let name =
+        CString::new(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let result = unsafe {
+        libc::fsetxattr(
+            file.as_raw_fd(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set(_file: &File, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+// Calls a size-probing xattr function twice: once for the length, once to
+// fill a buffer of that length, retrying if the value grew in between.
+#[cfg(target_os = "linux")]
+fn read_buffer(
+    mut call: impl FnMut(*mut libc::c_void, usize) -> libc::ssize_t,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let size = call(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let read = call(buffer.as_mut_ptr() as *mut libc::c_void, buffer.len());
+        if read >= 0 {
+            buffer.truncate(read as usize);
+            return Ok(buffer);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
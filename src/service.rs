@@ -1,30 +1,100 @@
+use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
-use std::io::ErrorKind;
+use std::io::{self, ErrorKind};
 use std::panic::{catch_unwind, AssertUnwindSafe};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use crate::contents::enums::{self, FileType};
+use filetime::FileTime;
+use walkdir::WalkDir;
+
+use crate::contents::enums::{
+    self, ConflictPolicy, Destination, DirectoryMode, FileType, TarFormat,
+};
 use crate::modules;
+use crate::modules::compression::links::{self, Kind};
+use crate::modules::compression::source::SourceTree;
+use crate::modules::conflict::{self, Decision, Existing};
+use crate::modules::error::{ArchiveError, Limit, SpecialKind, UnsafeReason};
+use crate::modules::filter;
+use crate::modules::limits::Spent;
+use crate::modules::metadata;
+use crate::modules::options::{CompressOptions, DecompressOptions, NestedOptions, SourceOptions};
+use crate::modules::pattern::{self, Pattern};
+use crate::modules::staging::Staged;
+
+const SEVENZ_MIN_DICT_SIZE: u32 = 4 << 10;
+const SEVENZ_MAX_DICT_SIZE: u32 = 1536 << 20;
+// How deep `auto` digs when no --max-depth is given.
+const AUTO_MAX_LAYERS: u32 = 16;
 
 #[derive(Debug, Clone)]
 pub struct CompressRequest {
     pub file_type: FileType,
     pub input: PathBuf,
     pub output: PathBuf,
+    pub options: CompressOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateRequest {
+    pub file_type: FileType,
+    // the archive to change, appended to in place when it is an uncompressed
+    // tar that keeps every entry, replaced by a rewritten copy otherwise
+    pub archive: PathBuf,
+    // files and directories to store, under the names compress gives them;
+    // entries already there under the same names are replaced
+    pub add: Vec<PathBuf>,
+    // entry names to remove, along with everything below them
+    pub delete: Vec<PathBuf>,
+    pub options: CompressOptions,
 }
 
 #[derive(Debug, Clone)]
 pub struct DecompressRequest {
     pub input: PathBuf,
     pub output: PathBuf,
-    pub level: i8,
+    pub level: Level,
+    pub options: DecompressOptions,
+}
+
+// How many layers decompress peels off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    // at most this many, stopping early at content that isn't an archive
+    Layers(i8),
+    // until the content isn't a recognised format, up to the depth limit
+    Auto,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::Layers(5)
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        if raw.eq_ignore_ascii_case("auto") {
+            return Ok(Level::Auto);
+        }
+        raw.parse()
+            .map(Level::Layers)
+            .map_err(|_| format!("invalid level: {} (expected a number or auto)", raw))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct OperationResult {
     pub output_path: PathBuf,
     pub message: String,
+    pub warnings: Vec<String>,
+    // the formats decompress removed, outermost first
+    pub layers: Vec<FileType>,
+    // archives found inside the output and unpacked next to themselves
+    pub nested_archives: Vec<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -33,6 +103,10 @@ pub enum MagicPackError {
     UnsupportedFileType,
     InvalidInput(String),
     OperationFailed(String),
+    UnsafeEntry { path: PathBuf, reason: UnsafeReason },
+    LimitExceeded(Limit),
+    Conflict { path: PathBuf },
+    SpecialFile { path: PathBuf, kind: SpecialKind },
 }
 
 impl fmt::Display for MagicPackError {
@@ -42,6 +116,16 @@ impl fmt::Display for MagicPackError {
             MagicPackError::UnsupportedFileType => write!(f, "unsupported file type"),
             MagicPackError::InvalidInput(message) => write!(f, "{}", message),
             MagicPackError::OperationFailed(message) => write!(f, "{}", message),
+            MagicPackError::UnsafeEntry { path, reason } => {
+                write!(f, "unsafe archive entry {}: {}", path.display(), reason)
+            }
+            MagicPackError::LimitExceeded(limit) => {
+                write!(f, "decompression limit exceeded: {}", limit)
+            }
+            MagicPackError::Conflict { path } => write!(f, "{} already exists", path.display()),
+            MagicPackError::SpecialFile { path, kind } => {
+                write!(f, "special file {}: {}", path.display(), kind)
+            }
         }
     }
 }
@@ -57,6 +141,25 @@ impl From<std::io::Error> for MagicPackError {
     }
 }
 
+impl From<ArchiveError> for MagicPackError {
+    fn from(err: ArchiveError) -> Self {
+        match err {
+            ArchiveError::UnsafeEntry { path, reason } => {
+                MagicPackError::UnsafeEntry { path, reason }
+            }
+            ArchiveError::LimitExceeded(limit) => MagicPackError::LimitExceeded(limit),
+            ArchiveError::Conflict { path } => MagicPackError::Conflict { path },
+            ArchiveError::SpecialFile { path, kind } => MagicPackError::SpecialFile { path, kind },
+        }
+    }
+}
+
+// For the CLI and the MCP server to call once at startup: a run cancelled by
+// SIGINT, SIGTERM or SIGHUP removes its half-written output before exiting.
+pub fn remove_staged_on_signal() -> io::Result<()> {
+    modules::staging::remove_on_signal()
+}
+
 pub fn supported_formats() -> Vec<&'static str> {
     vec![
         "zip", "tar", "bz2", "gz", "tar.bz2", "tar.gz", "7z", "xz", "tar.xz", "zst", "tar.zst",
@@ -71,15 +174,33 @@ pub fn detect_file_type(path: &Path) -> Result<FileType, MagicPackError> {
 pub fn compress(req: CompressRequest) -> Result<OperationResult, MagicPackError> {
     validate_compress_request(&req)?;
 
+    if req.input.is_dir() {
+        if let Some(tarred) = tarred_file_type(req.file_type) {
+            return match req.options.directories {
+                DirectoryMode::Tar => compress(CompressRequest {
+                    file_type: tarred,
+                    ..req
+                }),
+                DirectoryMode::PerFile => compress_each(req),
+            };
+        }
+    }
+
     let output_path = if req.output == Path::new(".") {
         default_compress_output_path(&req.input, &req.output, req.file_type)?
     } else {
         req.output.clone()
     };
 
-    run_operation("compress", || {
-        modules::compress(req.file_type, &req.input, &output_path);
+    let warnings = run_operation("compress", || {
+        Ok(modules::compress_with_options(
+            req.file_type,
+            &req.input,
+            &output_path,
+            &req.options,
+        ))
     })?;
+    settle_compressed_source(req.file_type, &req.input, &output_path, &req.options.source)?;
 
     Ok(OperationResult {
         output_path,
@@ -87,6 +208,94 @@ pub fn compress(req: CompressRequest) -> Result<OperationResult, MagicPackError>
             "compressed as {}",
             enums::get_file_type_string(req.file_type)
         ),
+        warnings,
+        layers: Vec::new(),
+        nested_archives: Vec::new(),
+    })
+}
+
+// gzip -r: every file below the input directory gets a compressed sibling
+// with the format's extension appended.
+fn compress_each(req: CompressRequest) -> Result<OperationResult, MagicPackError> {
+    if req.output != Path::new(".") {
+        return Err(MagicPackError::InvalidInput(
+            "per-file compression writes next to each file; leave the output path unset".into(),
+        ));
+    }
+    let extension = enums::get_file_type_string(req.file_type);
+    let mut warnings = Vec::new();
+
+    // Listed up front so the walk never sees the files it produces.
+    let mut files = Vec::new();
+    let mut tree = SourceTree::new(&req.input, &req.options, false)?;
+    for entry in tree.by_ref() {
+        let entry = entry?;
+        match entry.kind {
+            Kind::File => {}
+            // a single-file codec has nothing to store them as
+            Kind::Special(kind) => {
+                links::store_special(
+                    kind,
+                    &entry.path,
+                    req.options.special_files,
+                    false,
+                    &mut warnings,
+                )?;
+                continue;
+            }
+            _ => continue,
+        }
+        if entry.path.extension() == Some(OsStr::new(extension)) {
+            warnings.push(format!(
+                "{} already has the .{} suffix, left unchanged",
+                entry.path.display(),
+                extension
+            ));
+            continue;
+        }
+        files.push(entry.path);
+    }
+    warnings.splice(0..0, tree.finish());
+
+    for file in &files {
+        let mut output = file.as_os_str().to_os_string();
+        output.push(".");
+        output.push(extension);
+        run_operation("compress", || {
+            modules::compress_with_options(req.file_type, file, Path::new(&output), &req.options);
+            Ok(())
+        })?;
+        settle_compressed_source(req.file_type, file, Path::new(&output), &req.options.source)?;
+    }
+
+    Ok(OperationResult {
+        output_path: req.input.clone(),
+        message: format!("compressed {} files as {}", files.len(), extension),
+        warnings,
+        layers: Vec::new(),
+        nested_archives: Vec::new(),
+    })
+}
+
+pub fn update(req: UpdateRequest) -> Result<OperationResult, MagicPackError> {
+    validate_update_request(&req)?;
+
+    let warnings = run_operation("update", || {
+        Ok(modules::update_with_options(
+            req.file_type,
+            &req.archive,
+            &req.add,
+            &req.delete,
+            &req.options,
+        ))
+    })?;
+
+    Ok(OperationResult {
+        output_path: req.archive.clone(),
+        message: format!("updated {}", enums::get_file_type_string(req.file_type)),
+        warnings,
+        layers: Vec::new(),
+        nested_archives: Vec::new(),
     })
 }
 
@@ -97,53 +306,344 @@ pub fn decompress(req: DecompressRequest) -> Result<OperationResult, MagicPackEr
         fs::create_dir_all(&req.output)?;
     }
 
-    let src_filename = req.input.file_stem().ok_or_else(|| {
+    let src_filename = req.input.file_name().ok_or_else(|| {
         MagicPackError::InvalidInput("input path must include a file name".into())
     })?;
 
-    let mut decompress_output = req.output.join(src_filename);
+    // Every layer is unpacked into its own directory inside one hidden staging
+    // directory next to the output, so layer names never collide with each
+    // other or with real files, and whatever is left over goes with it.
+    let staging = Staged::next_to(&req.output.join(src_filename));
+    fs::create_dir(staging.path())?;
     let mut decompress_input = req.input.clone();
-    let filename = decompress_output.file_name().ok_or_else(|| {
-        MagicPackError::InvalidInput("output path must include a file name".into())
-    })?;
-    let mg_filename = format!("mg_{}", filename.to_string_lossy());
-    decompress_output.set_file_name(mg_filename);
+    let mut warnings = Vec::new();
+
+    let mut layers = Vec::new();
+    let (max_layers, max_depth) = match req.level {
+        Level::Layers(level) => (level as u32, req.options.limits.max_depth),
+        Level::Auto => {
+            let max_depth = req.options.limits.max_depth.unwrap_or(AUTO_MAX_LAYERS);
+            (max_depth.saturating_add(1), Some(max_depth))
+        }
+    };
 
-    for index in 0..req.level {
+    for index in 0..max_layers {
+        // A container unpacks to a directory, which is as far as peeling goes.
+        if index != 0 && decompress_input.is_dir() {
+            break;
+        }
         let file_type = match detect_file_type(&decompress_input) {
             Ok(file_type) => file_type,
             Err(MagicPackError::UnsupportedFileType) if index != 0 => break,
             Err(err) => return Err(err),
         };
+        if let Some(max_depth) = max_depth {
+            if index >= max_depth {
+                return Err(MagicPackError::LimitExceeded(Limit::Depth(max_depth)));
+            }
+        }
 
-        let current_output = decompress_output.clone();
-        run_operation("decompress", || {
-            modules::decompress(file_type, &decompress_input, &current_output);
-        })?;
-        decompress_input = current_output;
-        let temp_filename = decompress_input.file_stem().ok_or_else(|| {
+        let layer_name = decompress_input.file_stem().ok_or_else(|| {
             MagicPackError::InvalidInput("decompressed output must include a file name".into())
         })?;
-        decompress_output.set_file_name(temp_filename);
+        let layer_dir = staging.path().join(index.to_string());
+        fs::create_dir(&layer_dir)?;
+        let layer_output = layer_dir.join(layer_name);
+        let report = run_operation("decompress", || {
+            modules::decompress_with_options(
+                file_type,
+                &decompress_input,
+                &layer_output,
+                &req.options,
+            )
+        })?;
+        warnings.extend(report.warnings);
+        layers.push(file_type);
+        if decompress_input.starts_with(staging.path()) {
+            remove_layer(&decompress_input)?;
+        }
+        // A restored gzip name may differ from the one derived here.
+        decompress_input = report.output_path;
     }
 
-    let final_filename = decompress_input
-        .file_name()
-        .ok_or_else(|| {
-            MagicPackError::InvalidInput("decompressed output must include a file name".into())
-        })?
-        .to_string_lossy()
-        .replace("mg_", "");
-    let mut final_output = decompress_input.clone();
-    final_output.set_file_name(final_filename);
-    fs::rename(&decompress_input, &final_output)?;
+    // Nested archives get what the outer one left of the limits.
+    let mut spent = Spent::default();
+    if req.options.nested.enabled && decompress_input.is_dir() {
+        spent.add_tree(&decompress_input)?;
+    }
+
+    let final_filename = decompress_input.file_name().ok_or_else(|| {
+        MagicPackError::InvalidInput("decompressed output must include a file name".into())
+    })?;
+    let mut target = req.output.join(final_filename);
+    // Only a container unpacks to a directory; a single stream keeps its name.
+    if decompress_input.is_dir() {
+        match req.options.destination {
+            Destination::Subdir => {}
+            Destination::Smart => {
+                if let Some(top) = single_top_dir(&decompress_input)? {
+                    target = req.output.join(top.file_name().unwrap_or_default());
+                    decompress_input = top;
+                }
+            }
+            Destination::Here => target = req.output.clone(),
+        }
+    }
+    let final_output = place_output(
+        &decompress_input,
+        &target,
+        req.options.conflicts,
+        &mut warnings,
+    )?;
+
+    // Whatever the conflict policy kept out of the output is still staged,
+    // and only exists in the input now.
+    let complete = !anything_left(&decompress_input, &mut warnings);
+    let source = req.options.source;
+    if source.preserve_metadata && final_output.is_file() {
+        // A restored gzip name brings its own timestamp.
+        metadata::copy(&req.input, &final_output, !req.options.restore_name)?;
+    }
+    if source.remove {
+        if complete {
+            fs::remove_file(&req.input)?;
+        } else {
+            warnings.push(format!(
+                "kept {}: not all of it was written out",
+                req.input.display()
+            ));
+        }
+    }
+
+    let mut nested_archives = Vec::new();
+    if req.options.nested.enabled && final_output.is_dir() {
+        unpack_nested(
+            &final_output,
+            &req.options,
+            1,
+            &mut spent,
+            &mut warnings,
+            &mut nested_archives,
+        )?;
+    }
 
     Ok(OperationResult {
         output_path: final_output,
         message: String::from("decompressed"),
+        warnings,
+        layers,
+        nested_archives,
     })
 }
 
+// Unpacks every archive member below `dir` into `<member>.d/` beside it, then
+// looks inside those in turn. Members are recognised by content, not name.
+fn unpack_nested(
+    dir: &Path,
+    options: &DecompressOptions,
+    depth: u32,
+    spent: &mut Spent,
+    warnings: &mut Vec<String>,
+    nested_archives: &mut Vec<PathBuf>,
+) -> Result<(), MagicPackError> {
+    let include = &options.nested.include;
+    let mut members = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                warnings.push(format!(
+                    "could not check {} for nested archives: {}",
+                    walk_error_path(&err, dir).display(),
+                    walk_error_reason(&err)
+                ));
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        if include.is_empty()
+            || include
+                .iter()
+                .any(|pattern| pattern::matches_path(pattern, relative))
+        {
+            members.push(entry.into_path());
+        }
+    }
+
+    for member in members {
+        match detect_file_type(&member) {
+            Ok(_) => {}
+            Err(MagicPackError::UnsupportedFileType) => continue,
+            Err(err) => {
+                warnings.push(format!(
+                    "could not check {} for a nested archive: {}",
+                    member.display(),
+                    err
+                ));
+                continue;
+            }
+        }
+        if depth > options.nested.max_depth {
+            warnings.push(format!(
+                "left nested archive {} packed: deeper than {} levels",
+                member.display(),
+                options.nested.max_depth
+            ));
+            continue;
+        }
+        let limits = spent
+            .remaining(&options.limits)
+            .map_err(MagicPackError::LimitExceeded)?;
+        let mut target_name = member.file_name().unwrap_or_default().to_os_string();
+        target_name.push(".d");
+        let target = member.with_file_name(target_name);
+
+        let staging = Staged::next_to(&target);
+        fs::create_dir(staging.path())?;
+        let result = decompress(DecompressRequest {
+            input: member.clone(),
+            output: staging.path().to_path_buf(),
+            level: Level::Auto,
+            options: DecompressOptions {
+                nested: NestedOptions {
+                    enabled: false,
+                    ..options.nested.clone()
+                },
+                limits,
+                destination: Destination::Here,
+                strip_components: 0,
+                transforms: Vec::new(),
+                ..options.clone()
+            },
+        });
+        let result = match result {
+            Ok(result) => result,
+            // A bomb or a hostile entry one level down is as bad as at the top.
+            Err(err @ MagicPackError::UnsafeEntry { .. }) => return Err(err),
+            Err(MagicPackError::LimitExceeded(limit)) => {
+                return Err(MagicPackError::LimitExceeded(Spent::overall(
+                    limit,
+                    &options.limits,
+                )))
+            }
+            Err(err) => {
+                warnings.push(format!(
+                    "could not unpack nested archive {}: {}",
+                    member.display(),
+                    err
+                ));
+                continue;
+            }
+        };
+        warnings.extend(result.warnings);
+        spent.add_tree(staging.path())?;
+
+        // A container's entries land straight in the staging directory, a
+        // compressed single file as its only entry; either way it becomes
+        // `<member>.d/`.
+        let placed = place_output(staging.path(), &target, options.conflicts, warnings)?;
+        if options.nested.delete_archives {
+            fs::remove_file(&member)?;
+        }
+        nested_archives.push(member);
+        if placed.is_dir() {
+            unpack_nested(
+                &placed,
+                options,
+                depth + 1,
+                spent,
+                warnings,
+                nested_archives,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// The only entry of `dir` when that is a directory, the "one top-level
+// folder" layout that needs no wrapper directory.
+fn single_top_dir(dir: &Path) -> Result<Option<PathBuf>, MagicPackError> {
+    let mut entries = fs::read_dir(dir)?;
+    let top = match (entries.next(), entries.next()) {
+        (Some(entry), None) => entry?,
+        _ => return Ok(None),
+    };
+    Ok(top.file_type()?.is_dir().then(|| top.path()))
+}
+
+// Moves finished output to its final name under the conflict policy, merging
+// into an existing directory entry by entry. Returns where it ended up.
+fn place_output(
+    staged: &Path,
+    target: &Path,
+    policy: ConflictPolicy,
+    warnings: &mut Vec<String>,
+) -> Result<PathBuf, MagicPackError> {
+    let existing = match fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_dir() => Existing::Dir,
+        Ok(metadata) => Existing::Other {
+            mtime: FileTime::from_last_modification_time(&metadata),
+        },
+        Err(_) => {
+            fs::rename(staged, target)?;
+            return Ok(target.to_path_buf());
+        }
+    };
+    let staged_metadata = fs::symlink_metadata(staged)?;
+    let staged_mtime = FileTime::from_last_modification_time(&staged_metadata);
+
+    match conflict::decide(
+        policy,
+        existing,
+        staged_metadata.is_dir(),
+        Some(staged_mtime),
+    ) {
+        Decision::Write => match existing {
+            Existing::Dir => {
+                for entry in fs::read_dir(staged)? {
+                    let entry = entry?;
+                    place_output(
+                        &entry.path(),
+                        &target.join(entry.file_name()),
+                        policy,
+                        warnings,
+                    )?;
+                }
+            }
+            Existing::Other { .. } => {
+                if staged_metadata.is_dir() {
+                    fs::remove_file(target)?;
+                }
+                fs::rename(staged, target)?;
+            }
+        },
+        Decision::Skip => warnings.push(conflict::skip_warning(target)),
+        Decision::Rename => {
+            let renamed = conflict::free_path(target);
+            fs::rename(staged, &renamed)?;
+            return Ok(renamed);
+        }
+        Decision::Fail => {
+            return Err(MagicPackError::Conflict {
+                path: target.to_path_buf(),
+            });
+        }
+    }
+    Ok(target.to_path_buf())
+}
+
+// A finished intermediate layer, no longer needed once the next one is out.
+fn remove_layer(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
 fn validate_compress_request(req: &CompressRequest) -> Result<(), MagicPackError> {
     if !req.input.exists() {
         return Err(MagicPackError::InvalidInput(format!(
@@ -152,6 +652,23 @@ fn validate_compress_request(req: &CompressRequest) -> Result<(), MagicPackError
         )));
     }
 
+    validate_sevenz_options(&req.options)?;
+    validate_entry_options(req.file_type, &req.options)?;
+
+    let source = &req.options.source;
+    if source.remove || source.preserve_metadata {
+        if tarred_file_type(req.file_type).is_none() {
+            return Err(MagicPackError::InvalidInput(
+                "removing or preserving the source needs gz, bz2, xz, zst or lz4".into(),
+            ));
+        }
+        if req.input.is_dir() && req.options.directories != DirectoryMode::PerFile {
+            return Err(MagicPackError::InvalidInput(
+                "removing or preserving a source directory needs --directory-mode per-file".into(),
+            ));
+        }
+    }
+
     if req.output == Path::new(".") {
         return Ok(());
     }
@@ -165,6 +682,133 @@ fn validate_compress_request(req: &CompressRequest) -> Result<(), MagicPackError
     Ok(())
 }
 
+// Checks on how entries are stored, shared by compress and update.
+fn validate_entry_options(
+    file_type: FileType,
+    options: &CompressOptions,
+) -> Result<(), MagicPackError> {
+    for source in options.filter.include.iter().chain(&options.filter.exclude) {
+        if let Err(err) = Pattern::new(source) {
+            return Err(MagicPackError::InvalidInput(format!(
+                "invalid pattern {:?}: {}",
+                source,
+                err.kind()
+            )));
+        }
+    }
+    // The lines of an exclude file are left out of errors; it may not be
+    // the caller's to read.
+    for path in &options.filter.exclude_from {
+        match filter::read_patterns(path) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::InvalidInput => {
+                return Err(MagicPackError::InvalidInput(format!(
+                    "invalid pattern in {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+            Err(err) => {
+                return Err(MagicPackError::InvalidInput(format!(
+                    "cannot read exclude patterns from {}: {}",
+                    path.display(),
+                    err
+                )))
+            }
+        }
+    }
+
+    if options.xattrs && matches!(file_type, FileType::Zip | FileType::SevenZ) {
+        return Err(MagicPackError::InvalidInput(
+            "extended attributes can only be stored in tar formats".into(),
+        ));
+    }
+    if options.xattrs && options.tar_format == TarFormat::Ustar {
+        return Err(MagicPackError::InvalidInput(
+            "extended attributes need the gnu or pax tar format".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_update_request(req: &UpdateRequest) -> Result<(), MagicPackError> {
+    if !matches!(
+        req.file_type,
+        FileType::Tar
+            | FileType::Targz
+            | FileType::Tarbz2
+            | FileType::Tarxz
+            | FileType::Tarzst
+            | FileType::Tarlz4
+            | FileType::Zip
+    ) {
+        return Err(MagicPackError::InvalidInput(
+            "only tar, tar.* and zip archives can be updated".into(),
+        ));
+    }
+    if !req.archive.is_file() {
+        return Err(MagicPackError::InvalidInput(format!(
+            "archive does not exist: {}",
+            req.archive.display()
+        )));
+    }
+    if req.add.is_empty() && req.delete.is_empty() {
+        return Err(MagicPackError::InvalidInput(
+            "nothing to add or delete".into(),
+        ));
+    }
+    for path in &req.add {
+        if !path.exists() {
+            return Err(MagicPackError::InvalidInput(format!(
+                "input path does not exist: {}",
+                path.display()
+            )));
+        }
+    }
+    // Only plain names below the archive root; an empty one would match
+    // every entry.
+    for name in &req.delete {
+        let plain = name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        let named = name
+            .components()
+            .any(|component| matches!(component, Component::Normal(_)));
+        if !plain || !named {
+            return Err(MagicPackError::InvalidInput(format!(
+                "not an entry name to delete: {}",
+                name.display()
+            )));
+        }
+    }
+
+    validate_entry_options(req.file_type, &req.options)
+}
+
+fn validate_sevenz_options(options: &CompressOptions) -> Result<(), MagicPackError> {
+    let sevenz = &options.sevenz;
+    if sevenz.preset > 9 {
+        return Err(MagicPackError::InvalidInput(
+            "7z LZMA2 preset must be between 0 and 9".into(),
+        ));
+    }
+    if let Some(dict_size) = sevenz.dict_size {
+        if !(SEVENZ_MIN_DICT_SIZE..=SEVENZ_MAX_DICT_SIZE).contains(&dict_size) {
+            return Err(MagicPackError::InvalidInput(format!(
+                "7z LZMA2 dictionary size must be between {} and {} bytes",
+                SEVENZ_MIN_DICT_SIZE, SEVENZ_MAX_DICT_SIZE
+            )));
+        }
+    }
+    if sevenz.solid_block_size == Some(0) {
+        return Err(MagicPackError::InvalidInput(
+            "7z solid block size must be greater than 0".into(),
+        ));
+    }
+    Ok(())
+}
+
 fn validate_decompress_request(req: &DecompressRequest) -> Result<(), MagicPackError> {
     if !req.input.exists() {
         return Err(MagicPackError::InvalidInput(format!(
@@ -173,15 +817,100 @@ fn validate_decompress_request(req: &DecompressRequest) -> Result<(), MagicPackE
         )));
     }
 
-    if req.level <= 0 {
+    if matches!(req.level, Level::Layers(level) if level <= 0) {
         return Err(MagicPackError::InvalidInput(
             "decompress level must be greater than 0".into(),
         ));
     }
 
+    let permissions = &req.options.permissions;
+    if !permissions.numeric_owner
+        && (!permissions.uid_map.is_empty() || !permissions.gid_map.is_empty())
+    {
+        return Err(MagicPackError::InvalidInput(
+            "uid and gid maps only apply when restoring numeric owners".into(),
+        ));
+    }
+    if permissions.umask.is_some_and(|umask| umask > 0o7777) {
+        return Err(MagicPackError::InvalidInput(
+            "umask must not have bits above 07777".into(),
+        ));
+    }
+
     Ok(())
 }
 
+// Carries the input's metadata over to a freshly compressed file, then
+// removes the input once the output decodes back to exactly its bytes.
+fn settle_compressed_source(
+    file_type: FileType,
+    input: &Path,
+    output: &Path,
+    source: &SourceOptions,
+) -> Result<(), MagicPackError> {
+    if source.preserve_metadata {
+        metadata::copy(input, output, true)?;
+    }
+    if source.remove {
+        if !modules::verify_stream(file_type, output, input)? {
+            return Err(MagicPackError::OperationFailed(format!(
+                "{} does not decode back to {}; kept the source",
+                output.display(),
+                input.display()
+            )));
+        }
+        fs::remove_file(input)?;
+    }
+    Ok(())
+}
+
+// Whatever can't be walked counts as left, with a warning, so the source is
+// kept rather than removed on a guess. A path that was moved away entirely
+// leaves nothing.
+fn anything_left(path: &Path, warnings: &mut Vec<String>) -> bool {
+    if matches!(fs::symlink_metadata(path), Err(err) if err.kind() == ErrorKind::NotFound) {
+        return false;
+    }
+    for entry in WalkDir::new(path) {
+        match entry {
+            Ok(entry) if entry.file_type().is_dir() => {}
+            Ok(_) => return true,
+            Err(err) => {
+                warnings.push(format!(
+                    "could not check {} for entries left out: {}",
+                    walk_error_path(&err, path).display(),
+                    walk_error_reason(&err)
+                ));
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn walk_error_path<'a>(err: &'a walkdir::Error, root: &'a Path) -> &'a Path {
+    err.path().unwrap_or(root)
+}
+
+fn walk_error_reason(err: &walkdir::Error) -> String {
+    match err.io_error() {
+        Some(io_err) => io_err.to_string(),
+        None => err.to_string(),
+    }
+}
+
+// The tar-wrapped form of a single-stream codec, None for containers.
+fn tarred_file_type(file_type: FileType) -> Option<FileType> {
+    match file_type {
+        FileType::Gz => Some(FileType::Targz),
+        FileType::Bz2 => Some(FileType::Tarbz2),
+        FileType::Xz => Some(FileType::Tarxz),
+        FileType::Zst => Some(FileType::Tarzst),
+        FileType::Lz4 => Some(FileType::Tarlz4),
+        _ => None,
+    }
+}
+
 fn default_compress_output_path(
     src_path: &Path,
     dst_path: &Path,
@@ -195,11 +924,11 @@ fn default_compress_output_path(
     Ok(temp_output)
 }
 
-fn run_operation<F>(label: &str, operation: F) -> Result<(), MagicPackError>
+fn run_operation<F, T>(label: &str, operation: F) -> Result<T, MagicPackError>
 where
-    F: FnOnce(),
+    F: FnOnce() -> Result<T, ArchiveError>,
 {
-    catch_unwind(AssertUnwindSafe(operation)).map_err(|panic_payload| {
+    let result = catch_unwind(AssertUnwindSafe(operation)).map_err(|panic_payload| {
         let message = if let Some(message) = panic_payload.downcast_ref::<&str>() {
             (*message).to_string()
         } else if let Some(message) = panic_payload.downcast_ref::<String>() {
@@ -208,5 +937,6 @@ where
             format!("{} failed", label)
         };
         MagicPackError::OperationFailed(message)
-    })
+    })?;
+    result.map_err(MagicPackError::from)
 }
@@ -0,0 +1,200 @@
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::error::ArchiveError;
+use magic_pack::modules::options::{DecompressOptions, Limits};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir};
+
+fn write_bytes(path: &Path, contents: &[u8]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("create parent");
+    }
+    fs::write(path, contents).expect("write file");
+}
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .expect("read dir")
+        .map(|entry| {
+            entry
+                .expect("dir entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn entry_limit(max_entry_bytes: u64) -> DecompressOptions {
+    DecompressOptions {
+        limits: Limits {
+            max_entry_bytes: Some(max_entry_bytes),
+            ..Limits::default()
+        },
+        ..DecompressOptions::default()
+    }
+}
+
+// A tree whose big.bin trips a 1 KiB entry limit wherever it falls in the archive.
+fn pack_with_big_entry(root: &Path, file_type: FileType, name: &str) -> PathBuf {
+    let src = root.join("src");
+    write_bytes(&src.join("a.txt"), b"a");
+    write_bytes(&src.join("nested/b.txt"), b"b");
+    write_bytes(&src.join("big.bin"), &vec![1u8; 4096]);
+    let archive = root.join(name);
+    modules::compress(file_type, &src, &archive);
+    archive
+}
+
+#[test]
+fn failed_compress_leaves_no_output() {
+    let root = make_unique_dir("atomic_compress");
+    let dst = root.join("missing.gz");
+    let result = panic::catch_unwind(|| {
+        modules::compress(FileType::Gz, &root.join("missing"), &dst);
+    });
+    assert!(result.is_err());
+    assert!(names(&root).is_empty(), "left {:?}", names(&root));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn compress_replaces_existing_output_in_one_step() {
+    let root = make_unique_dir("atomic_compress_replace");
+    let src = root.join("data.txt");
+    write_bytes(&src, b"fresh");
+    let dst = root.join("data.txt.gz");
+    write_bytes(&dst, b"stale");
+    modules::compress(FileType::Gz, &src, &dst);
+
+    let out = root.join("out.txt");
+    modules::decompress(FileType::Gz, &dst, &out);
+    assert_eq!(fs::read(&out).expect("read output"), b"fresh");
+    assert_eq!(names(&root), ["data.txt", "data.txt.gz", "out.txt"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn failed_stream_decompress_leaves_no_temporary() {
+    let root = make_unique_dir("atomic_stream");
+    let src = root.join("data.bin");
+    write_bytes(&src, &vec![7u8; 4096]);
+    let compressed = root.join("data.bin.xz");
+    modules::compress(FileType::Xz, &src, &compressed);
+
+    let out = root.join("out.bin");
+    let result =
+        modules::decompress_with_options(FileType::Xz, &compressed, &out, &entry_limit(1024));
+    assert!(matches!(result, Err(ArchiveError::LimitExceeded(_))));
+    assert_eq!(names(&root), ["data.bin", "data.bin.xz"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn failed_extraction_into_new_dir_removes_it() {
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Zip, "src.zip"),
+        (FileType::SevenZ, "src.7z"),
+    ] {
+        let root = make_unique_dir("atomic_new_dir");
+        let archive = pack_with_big_entry(&root, file_type, name);
+        let out = root.join("out");
+        let result =
+            modules::decompress_with_options(file_type, &archive, &out, &entry_limit(1024));
+        assert!(
+            matches!(result, Err(ArchiveError::LimitExceeded(_))),
+            "{}",
+            name
+        );
+        assert!(!out.exists(), "{} left {:?}", name, names(&out));
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn failed_extraction_keeps_what_was_already_there() {
+    let root = make_unique_dir("atomic_existing_dir");
+    let archive = pack_with_big_entry(&root, FileType::Targz, "src.tar.gz");
+    let out = root.join("out");
+    write_bytes(&out.join("keep.txt"), b"keep");
+    write_bytes(&out.join("src/mine.txt"), b"mine");
+
+    let result =
+        modules::decompress_with_options(FileType::Targz, &archive, &out, &entry_limit(1024));
+    assert!(matches!(result, Err(ArchiveError::LimitExceeded(_))));
+    assert_eq!(names(&out), ["keep.txt", "src"]);
+    assert_eq!(names(&out.join("src")), ["mine.txt"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn successful_extraction_leaves_no_temporaries() {
+    let root = make_unique_dir("atomic_success");
+    let src = root.join("src");
+    write_bytes(&src.join("a.txt"), b"a");
+    write_bytes(&src.join("nested/b.txt"), b"b");
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    assert_eq!(names(&out.join("src")), ["a.txt", "nested"]);
+    assert_eq!(names(&out.join("src/nested")), ["b.txt"]);
+    cleanup_dir(&root);
+}
+
+// Destructors don't run when a signal ends the process; the handler the CLI
+// installs removes the staged output instead.
+#[cfg(unix)]
+#[test]
+fn signals_remove_the_staged_output() {
+    use std::process::Command;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let root = make_unique_dir("atomic_signal");
+    // xz of 64 MiB of noise takes long enough to catch it halfway
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let noise: Vec<u8> = (0..64 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    write_bytes(&root.join("in/noise.bin"), &noise);
+    let out = root.join("out");
+    fs::create_dir_all(&out).expect("create out");
+
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_magic-pack"))
+            .args(["-c", "-f", "xz", "-o"])
+            .arg(out.join("noise.bin.xz"))
+            .arg(root.join("in/noise.bin"))
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .expect("run magic-pack");
+        let started = Instant::now();
+        while names(&out).is_empty() {
+            assert!(
+                started.elapsed() < Duration::from_secs(30),
+                "no staged output"
+            );
+            thread::sleep(Duration::from_millis(5));
+        }
+        unsafe { libc::kill(child.id() as libc::pid_t, signal) };
+        let status = child.wait().expect("wait for magic-pack");
+        assert_eq!(status.code(), Some(130), "{}", signal);
+        assert!(names(&out).is_empty(), "{}: {:?}", signal, names(&out));
+    }
+    cleanup_dir(&root);
+}
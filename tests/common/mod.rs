@@ -0,0 +1,36 @@
+// Fixtures shared by the integration tests. Each test binary includes this
+// module and uses only some of it.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn make_unique_dir(name: &str) -> PathBuf {
+    let base = PathBuf::from("target/tests");
+    fs::create_dir_all(&base).expect("create base test dir");
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time")
+        .as_nanos();
+    let pid = process::id();
+    let dir = base.join(format!("{}_{}_{}", name, pid, nanos));
+    fs::create_dir_all(&dir).expect("create test dir");
+    dir
+}
+
+pub fn write_file(path: &Path, contents: &str) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("create parent");
+    }
+    fs::write(path, contents).expect("write file");
+}
+
+pub fn cleanup_dir(path: &Path) {
+    let _ = fs::remove_dir_all(path);
+}
+
+pub fn tool_available(tool: &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok()
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use magic_pack::contents::enums::{ConflictPolicy, FileType};
+use magic_pack::modules;
+use magic_pack::modules::error::ArchiveError;
+use magic_pack::modules::options::DecompressOptions;
+use magic_pack::service::{self, DecompressRequest, Level, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn with_conflicts(conflicts: ConflictPolicy) -> DecompressOptions {
+    DecompressOptions {
+        conflicts,
+        ..DecompressOptions::default()
+    }
+}
+
+// Packs src/a.txt = "archived" and returns the archive with an output
+// directory that already has src/a.txt = "existing" in it.
+fn prepare(root: &Path, file_type: FileType, name: &str) -> (PathBuf, PathBuf) {
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "archived");
+    let archive = root.join(name);
+    modules::compress(file_type, &src, &archive);
+    let out = root.join("out");
+    write_file(&out.join("src/a.txt"), "existing");
+    (archive, out)
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).expect("read file")
+}
+
+#[test]
+fn error_policy_refuses_existing_file() {
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Zip, "src.zip"),
+        (FileType::SevenZ, "src.7z"),
+    ] {
+        let root = make_unique_dir("conflict_error");
+        let (archive, out) = prepare(&root, file_type, name);
+        let result = modules::decompress_with_options(
+            file_type,
+            &archive,
+            &out,
+            &with_conflicts(ConflictPolicy::Error),
+        );
+        match result {
+            Err(ArchiveError::Conflict { path }) => assert_eq!(path, Path::new("src/a.txt")),
+            other => panic!("expected a conflict for {}, got {:?}", name, other),
+        }
+        assert_eq!(read(&out.join("src/a.txt")), "existing");
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn skip_policy_keeps_existing_file() {
+    let root = make_unique_dir("conflict_skip");
+    let (archive, out) = prepare(&root, FileType::Zip, "src.zip");
+    let report = modules::decompress_with_options(
+        FileType::Zip,
+        &archive,
+        &out,
+        &with_conflicts(ConflictPolicy::Skip),
+    )
+    .expect("decompress with skip");
+    assert_eq!(read(&out.join("src/a.txt")), "existing");
+    assert_eq!(report.warnings.len(), 1);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn overwrite_policy_replaces_existing_file() {
+    let root = make_unique_dir("conflict_overwrite");
+    let (archive, out) = prepare(&root, FileType::SevenZ, "src.7z");
+    modules::decompress_with_options(
+        FileType::SevenZ,
+        &archive,
+        &out,
+        &with_conflicts(ConflictPolicy::Overwrite),
+    )
+    .expect("decompress with overwrite");
+    assert_eq!(read(&out.join("src/a.txt")), "archived");
+    cleanup_dir(&root);
+}
+
+#[test]
+fn rename_policy_keeps_both() {
+    let root = make_unique_dir("conflict_rename");
+    let (archive, out) = prepare(&root, FileType::Targz, "src.tar.gz");
+    write_file(&out.join("src/a_1.txt"), "taken");
+    modules::decompress_with_options(
+        FileType::Targz,
+        &archive,
+        &out,
+        &with_conflicts(ConflictPolicy::Rename),
+    )
+    .expect("decompress with rename");
+    assert_eq!(read(&out.join("src/a.txt")), "existing");
+    assert_eq!(read(&out.join("src/a_1.txt")), "taken");
+    assert_eq!(read(&out.join("src/a_2.txt")), "archived");
+    cleanup_dir(&root);
+}
+
+#[test]
+fn newer_policy_compares_mtimes() {
+    let root = make_unique_dir("conflict_newer");
+    let (archive, out) = prepare(&root, FileType::Tar, "src.tar");
+    let existing = out.join("src/a.txt");
+
+    let future = FileTime::from_unix_time(4_000_000_000, 0);
+    filetime::set_file_mtime(&existing, future).expect("set future mtime");
+    modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &out,
+        &with_conflicts(ConflictPolicy::Newer),
+    )
+    .expect("decompress with newer");
+    assert_eq!(read(&existing), "existing");
+
+    let past = FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(&existing, past).expect("set past mtime");
+    modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &out,
+        &with_conflicts(ConflictPolicy::Newer),
+    )
+    .expect("decompress with newer");
+    assert_eq!(read(&existing), "archived");
+    cleanup_dir(&root);
+}
+
+#[test]
+fn service_refuses_existing_output_with_error_policy() {
+    let root = make_unique_dir("conflict_service_error");
+    let src = root.join("note.txt");
+    write_file(&src, "archived");
+    let compressed = root.join("note.txt.gz");
+    modules::compress(FileType::Gz, &src, &compressed);
+    let out = root.join("out");
+    write_file(&out.join("note.txt"), "existing");
+
+    let result = service::decompress(DecompressRequest {
+        input: compressed,
+        output: out.clone(),
+        level: Level::Layers(1),
+        options: with_conflicts(ConflictPolicy::Error),
+    });
+    assert!(matches!(result, Err(MagicPackError::Conflict { .. })));
+    assert_eq!(read(&out.join("note.txt")), "existing");
+    assert_eq!(fs::read_dir(&out).expect("read out dir").count(), 1);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn service_merges_into_existing_output_with_rename_policy() {
+    let root = make_unique_dir("conflict_service_rename");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "archived");
+    let archive = root.join("src.zip");
+    modules::compress(FileType::Zip, &src, &archive);
+    let out = root.join("out");
+
+    let request = DecompressRequest {
+        input: archive,
+        output: out.clone(),
+        level: Level::Layers(1),
+        options: with_conflicts(ConflictPolicy::Rename),
+    };
+    let first = service::decompress(request.clone()).expect("first decompress");
+    let second = service::decompress(request).expect("second decompress");
+    assert_eq!(first.output_path, second.output_path);
+    assert_eq!(read(&first.output_path.join("src/a.txt")), "archived");
+    assert_eq!(read(&first.output_path.join("src/a_1.txt")), "archived");
+    cleanup_dir(&root);
+}
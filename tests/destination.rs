@@ -0,0 +1,175 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use magic_pack::contents::enums::{Destination, FileType};
+use magic_pack::modules;
+use magic_pack::modules::options::DecompressOptions;
+use magic_pack::service::{self, DecompressRequest, Level, OperationResult};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .expect("read dir")
+        .map(|entry| {
+            entry
+                .expect("dir entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+// A tarbomb: two files and a directory side by side at the top level.
+fn write_flat_zip(path: &Path) {
+    let mut writer = zip::ZipWriter::new(fs::File::create(path).expect("create zip"));
+    for name in ["a.txt", "b.txt", "docs/c.txt"] {
+        writer
+            .start_file(name, zip::write::FileOptions::default())
+            .expect("start file");
+        writer.write_all(name.as_bytes()).expect("write entry");
+    }
+    writer.finish().expect("finish zip");
+}
+
+fn unpack(input: &Path, output: &Path, options: DecompressOptions) -> OperationResult {
+    service::decompress(DecompressRequest {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        level: Level::Auto,
+        options,
+    })
+    .expect("decompress")
+}
+
+fn with_destination(destination: Destination) -> DecompressOptions {
+    DecompressOptions {
+        destination,
+        ..DecompressOptions::default()
+    }
+}
+
+#[test]
+fn smart_keeps_a_single_top_level_directory() {
+    let root = make_unique_dir("destination_smart_single");
+    let src = root.join("project");
+    write_file(&src.join("a.txt"), "a");
+    let archive = root.join("project-1.0.tar.gz");
+    modules::compress(FileType::Targz, &src, &archive);
+
+    let out = root.join("out");
+    let result = unpack(&archive, &out, with_destination(Destination::Smart));
+    assert_eq!(result.output_path, out.join("project"));
+    assert_eq!(names(&out), ["project"]);
+    assert_eq!(names(&out.join("project")), ["a.txt"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn smart_wraps_a_tarbomb() {
+    let root = make_unique_dir("destination_smart_bomb");
+    let archive = root.join("bundle.zip");
+    write_flat_zip(&archive);
+
+    let out = root.join("out");
+    let result = unpack(&archive, &out, with_destination(Destination::Smart));
+    assert_eq!(result.output_path, out.join("bundle"));
+    assert_eq!(names(&out), ["bundle"]);
+    assert_eq!(names(&out.join("bundle")), ["a.txt", "b.txt", "docs"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn subdir_always_wraps() {
+    let root = make_unique_dir("destination_subdir");
+    let src = root.join("project");
+    write_file(&src.join("a.txt"), "a");
+    let archive = root.join("project.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+
+    let out = root.join("out");
+    let result = unpack(&archive, &out, DecompressOptions::default());
+    assert_eq!(result.output_path, out.join("project"));
+    assert_eq!(names(&out.join("project")), ["project"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn here_merges_into_the_output_directory() {
+    let root = make_unique_dir("destination_here");
+    let archive = root.join("bundle.zip");
+    write_flat_zip(&archive);
+
+    let out = root.join("out");
+    write_file(&out.join("existing.txt"), "existing");
+    let result = unpack(&archive, &out, with_destination(Destination::Here));
+    assert_eq!(result.output_path, out);
+    assert_eq!(names(&out), ["a.txt", "b.txt", "docs", "existing.txt"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn strip_components_works_for_every_container() {
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Zip, "src.zip"),
+        (FileType::SevenZ, "src.7z"),
+    ] {
+        let root = make_unique_dir("destination_strip");
+        let src = root.join("src");
+        write_file(&src.join("a.txt"), "a");
+        write_file(&src.join("nested/b.txt"), "b");
+        let archive = root.join(name);
+        modules::compress(file_type, &src, &archive);
+
+        let out = root.join("out");
+        let options = DecompressOptions {
+            strip_components: 1,
+            ..DecompressOptions::default()
+        };
+        modules::decompress_with_options(file_type, &archive, &out, &options).expect(name);
+        assert_eq!(names(&out), ["a.txt", "nested"], "{}", name);
+        assert_eq!(
+            fs::read_to_string(out.join("nested/b.txt")).expect("read"),
+            "b"
+        );
+
+        // Stripping more than any entry has leaves nothing behind.
+        let empty = root.join("empty");
+        let options = DecompressOptions {
+            strip_components: 3,
+            ..DecompressOptions::default()
+        };
+        modules::decompress_with_options(file_type, &archive, &empty, &options).expect(name);
+        assert!(names(&empty).is_empty(), "{}", name);
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn strip_components_with_smart_destination() {
+    let root = make_unique_dir("destination_strip_smart");
+    let src = root.join("release");
+    write_file(&src.join("project/a.txt"), "a");
+    let archive = root.join("release.tar.gz");
+    modules::compress(FileType::Targz, &src, &archive);
+
+    let out = root.join("out");
+    let result = unpack(
+        &archive,
+        &out,
+        DecompressOptions {
+            destination: Destination::Smart,
+            strip_components: 1,
+            ..DecompressOptions::default()
+        },
+    );
+    assert_eq!(result.output_path, out.join("project"));
+    assert_eq!(names(&out), ["project"]);
+    cleanup_dir(&root);
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::PathBuf;
+
+use magic_pack::contents::enums::{DirectoryMode, FileType};
+use magic_pack::modules;
+use magic_pack::modules::options::CompressOptions;
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn with_directories(directories: DirectoryMode) -> CompressOptions {
+    CompressOptions {
+        directories,
+        ..CompressOptions::default()
+    }
+}
+
+#[test]
+fn single_stream_codecs_tar_a_directory() {
+    for (file_type, tarred, name) in [
+        (FileType::Gz, FileType::Targz, "src.tar.gz"),
+        (FileType::Bz2, FileType::Tarbz2, "src.tar.bz2"),
+        (FileType::Xz, FileType::Tarxz, "src.tar.xz"),
+        (FileType::Zst, FileType::Tarzst, "src.tar.zst"),
+        (FileType::Lz4, FileType::Tarlz4, "src.tar.lz4"),
+    ] {
+        let root = make_unique_dir("directory_input_tar");
+        let src = root.join("src");
+        write_file(&src.join("a.txt"), "a");
+        write_file(&src.join("sub/b.txt"), "b");
+
+        let archive = root.join(name);
+        let result = service::compress(CompressRequest {
+            file_type,
+            input: src,
+            output: archive.clone(),
+            options: CompressOptions::default(),
+        })
+        .expect(name);
+        assert!(result.message.ends_with(name.trim_start_matches("src.")));
+
+        let out = root.join("out");
+        modules::decompress(tarred, &archive, &out);
+        assert_eq!(
+            fs::read_to_string(out.join("src/sub/b.txt")).expect("read"),
+            "b",
+            "{}",
+            name
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn per_file_compresses_next_to_each_file() {
+    let root = make_unique_dir("directory_input_per_file");
+    let src = root.join("logs");
+    write_file(&src.join("a.log"), "a");
+    write_file(&src.join("old/b.log"), "b");
+    write_file(&src.join("done.gz"), "not really gzip");
+
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Gz,
+        input: src.clone(),
+        output: PathBuf::from("."),
+        options: with_directories(DirectoryMode::PerFile),
+    })
+    .expect("compress");
+    assert_eq!(result.output_path, src);
+    assert_eq!(result.message, "compressed 2 files as gz");
+    assert!(result.warnings[0].contains("done.gz"));
+
+    assert!(src.join("a.log").exists());
+    let out = root.join("b.log");
+    modules::decompress(FileType::Gz, &src.join("old/b.log.gz"), &out);
+    assert_eq!(fs::read_to_string(&out).expect("read"), "b");
+    assert!(!src.join("done.gz.gz").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn per_file_refuses_an_output_path() {
+    let root = make_unique_dir("directory_input_per_file_output");
+    let src = root.join("logs");
+    write_file(&src.join("a.log"), "a");
+
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Zst,
+        input: src,
+        output: root.join("logs.zst"),
+        options: with_directories(DirectoryMode::PerFile),
+    });
+    assert!(matches!(result, Err(MagicPackError::InvalidInput(_))));
+    cleanup_dir(&root);
+}
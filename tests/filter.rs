@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use magic_pack::contents::enums::{DirectoryMode, FileType};
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, FilterOptions};
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+use walkdir::WalkDir;
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+// Every file below `dir`, as sorted slash-separated relative paths.
+fn files(dir: &Path) -> Vec<String> {
+    let mut files = WalkDir::new(dir)
+        .into_iter()
+        .map(|entry| entry.expect("walk"))
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            entry
+                .path()
+                .strip_prefix(dir)
+                .expect("relative")
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+// A small project with build output, a git directory and a couple of
+// ignore files.
+fn write_project(src: &Path) {
+    write_file(&src.join("Cargo.toml"), "[package]");
+    write_file(&src.join("src/main.rs"), "fn main() {}");
+    write_file(&src.join("src/lib.rs"), "");
+    write_file(&src.join("src/generated.rs"), "");
+    write_file(&src.join("src/.gitignore"), "generated.rs\n");
+    write_file(&src.join("target/debug/app"), "binary");
+    write_file(&src.join("logs/app.log"), "log");
+    write_file(&src.join("logs/keep.log"), "log");
+    write_file(
+        &src.join(".gitignore"),
+        "# build output\n/target/\n*.log\n!keep.log\n",
+    );
+    write_file(&src.join(".ignore"), "notes/\n");
+    write_file(&src.join("notes/todo.txt"), "todo");
+    write_file(&src.join(".git/HEAD"), "ref: refs/heads/main");
+    write_file(&src.join(".env"), "SECRET=1");
+}
+
+// Packs `src` with the given filters and lists what comes back out.
+fn packed(root: &Path, file_type: FileType, name: &str, filter: FilterOptions) -> Vec<String> {
+    let src = root.join("src");
+    let archive = root.join(name);
+    let options = CompressOptions {
+        filter,
+        ..CompressOptions::default()
+    };
+    modules::compress_with_options(file_type, &src, &archive, &options);
+    let out = root.join(format!("out-{}", name));
+    modules::decompress(file_type, &archive, &out);
+    files(&out.join("src"))
+}
+
+#[test]
+fn gitignore_vcs_and_hidden_in_every_container() {
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Targz, "src.tar.gz"),
+        (FileType::Zip, "src.zip"),
+        (FileType::SevenZ, "src.7z"),
+    ] {
+        let root = make_unique_dir("filter_gitignore");
+        write_project(&root.join("src"));
+        let filter = FilterOptions {
+            gitignore: true,
+            exclude_vcs: true,
+            ..FilterOptions::default()
+        };
+        assert_eq!(
+            packed(&root, file_type, name, filter),
+            [
+                ".env",
+                ".gitignore",
+                ".ignore",
+                "Cargo.toml",
+                "logs/keep.log",
+                "src/.gitignore",
+                "src/lib.rs",
+                "src/main.rs",
+            ],
+            "{}",
+            name
+        );
+
+        let filter = FilterOptions {
+            exclude_hidden: true,
+            ..FilterOptions::default()
+        };
+        let hidden = packed(&root, file_type, &format!("hidden-{}", name), filter);
+        assert!(hidden
+            .iter()
+            .all(|file| !file.contains("/.") && !file.starts_with('.')));
+        assert!(hidden.contains(&String::from("target/debug/app")));
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn include_and_exclude_wildcards() {
+    let root = make_unique_dir("filter_wildcards");
+    write_project(&root.join("src"));
+    let filter = FilterOptions {
+        include: vec![String::from("*.rs"), String::from("*.toml")],
+        exclude: vec![String::from("target"), String::from("src/gen*")],
+        ..FilterOptions::default()
+    };
+    assert_eq!(
+        packed(&root, FileType::Tar, "src.tar", filter),
+        ["Cargo.toml", "src/lib.rs", "src/main.rs"]
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn double_star_and_character_classes() {
+    let root = make_unique_dir("filter_globs");
+    write_project(&root.join("src"));
+    write_file(&root.join("src/logs/2024/old.log"), "log");
+    write_file(
+        &root.join("src/.gitignore"),
+        "logs/**/*.log\n!logs/keep.log\n",
+    );
+    let filter = FilterOptions {
+        gitignore: true,
+        exclude: vec![String::from("[.t]*"), String::from("src/*.rs")],
+        ..FilterOptions::default()
+    };
+    assert_eq!(
+        packed(&root, FileType::Tar, "src.tar", filter),
+        ["Cargo.toml", "logs/keep.log"]
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn trailing_slash_only_matches_directories() {
+    let root = make_unique_dir("filter_dir_only");
+    write_project(&root.join("src"));
+    let filter = FilterOptions {
+        exclude: vec![
+            String::from("target/"),
+            String::from("src/"),
+            String::from("Cargo.toml/"),
+            String::from("logs/*.log/"),
+        ],
+        exclude_hidden: true,
+        ..FilterOptions::default()
+    };
+    assert_eq!(
+        packed(&root, FileType::Tar, "src.tar", filter),
+        [
+            "Cargo.toml",
+            "logs/app.log",
+            "logs/keep.log",
+            "notes/todo.txt"
+        ]
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn malformed_pattern_is_invalid_input() {
+    let root = make_unique_dir("filter_malformed");
+    write_project(&root.join("src"));
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: root.join("src"),
+        output: root.join("src.tar"),
+        options: CompressOptions {
+            filter: FilterOptions {
+                exclude: vec![String::from("[unclosed")],
+                ..FilterOptions::default()
+            },
+            ..CompressOptions::default()
+        },
+    });
+    assert!(matches!(result, Err(MagicPackError::InvalidInput(_))));
+    assert!(!root.join("src.tar").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn exclude_from_reads_one_pattern_per_line() {
+    let root = make_unique_dir("filter_exclude_from");
+    write_project(&root.join("src"));
+    let list = root.join("exclude.txt");
+    write_file(&list, "# not shipped\n.*\n\ntarget\nlogs\nnotes\n");
+    let filter = FilterOptions {
+        exclude_from: vec![list],
+        ..FilterOptions::default()
+    };
+    assert_eq!(
+        packed(&root, FileType::Zip, "src.zip", filter),
+        [
+            "Cargo.toml",
+            "src/generated.rs",
+            "src/lib.rs",
+            "src/main.rs"
+        ]
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn missing_exclude_from_file_is_invalid_input() {
+    let root = make_unique_dir("filter_exclude_from_missing");
+    write_project(&root.join("src"));
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: root.join("src"),
+        output: root.join("src.tar"),
+        options: CompressOptions {
+            filter: FilterOptions {
+                exclude_from: vec![root.join("missing.txt")],
+                ..FilterOptions::default()
+            },
+            ..CompressOptions::default()
+        },
+    });
+    assert!(matches!(result, Err(MagicPackError::InvalidInput(_))));
+    assert!(!root.join("src.tar").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn per_file_mode_skips_filtered_files() {
+    let root = make_unique_dir("filter_per_file");
+    let src = root.join("src");
+    write_project(&src);
+    service::compress(CompressRequest {
+        file_type: FileType::Gz,
+        input: src.clone(),
+        output: PathBuf::from("."),
+        options: CompressOptions {
+            directories: DirectoryMode::PerFile,
+            filter: FilterOptions {
+                include: vec![String::from("*.rs")],
+                exclude_hidden: true,
+                ..FilterOptions::default()
+            },
+            ..CompressOptions::default()
+        },
+    })
+    .expect("compress");
+    let compressed: Vec<String> = files(&src)
+        .into_iter()
+        .filter(|file| file.ends_with(".gz"))
+        .collect();
+    assert_eq!(
+        compressed,
+        ["src/generated.rs.gz", "src/lib.rs.gz", "src/main.rs.gz"]
+    );
+    cleanup_dir(&root);
+}
+
+// A bad line of an exclude file is named by its number; its text stays out
+// of the error.
+#[test]
+fn bad_exclude_from_line_is_reported_by_number() {
+    let root = make_unique_dir("filter_exclude_from_bad");
+    write_project(&root.join("src"));
+    let list = root.join("exclude.txt");
+    write_file(&list, "# comment\ntarget\nsecret[value\n");
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: root.join("src"),
+        output: root.join("src.tar"),
+        options: CompressOptions {
+            filter: FilterOptions {
+                exclude_from: vec![list],
+                ..FilterOptions::default()
+            },
+            ..CompressOptions::default()
+        },
+    });
+    match result {
+        Err(MagicPackError::InvalidInput(message)) => {
+            assert!(message.contains("exclude.txt: line 3"), "{}", message);
+            assert!(!message.contains("secret"), "{}", message);
+        }
+        other => panic!("expected invalid input, got {:?}", other.map(|_| ())),
+    }
+    assert!(!root.join("src.tar").exists());
+    cleanup_dir(&root);
+}
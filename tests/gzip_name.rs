@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use flate2::read::GzDecoder;
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, DecompressOptions, GzipOptions};
+use magic_pack::service::{self, DecompressRequest, Level};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn header(path: &Path) -> flate2::GzHeader {
+    let decoder = GzDecoder::new(fs::File::open(path).expect("open gz"));
+    decoder.header().expect("gzip header").clone()
+}
+
+fn no_name() -> CompressOptions {
+    CompressOptions {
+        gzip: GzipOptions { store_name: false },
+        ..CompressOptions::default()
+    }
+}
+
+fn restore_name(input: &Path, output: &Path, restore_name: bool) -> PathBuf {
+    service::decompress(DecompressRequest {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        level: Level::Layers(1),
+        options: DecompressOptions {
+            restore_name,
+            ..DecompressOptions::default()
+        },
+    })
+    .expect("decompress")
+    .output_path
+}
+
+// Hand-built so the stored name can be anything, hostile ones included.
+fn write_gz_named(path: &Path, name: &str, mtime: u32, contents: &str) {
+    use std::io::Write;
+    let mut encoder = flate2::GzBuilder::new().filename(name).mtime(mtime).write(
+        fs::File::create(path).expect("create gz"),
+        Default::default(),
+    );
+    encoder.write_all(contents.as_bytes()).expect("write gz");
+    encoder.finish().expect("finish gz");
+}
+
+#[test]
+fn compress_records_name_and_mtime() {
+    let root = make_unique_dir("gzip_name_store");
+    let src = root.join("report.csv");
+    write_file(&src, "a,b");
+    filetime::set_file_mtime(&src, FileTime::from_unix_time(1_600_000_000, 0)).expect("mtime");
+    let compressed = root.join("report.csv.gz");
+    modules::compress(FileType::Gz, &src, &compressed);
+
+    let header = header(&compressed);
+    assert_eq!(header.filename(), Some(&b"report.csv"[..]));
+    assert_eq!(header.mtime(), 1_600_000_000);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn no_name_output_depends_only_on_content() {
+    let root = make_unique_dir("gzip_name_none");
+    let first = root.join("first.txt");
+    let second = root.join("second.txt");
+    write_file(&first, "same");
+    write_file(&second, "same");
+    filetime::set_file_mtime(&first, FileTime::from_unix_time(1_000, 0)).expect("mtime");
+
+    let first_gz = root.join("first.gz");
+    let second_gz = root.join("second.gz");
+    modules::compress_with_options(FileType::Gz, &first, &first_gz, &no_name());
+    modules::compress_with_options(FileType::Gz, &second, &second_gz, &no_name());
+
+    let header = header(&first_gz);
+    assert_eq!(header.filename(), None);
+    assert_eq!(header.mtime(), 0);
+    assert_eq!(
+        fs::read(&first_gz).expect("read"),
+        fs::read(&second_gz).expect("read")
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn decompress_restores_stored_name_and_mtime() {
+    let root = make_unique_dir("gzip_name_restore");
+    let compressed = root.join("download.gz");
+    write_gz_named(&compressed, "report.csv", 1_500_000_000, "a,b");
+
+    let out = root.join("out");
+    let restored = restore_name(&compressed, &out, true);
+    assert_eq!(restored, out.join("report.csv"));
+    assert_eq!(fs::read_to_string(&restored).expect("read"), "a,b");
+    let metadata = fs::metadata(&restored).expect("stat");
+    assert_eq!(
+        FileTime::from_last_modification_time(&metadata).unix_seconds(),
+        1_500_000_000
+    );
+
+    let plain = restore_name(&compressed, &root.join("plain"), false);
+    assert_eq!(plain, root.join("plain/download"));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn restored_name_stays_inside_the_output() {
+    let root = make_unique_dir("gzip_name_hostile");
+    let compressed = root.join("evil.gz");
+    write_gz_named(&compressed, "../../escaped.txt", 0, "x");
+
+    let out = root.join("out");
+    let restored = restore_name(&compressed, &out, true);
+    assert_eq!(restored, out.join("escaped.txt"));
+    assert!(!root.join("escaped.txt").exists());
+    cleanup_dir(&root);
+}
@@ -1,32 +1,14 @@
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{self, Command};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::Command;
 
 use magic_pack::contents::enums::FileType;
 use magic_pack::modules;
 use std::sync::Once;
 use walkdir::WalkDir;
 
-fn make_unique_dir(name: &str) -> PathBuf {
-    let base = PathBuf::from("target/tests");
-    fs::create_dir_all(&base).expect("create base test dir");
-    let nanos = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("time")
-        .as_nanos();
-    let pid = process::id();
-    let dir = base.join(format!("{}_{}_{}", name, pid, nanos));
-    fs::create_dir_all(&dir).expect("create test dir");
-    dir
-}
-
-fn write_file(path: &Path, contents: &str) {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).expect("create parent");
-    }
-    fs::write(path, contents).expect("write file");
-}
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
 
 fn prepare_src_dir(root: &Path, name: &str) -> PathBuf {
     let src_dir = root.join(name);
@@ -35,10 +17,6 @@ fn prepare_src_dir(root: &Path, name: &str) -> PathBuf {
     src_dir
 }
 
-fn cleanup_dir(path: &Path) {
-    let _ = fs::remove_dir_all(path);
-}
-
 fn find_file_by_suffix(root: &Path, suffix: &str) -> Option<PathBuf> {
     for entry in WalkDir::new(root).into_iter().filter_map(|e| e.ok()) {
         let path = entry.path();
@@ -336,6 +314,63 @@ fn tool_compress_zip_command_list() {
     cleanup_dir(&root);
 }
 
+// Info-ZIP keeps the exact UTC mtime in an extended timestamp field and a
+// local DOS time next to it; `-X` leaves the field out. Either way the time
+// comes back, whatever timezone the archive was written in.
+#[test]
+fn zip_command_mtimes_survive_timezones() {
+    if !zip_available() {
+        warn_missing_zip_once();
+        return;
+    }
+
+    let root = make_unique_dir("interop_zip_mtime");
+    let src_dir = prepare_src_dir(&root, "srcdir");
+    // 2020-01-01T00:00:00Z
+    let mtime = filetime::FileTime::from_unix_time(1_577_836_800, 0);
+    filetime::set_file_mtime(src_dir.join("a.txt"), mtime).expect("set mtime");
+    let mtime_of = |path: &Path| {
+        filetime::FileTime::from_last_modification_time(&fs::metadata(path).expect("stat"))
+    };
+
+    // with the field, made in another timezone; without it, made in ours
+    for (name, args, tz) in [
+        ("tokyo.zip", &["-r"][..], Some("Asia/Tokyo")),
+        ("plain.zip", &["-r", "-X"][..], None),
+    ] {
+        let mut command = Command::new("zip");
+        command
+            .current_dir(&root)
+            .args(args)
+            .arg(name)
+            .arg("srcdir");
+        if let Some(tz) = tz {
+            command.env("TZ", tz);
+        }
+        assert!(command.status().expect("run zip").success(), "{}", name);
+        let unpack = root.join(format!("unpack-{}", name));
+        modules::decompress(FileType::Zip, &root.join(name), &unpack);
+        assert_eq!(mtime_of(&unpack.join("srcdir/a.txt")), mtime, "{}", name);
+    }
+
+    // and ours read back by unzip in another timezone
+    let archive = root.join("from_tool.zip");
+    modules::compress(FileType::Zip, &src_dir, &archive);
+    let unpack = root.join("unpack-unzip");
+    let status = Command::new("unzip")
+        .env("TZ", "America/New_York")
+        .arg("-q")
+        .arg(&archive)
+        .arg("-d")
+        .arg(&unpack)
+        .status()
+        .expect("run unzip");
+    assert!(status.success(), "unzip failed");
+    assert_eq!(mtime_of(&unpack.join("srcdir/a.txt")), mtime);
+
+    cleanup_dir(&root);
+}
+
 #[test]
 fn tar_gz_command_compress_tool_decompress() {
     if !tar_available() {
@@ -0,0 +1,222 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::error::{ArchiveError, Limit};
+use magic_pack::modules::options::{DecompressOptions, Limits};
+use magic_pack::modules::Report;
+use magic_pack::service::{self, DecompressRequest, Level, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir};
+
+fn write_bytes(path: &Path, contents: &[u8]) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("create parent");
+    }
+    fs::write(path, contents).expect("write file");
+}
+
+fn with_limits(limits: Limits) -> DecompressOptions {
+    DecompressOptions {
+        limits,
+        ..DecompressOptions::default()
+    }
+}
+
+fn exceeded(result: Result<Report, ArchiveError>) -> Limit {
+    match result {
+        Err(ArchiveError::LimitExceeded(limit)) => limit,
+        other => panic!("expected a limit error, got {:?}", other),
+    }
+}
+
+#[test]
+fn gz_bomb_stops_at_ratio_limit() {
+    let root = make_unique_dir("limits_gz_ratio");
+    let src = root.join("zeros.bin");
+    write_bytes(&src, &vec![0u8; 8 << 20]);
+    let compressed = root.join("zeros.bin.gz");
+    modules::compress(FileType::Gz, &src, &compressed);
+
+    let limits = Limits {
+        max_ratio: Some(100),
+        ..Limits::default()
+    };
+    let result = modules::decompress_with_options(
+        FileType::Gz,
+        &compressed,
+        &root.join("out.bin"),
+        &with_limits(limits),
+    );
+    assert_eq!(exceeded(result), Limit::Ratio(100));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn zst_stops_at_total_output_limit() {
+    let root = make_unique_dir("limits_zst_total");
+    let src = root.join("data.bin");
+    write_bytes(&src, &vec![7u8; 200 << 10]);
+    let compressed = root.join("data.bin.zst");
+    modules::compress(FileType::Zst, &src, &compressed);
+
+    let limits = Limits {
+        max_total_bytes: Some(100 << 10),
+        ..Limits::default()
+    };
+    let out = root.join("out.bin");
+    let result =
+        modules::decompress_with_options(FileType::Zst, &compressed, &out, &with_limits(limits));
+    assert_eq!(exceeded(result), Limit::TotalBytes(100 << 10));
+    assert!(!out.exists(), "partial output should be removed");
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_stops_at_entry_size_limit() {
+    let root = make_unique_dir("limits_tar_entry");
+    let src = root.join("src");
+    write_bytes(&src.join("small.txt"), b"small");
+    write_bytes(&src.join("big.bin"), &vec![1u8; 4096]);
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+
+    let limits = Limits {
+        max_entry_bytes: Some(1024),
+        ..Limits::default()
+    };
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &root.join("out"),
+        &with_limits(limits),
+    );
+    assert_eq!(exceeded(result), Limit::EntryBytes(1024));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn zip_stops_at_entry_count_limit() {
+    let root = make_unique_dir("limits_zip_entries");
+    let archive = root.join("many.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&archive).expect("create zip"));
+    for i in 0..10 {
+        writer
+            .start_file(format!("f{}.txt", i), zip::write::FileOptions::default())
+            .expect("start file");
+        writer.write_all(b"x").expect("write entry");
+    }
+    writer.finish().expect("finish zip");
+
+    let limits = Limits {
+        max_entries: Some(3),
+        ..Limits::default()
+    };
+    let out = root.join("out");
+    let result =
+        modules::decompress_with_options(FileType::Zip, &archive, &out, &with_limits(limits));
+    assert_eq!(exceeded(result), Limit::Entries(3));
+    assert!(!out.join("f3.txt").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn sevenz_stops_at_total_output_limit() {
+    let root = make_unique_dir("limits_7z_total");
+    let src = root.join("src");
+    write_bytes(&src.join("a.bin"), &vec![3u8; 64 << 10]);
+    write_bytes(&src.join("b.bin"), &vec![4u8; 64 << 10]);
+    let archive = root.join("src.7z");
+    modules::compress(FileType::SevenZ, &src, &archive);
+
+    let limits = Limits {
+        max_total_bytes: Some(100 << 10),
+        ..Limits::default()
+    };
+    let result = modules::decompress_with_options(
+        FileType::SevenZ,
+        &archive,
+        &root.join("out"),
+        &with_limits(limits),
+    );
+    assert_eq!(exceeded(result), Limit::TotalBytes(100 << 10));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn archive_within_limits_extracts() {
+    let root = make_unique_dir("limits_within");
+    let src = root.join("src");
+    write_bytes(&src.join("a.txt"), b"hello");
+    let archive = root.join("src.tar.gz");
+    modules::compress(FileType::Targz, &src, &archive);
+
+    let limits = Limits {
+        max_total_bytes: Some(1 << 20),
+        max_entry_bytes: Some(1 << 20),
+        max_ratio: Some(10),
+        max_entries: Some(10),
+        max_depth: Some(2),
+    };
+    let out = root.join("out");
+    modules::decompress_with_options(FileType::Targz, &archive, &out, &with_limits(limits))
+        .expect("decompress within limits");
+    assert_eq!(
+        fs::read_to_string(out.join("src/a.txt")).expect("read a.txt"),
+        "hello"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn service_stops_at_depth_limit() {
+    let root = make_unique_dir("limits_depth");
+    let src = root.join("data.txt");
+    write_bytes(&src, b"layered");
+    let inner = root.join("data.txt.gz");
+    modules::compress(FileType::Gz, &src, &inner);
+    let outer = root.join("data.txt.gz.gz");
+    modules::compress(FileType::Gz, &inner, &outer);
+
+    let out = root.join("out");
+    let result = service::decompress(DecompressRequest {
+        input: outer,
+        output: out.clone(),
+        level: Level::Layers(5),
+        options: with_limits(Limits {
+            max_depth: Some(1),
+            ..Limits::default()
+        }),
+    });
+    match result {
+        Err(MagicPackError::LimitExceeded(limit)) => assert_eq!(limit, Limit::Depth(1)),
+        other => panic!("expected a depth limit error, got {:?}", other),
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn service_removes_partial_output_on_limit() {
+    let root = make_unique_dir("limits_cleanup");
+    let src = root.join("data.bin");
+    write_bytes(&src, &vec![9u8; 256 << 10]);
+    let compressed = root.join("data.bin.xz");
+    modules::compress(FileType::Xz, &src, &compressed);
+
+    let out = root.join("out");
+    let result = service::decompress(DecompressRequest {
+        input: compressed,
+        output: out.clone(),
+        level: Level::Layers(1),
+        options: with_limits(Limits {
+            max_total_bytes: Some(1024),
+            ..Limits::default()
+        }),
+    });
+    assert!(matches!(result, Err(MagicPackError::LimitExceeded(_))));
+    assert_eq!(fs::read_dir(&out).expect("read out dir").count(), 0);
+    cleanup_dir(&root);
+}
@@ -0,0 +1,180 @@
+// Symlinks and hardlinks need a unix filesystem to set up.
+#![cfg(unix)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use magic_pack::contents::enums::{FileType, LinkPolicy};
+use magic_pack::modules;
+use magic_pack::modules::options::CompressOptions;
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+const CONTAINERS: [(FileType, &str); 4] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Targz, "src.tar.gz"),
+    (FileType::Zip, "src.zip"),
+    (FileType::SevenZ, "src.7z"),
+];
+
+// src/a.txt, src/sub/b.txt, a file link, a directory link and a link back up
+// to src itself.
+fn write_linked_tree(src: &Path) {
+    use std::os::unix::fs::symlink;
+    write_file(&src.join("a.txt"), "a");
+    write_file(&src.join("sub/b.txt"), "b");
+    symlink("a.txt", src.join("file-link")).expect("file link");
+    symlink("sub", src.join("dir-link")).expect("dir link");
+    symlink("..", src.join("sub/up")).expect("loop link");
+}
+
+fn roundtrip(root: &Path, file_type: FileType, name: &str, links: LinkPolicy) -> PathBuf {
+    let archive = root.join(format!("{:?}-{}", links, name));
+    let options = CompressOptions {
+        links,
+        ..CompressOptions::default()
+    };
+    modules::compress_with_options(file_type, &root.join("src"), &archive, &options);
+    let out = root.join(format!("out-{:?}-{}", links, name));
+    modules::decompress(file_type, &archive, &out);
+    out.join("src")
+}
+
+#[test]
+fn store_keeps_links_as_links() {
+    for (file_type, name) in CONTAINERS {
+        let root = make_unique_dir("links_store");
+        write_linked_tree(&root.join("src"));
+        let out = roundtrip(&root, file_type, name, LinkPolicy::Store);
+        assert_eq!(
+            fs::read_link(out.join("file-link")).expect(name),
+            Path::new("a.txt")
+        );
+        assert_eq!(
+            fs::read_link(out.join("dir-link")).expect(name),
+            Path::new("sub")
+        );
+        assert_eq!(
+            fs::read_link(out.join("sub/up")).expect(name),
+            Path::new("..")
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("dir-link/b.txt")).expect("read"),
+            "b"
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn follow_stores_link_targets_and_stops_at_cycles() {
+    for (file_type, name) in CONTAINERS {
+        let root = make_unique_dir("links_follow");
+        write_linked_tree(&root.join("src"));
+        let out = roundtrip(&root, file_type, name, LinkPolicy::Follow);
+        let file_link = fs::symlink_metadata(out.join("file-link")).expect(name);
+        assert!(file_link.is_file(), "{}", name);
+        assert_eq!(
+            fs::read_to_string(out.join("file-link")).expect("read"),
+            "a"
+        );
+        let dir_link = fs::symlink_metadata(out.join("dir-link")).expect(name);
+        assert!(dir_link.is_dir(), "{}", name);
+        assert_eq!(
+            fs::read_to_string(out.join("dir-link/b.txt")).expect("read"),
+            "b"
+        );
+        assert!(
+            fs::symlink_metadata(out.join("sub/up")).is_err(),
+            "{}",
+            name
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn skip_leaves_links_out() {
+    for (file_type, name) in CONTAINERS {
+        let root = make_unique_dir("links_skip");
+        write_linked_tree(&root.join("src"));
+        let out = roundtrip(&root, file_type, name, LinkPolicy::Skip);
+        let mut names = fs::read_dir(&out)
+            .expect("read dir")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .file_name()
+                    .into_string()
+                    .expect("utf-8")
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.txt", "sub"], "{}", name);
+        assert!(
+            fs::symlink_metadata(out.join("sub/up")).is_err(),
+            "{}",
+            name
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn hardlinked_files_are_stored_once() {
+    use std::os::unix::fs::MetadataExt;
+    let root = make_unique_dir("links_hardlinks");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), &"x".repeat(64 << 10));
+    fs::create_dir_all(src.join("copies")).expect("create copies");
+    fs::hard_link(src.join("a.txt"), src.join("copies/b.txt")).expect("hard link");
+
+    let tar = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &tar);
+    let mut archive = tar::Archive::new(fs::File::open(&tar).expect("open tar"));
+    let mut links = Vec::new();
+    for entry in archive.entries().expect("entries") {
+        let entry = entry.expect("entry");
+        if entry.header().entry_type() == tar::EntryType::Link {
+            let target = entry.link_name().expect("link name").expect("target");
+            links.push((
+                entry.path().expect("path").into_owned(),
+                target.into_owned(),
+            ));
+        }
+    }
+    // Whichever name the walk reaches first carries the data.
+    let (a, b) = (
+        PathBuf::from("src/a.txt"),
+        PathBuf::from("src/copies/b.txt"),
+    );
+    assert!(
+        links == [(b.clone(), a.clone())] || links == [(a, b)],
+        "{:?}",
+        links
+    );
+    let out = root.join("out-tar");
+    modules::decompress(FileType::Tar, &tar, &out);
+    let a = fs::metadata(out.join("src/a.txt")).expect("stat a");
+    let b = fs::metadata(out.join("src/copies/b.txt")).expect("stat b");
+    assert_eq!(a.ino(), b.ino());
+
+    // Zip has no hardlink entry: each name gets its own copy.
+    let zip = root.join("src.zip");
+    modules::compress(FileType::Zip, &src, &zip);
+    assert!(fs::metadata(&zip).expect("stat zip").len() > 128 << 10);
+    let out = root.join("out-zip");
+    modules::decompress(FileType::Zip, &zip, &out);
+    let a = fs::symlink_metadata(out.join("src/a.txt")).expect("stat a");
+    let b = fs::symlink_metadata(out.join("src/copies/b.txt")).expect("stat b");
+    assert!(a.is_file() && b.is_file());
+    assert_ne!(a.ino(), b.ino());
+    for name in ["src/a.txt", "src/copies/b.txt"] {
+        assert_eq!(
+            fs::read_to_string(out.join(name)).expect("read"),
+            "x".repeat(64 << 10)
+        );
+    }
+    cleanup_dir(&root);
+}
@@ -0,0 +1,382 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::error::Limit;
+use magic_pack::modules::options::{DecompressOptions, Limits, NestedOptions};
+use magic_pack::modules::pattern;
+use magic_pack::service::{self, DecompressRequest, Level, MagicPackError, OperationResult};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .expect("read dir")
+        .map(|entry| {
+            entry
+                .expect("dir entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn unpack(input: &Path, output: &Path, level: Level) -> OperationResult {
+    service::decompress(DecompressRequest {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        level,
+        options: DecompressOptions::default(),
+    })
+    .expect("decompress")
+}
+
+#[test]
+fn keeps_names_that_contain_the_old_temp_prefix() {
+    let root = make_unique_dir("nested_mg_name");
+    let src = root.join("img_001.png");
+    write_file(&src, "pixels");
+    let compressed = root.join("img_001.png.gz");
+    modules::compress(FileType::Gz, &src, &compressed);
+
+    let out = root.join("out");
+    let result = unpack(&compressed, &out, Level::Layers(2));
+    assert_eq!(result.output_path, out.join("img_001.png"));
+    assert_eq!(names(&out), ["img_001.png"]);
+    assert_eq!(
+        fs::read_to_string(out.join("img_001.png")).expect("read"),
+        "pixels"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn removes_intermediate_layers() {
+    let root = make_unique_dir("nested_intermediates");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let archive = root.join("src.tar.gz");
+    modules::compress(FileType::Targz, &src, &archive);
+
+    let out = root.join("out");
+    let result = unpack(&archive, &out, Level::Layers(2));
+    assert_eq!(result.output_path, out.join("src"));
+    assert_eq!(names(&out), ["src"]);
+    assert_eq!(names(&out.join("src")), ["src"]);
+    assert_eq!(
+        fs::read_to_string(out.join("src/src/a.txt")).expect("read"),
+        "a"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn stops_at_the_requested_level() {
+    let root = make_unique_dir("nested_level");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let archive = root.join("src.tar.gz");
+    modules::compress(FileType::Targz, &src, &archive);
+
+    let out = root.join("out");
+    let result = unpack(&archive, &out, Level::Layers(1));
+    assert_eq!(result.output_path, out.join("src.tar"));
+    assert_eq!(names(&out), ["src.tar"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn unpacks_next_to_an_input_without_extension() {
+    let root = make_unique_dir("nested_no_extension");
+    let src = root.join("data");
+    write_file(&src, "plain");
+    let compressed = root.join("packed");
+    modules::compress(FileType::Gz, &src, &compressed);
+
+    let out = root.join("out");
+    let result = unpack(&compressed, &out, Level::Layers(1));
+    assert_eq!(result.output_path, out.join("packed"));
+    assert_eq!(names(&out), ["packed"]);
+    assert_eq!(
+        fs::read_to_string(out.join("packed")).expect("read"),
+        "plain"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn auto_unwraps_every_layer() {
+    let root = make_unique_dir("nested_auto");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let tar = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &tar);
+    let gz = root.join("src.tar.gz");
+    modules::compress(FileType::Gz, &tar, &gz);
+    let xz = root.join("src.tar.gz.xz");
+    modules::compress(FileType::Xz, &gz, &xz);
+
+    let out = root.join("out");
+    let result = unpack(&xz, &out, Level::Auto);
+    assert_eq!(result.layers, [FileType::Xz, FileType::Gz, FileType::Tar]);
+    assert_eq!(result.output_path, out.join("src"));
+    assert_eq!(names(&out), ["src"]);
+    assert_eq!(
+        fs::read_to_string(out.join("src/src/a.txt")).expect("read"),
+        "a"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn auto_stops_at_plain_content() {
+    let root = make_unique_dir("nested_auto_plain");
+    let src = root.join("notes.txt");
+    write_file(&src, "plain");
+    let compressed = root.join("notes.txt.zst");
+    modules::compress(FileType::Zst, &src, &compressed);
+
+    let out = root.join("out");
+    let result = unpack(&compressed, &out, Level::Auto);
+    assert_eq!(result.layers, [FileType::Zst]);
+    assert_eq!(names(&out), ["notes.txt"]);
+    cleanup_dir(&root);
+}
+
+// Text that mentions "ustar" is still plain content, not a tar.
+#[test]
+fn auto_stops_at_text_that_mentions_ustar() {
+    let root = make_unique_dir("nested_auto_ustar");
+    let src = root.join("notes.txt");
+    write_file(&src, &format!("{}ustar and more\n", "x".repeat(300)));
+    let compressed = root.join("notes.txt.gz");
+    modules::compress(FileType::Gz, &src, &compressed);
+
+    let out = root.join("out");
+    let result = unpack(&compressed, &out, Level::Auto);
+    assert_eq!(result.layers, [FileType::Gz]);
+    assert_eq!(names(&out), ["notes.txt"]);
+    assert_eq!(
+        fs::read_to_string(out.join("notes.txt")).expect("read"),
+        fs::read_to_string(&src).expect("read source")
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn auto_fails_past_the_depth_limit() {
+    let root = make_unique_dir("nested_auto_depth");
+    let src = root.join("data.txt");
+    write_file(&src, "layered");
+    let inner = root.join("data.txt.gz");
+    modules::compress(FileType::Gz, &src, &inner);
+    let outer = root.join("data.txt.gz.gz");
+    modules::compress(FileType::Gz, &inner, &outer);
+
+    let out = root.join("out");
+    let result = service::decompress(DecompressRequest {
+        input: outer,
+        output: out.clone(),
+        level: Level::Auto,
+        options: DecompressOptions {
+            limits: Limits {
+                max_depth: Some(1),
+                ..Limits::default()
+            },
+            ..DecompressOptions::default()
+        },
+    });
+    match result {
+        Err(MagicPackError::LimitExceeded(limit)) => assert_eq!(limit, Limit::Depth(1)),
+        other => panic!("expected a depth limit error, got {:?}", other),
+    }
+    assert!(names(&out).is_empty(), "left {:?}", names(&out));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn level_parses_numbers_and_auto() {
+    assert_eq!("3".parse::<Level>(), Ok(Level::Layers(3)));
+    assert_eq!("auto".parse::<Level>(), Ok(Level::Auto));
+    assert!("deep".parse::<Level>().is_err());
+}
+
+// delivery.zip > delivery/part.tar.gz > part/lib/app.jar > app/classes/A.class,
+// plus a plain README next to the inner archive.
+fn pack_delivery(root: &Path) -> PathBuf {
+    let app = root.join("app");
+    write_file(&app.join("classes/A.class"), "bytecode");
+    let part = root.join("part");
+    fs::create_dir_all(part.join("lib")).expect("create lib dir");
+    modules::compress(FileType::Zip, &app, &part.join("lib/app.jar"));
+    let delivery = root.join("delivery");
+    write_file(&delivery.join("README"), "read me");
+    modules::compress(FileType::Targz, &part, &delivery.join("part.tar.gz"));
+    let archive = root.join("delivery.zip");
+    modules::compress(FileType::Zip, &delivery, &archive);
+    archive
+}
+
+fn unpack_recursive(input: &Path, output: &Path, nested: NestedOptions) -> OperationResult {
+    service::decompress(DecompressRequest {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        level: Level::default(),
+        options: DecompressOptions {
+            nested,
+            ..DecompressOptions::default()
+        },
+    })
+    .expect("decompress")
+}
+
+fn recursive() -> NestedOptions {
+    NestedOptions {
+        enabled: true,
+        ..NestedOptions::default()
+    }
+}
+
+#[test]
+fn recursive_unpacks_archives_inside_archives() {
+    let root = make_unique_dir("nested_recursive");
+    let archive = pack_delivery(&root);
+
+    let out = root.join("out");
+    let result = unpack_recursive(&archive, &out, recursive());
+    let delivery = out.join("delivery/delivery");
+    assert_eq!(names(&delivery), ["README", "part.tar.gz", "part.tar.gz.d"]);
+    let jar = delivery.join("part.tar.gz.d/part/lib/app.jar");
+    assert_eq!(
+        fs::read_to_string(jar.with_file_name("app.jar.d/app/classes/A.class")).expect("read"),
+        "bytecode"
+    );
+    assert_eq!(result.nested_archives, [delivery.join("part.tar.gz"), jar]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn recursive_include_filter_and_delete() {
+    let root = make_unique_dir("nested_recursive_include");
+    let archive = pack_delivery(&root);
+
+    let out = root.join("out");
+    let result = unpack_recursive(
+        &archive,
+        &out,
+        NestedOptions {
+            include: vec![String::from("*.tar.gz")],
+            delete_archives: true,
+            ..recursive()
+        },
+    );
+    let delivery = out.join("delivery/delivery");
+    assert_eq!(names(&delivery), ["README", "part.tar.gz.d"]);
+    assert_eq!(names(&delivery.join("part.tar.gz.d/part/lib")), ["app.jar"]);
+    assert_eq!(result.nested_archives, [delivery.join("part.tar.gz")]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn recursive_stops_at_its_depth_limit() {
+    let root = make_unique_dir("nested_recursive_depth");
+    let archive = pack_delivery(&root);
+
+    let out = root.join("out");
+    let result = unpack_recursive(
+        &archive,
+        &out,
+        NestedOptions {
+            max_depth: 1,
+            ..recursive()
+        },
+    );
+    let lib = out.join("delivery/delivery/part.tar.gz.d/part/lib");
+    assert_eq!(names(&lib), ["app.jar"]);
+    assert!(result
+        .warnings
+        .iter()
+        .any(|warning| warning.contains("app.jar") && warning.contains("deeper than 1")));
+    cleanup_dir(&root);
+}
+
+// Every inner archive stays well within the limits on its own; all of them
+// together don't.
+#[test]
+fn recursive_shares_the_limits_across_inner_archives() {
+    let root = make_unique_dir("nested_recursive_limits");
+    let parts = root.join("parts");
+    fs::create_dir_all(&parts).expect("create parts dir");
+    for index in 0..8 {
+        let part = root.join(format!("part{}", index));
+        write_file(&part.join("data.txt"), &"z".repeat(100_000));
+        modules::compress(
+            FileType::Targz,
+            &part,
+            &parts.join(format!("part{}.tar.gz", index)),
+        );
+    }
+    let archive = root.join("parts.zip");
+    modules::compress(FileType::Zip, &parts, &archive);
+
+    let unpack = |name: &str, limits: Limits| {
+        service::decompress(DecompressRequest {
+            input: archive.clone(),
+            output: root.join(name),
+            level: Level::default(),
+            options: DecompressOptions {
+                limits,
+                nested: recursive(),
+                ..DecompressOptions::default()
+            },
+        })
+    };
+    let bytes = Limits {
+        max_total_bytes: Some(300_000),
+        ..Limits::default()
+    };
+    match unpack("out-bytes", bytes) {
+        Err(MagicPackError::LimitExceeded(limit)) => {
+            assert_eq!(limit, Limit::TotalBytes(300_000))
+        }
+        other => panic!("expected the byte limit, got {:?}", other),
+    }
+    let entries = Limits {
+        max_entries: Some(20),
+        ..Limits::default()
+    };
+    match unpack("out-entries", entries) {
+        Err(MagicPackError::LimitExceeded(limit)) => assert_eq!(limit, Limit::Entries(20)),
+        other => panic!("expected the entry limit, got {:?}", other),
+    }
+    // enough for all of them
+    let result = unpack(
+        "out",
+        Limits {
+            max_total_bytes: Some(1_000_000),
+            max_entries: Some(100),
+            ..Limits::default()
+        },
+    )
+    .expect("decompress");
+    assert_eq!(result.nested_archives.len(), 8);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn wildcards_match_names_and_paths() {
+    assert!(pattern::matches("*.tar.gz", "part.tar.gz"));
+    assert!(pattern::matches("app-?.jar", "app-1.jar"));
+    assert!(!pattern::matches("*.jar", "app.jar.d"));
+    assert!(pattern::matches_path("*.jar", Path::new("lib/app.jar")));
+    assert!(pattern::matches_path("lib/*.jar", Path::new("lib/app.jar")));
+    assert!(!pattern::matches_path(
+        "lib/*.jar",
+        Path::new("vendor/app.jar")
+    ));
+}
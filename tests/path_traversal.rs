@@ -1,7 +1,17 @@
+use std::fs;
+use std::io::Write;
 use std::path::Path;
 
+use magic_pack::contents::enums::{FileType, UnsafeEntryPolicy};
+use magic_pack::modules;
+use magic_pack::modules::error::{ArchiveError, UnsafeReason};
+use magic_pack::modules::options::DecompressOptions;
+use magic_pack::modules::Report;
 use magic_pack::utils::is_safe_path;
 
+mod common;
+use common::{cleanup_dir, make_unique_dir};
+
 #[test]
 fn is_safe_path_blocks_traversal() {
     assert!(!is_safe_path(Path::new("../evil.txt")));
@@ -14,3 +24,629 @@ fn is_safe_path_allows_relative() {
     assert!(is_safe_path(Path::new("safe.txt")));
     assert!(is_safe_path(Path::new("dir/sub/file.txt")));
 }
+
+// tar::Builder refuses hostile names, so write the header fields directly.
+fn raw_tar_entry(
+    builder: &mut tar::Builder<fs::File>,
+    entry_type: tar::EntryType,
+    name: &str,
+    link: &str,
+    data: &[u8],
+) {
+    let mut header = tar::Header::new_gnu();
+    header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+    header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+    header.set_entry_type(entry_type);
+    header.set_mode(0o644);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data).expect("append raw entry");
+}
+
+fn write_tar(path: &Path, entries: &[(tar::EntryType, &str, &str, &[u8])]) {
+    let mut builder = tar::Builder::new(fs::File::create(path).expect("create tar"));
+    for (entry_type, name, link, data) in entries {
+        raw_tar_entry(&mut builder, *entry_type, name, link, data);
+    }
+    builder.finish().expect("finish tar");
+}
+
+fn unsafe_reason(result: Result<Report, ArchiveError>) -> UnsafeReason {
+    match result {
+        Err(ArchiveError::UnsafeEntry { reason, .. }) => reason,
+        other => panic!("expected an unsafe entry error, got {:?}", other),
+    }
+}
+
+fn skip_options() -> DecompressOptions {
+    DecompressOptions {
+        unsafe_entries: UnsafeEntryPolicy::Skip,
+        ..DecompressOptions::default()
+    }
+}
+
+#[test]
+fn tar_rejects_parent_dir_entry() {
+    let root = make_unique_dir("traversal_tar_parent");
+    let archive = root.join("evil.tar");
+    write_tar(
+        &archive,
+        &[(tar::EntryType::Regular, "../evil.txt", "", b"evil")],
+    );
+
+    let unpack = root.join("unpack");
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::PathTraversal);
+    assert!(!root.join("evil.txt").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_rejects_absolute_entry() {
+    let root = make_unique_dir("traversal_tar_absolute");
+    let archive = root.join("evil.tar");
+    write_tar(
+        &archive,
+        &[(tar::EntryType::Regular, "/tmp/evil.txt", "", b"evil")],
+    );
+
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &root.join("unpack"),
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::AbsolutePath);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_rejects_symlink_escape() {
+    let root = make_unique_dir("traversal_tar_symlink");
+    let archive = root.join("evil.tar");
+    write_tar(
+        &archive,
+        &[
+            (tar::EntryType::Directory, "dir/", "", b""),
+            (tar::EntryType::Symlink, "dir/link", "../../outside", b""),
+        ],
+    );
+
+    let unpack = root.join("unpack");
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkEscape);
+    assert!(fs::symlink_metadata(unpack.join("dir/link")).is_err());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_rejects_hardlink_escape() {
+    let root = make_unique_dir("traversal_tar_hardlink");
+    let archive = root.join("evil.tar");
+    write_tar(
+        &archive,
+        &[(tar::EntryType::Link, "link", "../outside.txt", b"")],
+    );
+
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &root.join("unpack"),
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::HardlinkEscape);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_allows_symlink_inside_destination() {
+    let root = make_unique_dir("traversal_tar_symlink_inside");
+    let archive = root.join("ok.tar");
+    write_tar(
+        &archive,
+        &[
+            (tar::EntryType::Regular, "dir/a.txt", "", b"alpha"),
+            (tar::EntryType::Symlink, "dir/link", "a.txt", b""),
+        ],
+    );
+
+    let unpack = root.join("unpack");
+    modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    )
+    .expect("extract safe symlink");
+    assert_eq!(
+        fs::read_to_string(unpack.join("dir/link")).expect("read through link"),
+        "alpha"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn zip_rejects_parent_dir_entry() {
+    let root = make_unique_dir("traversal_zip_parent");
+    let archive = root.join("evil.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&archive).expect("create zip"));
+    writer
+        .start_file("../evil.txt", zip::write::FileOptions::default())
+        .expect("start file");
+    writer.write_all(b"evil").expect("write entry");
+    writer.finish().expect("finish zip");
+
+    let unpack = root.join("unpack");
+    let result = modules::decompress_with_options(
+        FileType::Zip,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::PathTraversal);
+    assert!(!root.join("evil.txt").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn zip_rejects_symlink_escape() {
+    let root = make_unique_dir("traversal_zip_symlink");
+    let archive = root.join("evil.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&archive).expect("create zip"));
+    writer
+        .add_symlink("link", "/etc", zip::write::FileOptions::default())
+        .expect("add symlink");
+    writer.finish().expect("finish zip");
+
+    let result = modules::decompress_with_options(
+        FileType::Zip,
+        &archive,
+        &root.join("unpack"),
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkEscape);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn zip_skip_policy_extracts_safe_entries() {
+    let root = make_unique_dir("traversal_zip_skip");
+    let archive = root.join("mixed.zip");
+    let mut writer = zip::ZipWriter::new(fs::File::create(&archive).expect("create zip"));
+    writer
+        .start_file("../evil.txt", zip::write::FileOptions::default())
+        .expect("start file");
+    writer.write_all(b"evil").expect("write entry");
+    writer
+        .start_file("good.txt", zip::write::FileOptions::default())
+        .expect("start file");
+    writer.write_all(b"good").expect("write entry");
+    writer.finish().expect("finish zip");
+
+    let unpack = root.join("unpack");
+    let report =
+        modules::decompress_with_options(FileType::Zip, &archive, &unpack, &skip_options())
+            .expect("skip unsafe entries");
+    assert_eq!(report.warnings.len(), 1);
+    assert!(report.warnings[0].contains("../evil.txt"));
+    assert_eq!(
+        fs::read_to_string(unpack.join("good.txt")).expect("read good.txt"),
+        "good"
+    );
+    assert!(!root.join("evil.txt").exists());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn sevenz_rejects_parent_dir_entry() {
+    let root = make_unique_dir("traversal_7z_parent");
+    let archive = root.join("evil.7z");
+    let mut writer = sevenz_rust::SevenZWriter::new(fs::File::create(&archive).expect("create 7z"))
+        .expect("7z writer");
+    let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+    entry.name = String::from("../evil.txt");
+    writer
+        .push_archive_entry(entry, Some(&b"evil"[..]))
+        .expect("push entry");
+    writer.finish().expect("finish 7z");
+
+    let unpack = root.join("unpack");
+    let result = modules::decompress_with_options(
+        FileType::SevenZ,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::PathTraversal);
+    assert!(!root.join("evil.txt").exists());
+    cleanup_dir(&root);
+}
+
+// p7zip's encoding: S_IFLNK mode in the upper attribute bits, target as data.
+fn sevenz_symlink(name: &str) -> sevenz_rust::SevenZArchiveEntry {
+    let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+    entry.name = String::from(name);
+    entry.has_windows_attributes = true;
+    entry.windows_attributes = 0x8000 | (0o120777 << 16);
+    entry
+}
+
+fn write_7z(path: &Path, entries: &[(&str, Option<&str>, &[u8])]) {
+    let mut writer = sevenz_rust::SevenZWriter::new(fs::File::create(path).expect("create 7z"))
+        .expect("7z writer");
+    for (name, link, data) in entries {
+        let (entry, data) = match link {
+            Some(link) => (sevenz_symlink(name), link.as_bytes()),
+            None => {
+                let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+                entry.name = String::from(*name);
+                (entry, *data)
+            }
+        };
+        writer
+            .push_archive_entry(entry, Some(data))
+            .expect("push entry");
+    }
+    writer.finish().expect("finish 7z");
+}
+
+fn write_zip(path: &Path, entries: &[(&str, Option<&str>, &[u8])]) {
+    let mut writer = zip::ZipWriter::new(fs::File::create(path).expect("create zip"));
+    for (name, link, data) in entries {
+        match link {
+            Some(link) => writer
+                .add_symlink(*name, *link, zip::write::FileOptions::default())
+                .expect("add symlink"),
+            None => {
+                writer
+                    .start_file(*name, zip::write::FileOptions::default())
+                    .expect("start file");
+                writer.write_all(data).expect("write entry");
+            }
+        }
+    }
+    writer.finish().expect("finish zip");
+}
+
+// `x -> .` and `y -> x/x/x/..` both look inside the destination on paper, but
+// `y` really resolves to its parent, so `y/evil.txt` would land outside. `y`
+// is followed through `x` and refused before anything is written through it.
+fn write_symlink_chain(file_type: FileType, path: &Path) {
+    match file_type {
+        FileType::Tar => write_tar(
+            path,
+            &[
+                (tar::EntryType::Symlink, "x", ".", b""),
+                (tar::EntryType::Symlink, "y", "x/x/x/..", b""),
+                (tar::EntryType::Regular, "y/evil.txt", "", b"evil"),
+            ],
+        ),
+        FileType::Zip => write_zip(
+            path,
+            &[
+                ("x", Some("."), b""),
+                ("y", Some("x/x/x/.."), b""),
+                ("y/evil.txt", None, b"evil"),
+            ],
+        ),
+        FileType::SevenZ => write_7z(
+            path,
+            &[
+                ("x", Some("."), b""),
+                ("y", Some("x/x/x/.."), b""),
+                ("y/evil.txt", None, b"evil"),
+            ],
+        ),
+        _ => unreachable!(),
+    }
+}
+
+fn write_single_file(file_type: FileType, path: &Path, name: &str) {
+    match file_type {
+        FileType::Tar => write_tar(path, &[(tar::EntryType::Regular, name, "", b"evil")]),
+        FileType::Zip => write_zip(path, &[(name, None, b"evil")]),
+        FileType::SevenZ => write_7z(path, &[(name, None, b"evil")]),
+        _ => unreachable!(),
+    }
+}
+
+fn assert_rejects_symlink_chain(file_type: FileType, name: &str) {
+    let root = make_unique_dir(name);
+    let archive = root.join("chain.archive");
+    write_symlink_chain(file_type, &archive);
+
+    let unpack = root.join("unpack");
+    let result = modules::decompress_with_options(
+        file_type,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkEscape);
+    assert!(!root.join("evil.txt").exists());
+    cleanup_dir(&root);
+}
+
+// Symlinks (with a target) and regular files (without one).
+fn write_links(file_type: FileType, path: &Path, entries: &[(&str, Option<&str>)]) {
+    match file_type {
+        FileType::Tar => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(name, link)| match link {
+                    Some(link) => (tar::EntryType::Symlink, *name, *link, &b""[..]),
+                    None => (tar::EntryType::Regular, *name, "", &b"evil"[..]),
+                })
+                .collect();
+            write_tar(path, &entries);
+        }
+        FileType::Zip => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(name, link)| (*name, *link, &b"evil"[..]))
+                .collect();
+            write_zip(path, &entries);
+        }
+        FileType::SevenZ => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(name, link)| (*name, *link, &b"evil"[..]))
+                .collect();
+            write_7z(path, &entries);
+        }
+        _ => unreachable!(),
+    }
+}
+
+// `a/l -> ..` and `b -> a/l/..` each stay inside on their own, but `b`
+// followed through `a/l` is the destination's parent. Whichever of the two
+// comes second is refused.
+fn assert_rejects_chained_symlinks(file_type: FileType, name: &str) {
+    let root = make_unique_dir(name);
+    let orders: [&[(&str, Option<&str>)]; 2] = [
+        &[
+            ("a/l", Some("..")),
+            ("b", Some("a/l/..")),
+            ("b/evil.txt", None),
+        ],
+        &[
+            ("b", Some("a/l/..")),
+            ("a/l", Some("..")),
+            ("b/evil.txt", None),
+        ],
+    ];
+    for (index, entries) in orders.into_iter().enumerate() {
+        let archive = root.join(format!("chain{}.archive", index));
+        write_links(file_type, &archive, entries);
+        let unpack = root.join(format!("unpack{}", index));
+        let result = modules::decompress_with_options(
+            file_type,
+            &archive,
+            &unpack,
+            &DecompressOptions::default(),
+        );
+        assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkEscape);
+        assert!(!root.join("evil.txt").exists());
+
+        // skipped, the refused link is missing and the first one is kept
+        let unpack = root.join(format!("skipped{}", index));
+        let report =
+            modules::decompress_with_options(file_type, &archive, &unpack, &skip_options())
+                .expect("extract with skip policy");
+        assert!(
+            report
+                .warnings
+                .iter()
+                .any(|warning| warning.contains("symlink points outside the destination")),
+            "{:?}",
+            report.warnings
+        );
+        assert!(!root.join("evil.txt").exists());
+        let kept = if index == 0 { "a/l" } else { "b" };
+        assert!(fs::symlink_metadata(unpack.join(kept)).is_ok());
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_rejects_chained_symlinks() {
+    assert_rejects_chained_symlinks(FileType::Tar, "traversal_tar_chained");
+}
+
+#[test]
+fn zip_rejects_chained_symlinks() {
+    assert_rejects_chained_symlinks(FileType::Zip, "traversal_zip_chained");
+}
+
+#[test]
+fn sevenz_rejects_chained_symlinks() {
+    assert_rejects_chained_symlinks(FileType::SevenZ, "traversal_7z_chained");
+}
+
+// Chains inside the destination still extract.
+#[cfg(unix)]
+#[test]
+fn chained_symlinks_inside_destination_are_kept() {
+    let root = make_unique_dir("traversal_chained_inside");
+    let archive = root.join("chain.tar");
+    write_links(
+        FileType::Tar,
+        &archive,
+        &[
+            ("lib/libz.so.1", None),
+            ("lib64", Some("lib")),
+            ("usr/lib", Some("../lib64")),
+            ("usr/libz.so", Some("lib/libz.so.1")),
+        ],
+    );
+    let unpack = root.join("unpack");
+    modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    )
+    .expect("extract chained symlinks");
+    assert_eq!(
+        fs::read_to_string(unpack.join("usr/libz.so")).expect("read through the chain"),
+        "evil"
+    );
+    cleanup_dir(&root);
+}
+
+// A hardlink to a symlink is another symlink with the same target, which
+// leads somewhere else from its own directory; and a directory that takes
+// the place of a symlink changes where links through it lead.
+#[test]
+fn tar_rejects_links_that_escape_once_moved_or_replaced() {
+    let root = make_unique_dir("traversal_tar_moved_link");
+    let archive = root.join("moved.tar");
+    write_tar(
+        &archive,
+        &[
+            (tar::EntryType::Symlink, "a/b/l", "../..", b""),
+            (tar::EntryType::Link, "l2", "a/b/l", b""),
+        ],
+    );
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &root.join("unpack-moved"),
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkEscape);
+
+    let archive = root.join("replaced.tar");
+    write_tar(
+        &archive,
+        &[
+            (tar::EntryType::Symlink, "a/k", "..", b""),
+            (tar::EntryType::Symlink, "a/l", "b/c", b""),
+            (tar::EntryType::Symlink, "y", "a/l/../k/..", b""),
+            (tar::EntryType::Directory, "a/l", "", b""),
+        ],
+    );
+    let result = modules::decompress_with_options(
+        FileType::Tar,
+        &archive,
+        &root.join("unpack-replaced"),
+        &DecompressOptions::default(),
+    );
+    match result {
+        Err(ArchiveError::UnsafeEntry { path, reason }) => {
+            assert_eq!(path, Path::new("a/l"));
+            assert_eq!(reason, UnsafeReason::SymlinkEscape);
+        }
+        other => panic!("expected an unsafe entry error, got {:?}", other),
+    }
+    cleanup_dir(&root);
+}
+
+// Stands in for a link swapped into the destination while extraction runs.
+#[cfg(unix)]
+fn assert_rejects_existing_dir_symlink(file_type: FileType, name: &str) {
+    let root = make_unique_dir(name);
+    let archive = root.join("plain.archive");
+    write_single_file(file_type, &archive, "a/passwd");
+
+    let outside = root.join("outside");
+    let unpack = root.join("unpack");
+    fs::create_dir_all(&outside).expect("create outside dir");
+    fs::create_dir_all(&unpack).expect("create unpack dir");
+    std::os::unix::fs::symlink("../outside", unpack.join("a")).expect("plant symlink");
+
+    let result = modules::decompress_with_options(
+        file_type,
+        &archive,
+        &unpack,
+        &DecompressOptions::default(),
+    );
+    assert_eq!(unsafe_reason(result), UnsafeReason::SymlinkInPath);
+    assert!(!outside.join("passwd").exists());
+    cleanup_dir(&root);
+}
+
+// A link left at the entry's own name is replaced, not written through.
+#[cfg(unix)]
+fn assert_replaces_existing_file_symlink(file_type: FileType, name: &str) {
+    let root = make_unique_dir(name);
+    let archive = root.join("plain.archive");
+    write_single_file(file_type, &archive, "passwd");
+
+    let outside = root.join("outside.txt");
+    let unpack = root.join("unpack");
+    fs::write(&outside, "original").expect("write outside file");
+    fs::create_dir_all(&unpack).expect("create unpack dir");
+    std::os::unix::fs::symlink("../outside.txt", unpack.join("passwd")).expect("plant symlink");
+
+    modules::decompress_with_options(file_type, &archive, &unpack, &DecompressOptions::default())
+        .expect("extract over symlink");
+    assert_eq!(
+        fs::read_to_string(&outside).expect("read outside file"),
+        "original"
+    );
+    let metadata = fs::symlink_metadata(unpack.join("passwd")).expect("stat extracted file");
+    assert!(metadata.file_type().is_file());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_rejects_write_through_symlink_chain() {
+    assert_rejects_symlink_chain(FileType::Tar, "toctou_tar_chain");
+}
+
+#[test]
+fn zip_rejects_write_through_symlink_chain() {
+    assert_rejects_symlink_chain(FileType::Zip, "toctou_zip_chain");
+}
+
+#[test]
+fn sevenz_rejects_write_through_symlink_chain() {
+    assert_rejects_symlink_chain(FileType::SevenZ, "toctou_7z_chain");
+}
+
+#[cfg(unix)]
+#[test]
+fn tar_rejects_write_through_existing_symlink() {
+    assert_rejects_existing_dir_symlink(FileType::Tar, "toctou_tar_existing");
+}
+
+#[cfg(unix)]
+#[test]
+fn zip_rejects_write_through_existing_symlink() {
+    assert_rejects_existing_dir_symlink(FileType::Zip, "toctou_zip_existing");
+}
+
+#[cfg(unix)]
+#[test]
+fn sevenz_rejects_write_through_existing_symlink() {
+    assert_rejects_existing_dir_symlink(FileType::SevenZ, "toctou_7z_existing");
+}
+
+#[cfg(unix)]
+#[test]
+fn tar_replaces_symlink_at_entry_name() {
+    assert_replaces_existing_file_symlink(FileType::Tar, "toctou_tar_replace");
+}
+
+#[cfg(unix)]
+#[test]
+fn zip_replaces_symlink_at_entry_name() {
+    assert_replaces_existing_file_symlink(FileType::Zip, "toctou_zip_replace");
+}
+
+#[cfg(unix)]
+#[test]
+fn sevenz_replaces_symlink_at_entry_name() {
+    assert_replaces_existing_file_symlink(FileType::SevenZ, "toctou_7z_replace");
+}
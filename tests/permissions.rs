@@ -0,0 +1,217 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::options::{DecompressOptions, PermissionOptions};
+use magic_pack::service::{self, DecompressRequest, Level, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir};
+
+fn write_file(path: &Path, contents: &str, mode: u32) {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("create parent");
+    }
+    fs::write(path, contents).expect("write file");
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).expect("chmod");
+}
+
+fn mode_of(path: &Path) -> u32 {
+    fs::metadata(path).expect("stat").mode() & 0o7777
+}
+
+fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn extract(file_type: FileType, archive: &Path, out: &Path, permissions: PermissionOptions) {
+    let options = DecompressOptions {
+        permissions,
+        ..DecompressOptions::default()
+    };
+    modules::decompress_with_options(file_type, archive, out, &options).expect("decompress");
+}
+
+#[test]
+fn special_bits_are_cleared_unless_kept() {
+    let root = make_unique_dir("permissions_special");
+    let src = root.join("src");
+    write_file(&src.join("shared/tool"), "#!/bin/sh\n", 0o4755);
+    fs::set_permissions(src.join("shared"), fs::Permissions::from_mode(0o1777)).expect("chmod dir");
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+
+    let cleared = root.join("cleared");
+    extract(
+        FileType::Tar,
+        &archive,
+        &cleared,
+        PermissionOptions::default(),
+    );
+    assert_eq!(mode_of(&cleared.join("src/shared/tool")), 0o755);
+    assert_eq!(mode_of(&cleared.join("src/shared")), 0o777);
+
+    let kept = root.join("kept");
+    let permissions = PermissionOptions {
+        special_bits: true,
+        ..PermissionOptions::default()
+    };
+    extract(FileType::Tar, &archive, &kept, permissions);
+    assert_eq!(mode_of(&kept.join("src/shared/tool")), 0o4755);
+    assert_eq!(mode_of(&kept.join("src/shared")), 0o1777);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn umask_applies_to_stored_modes_in_every_container() {
+    let root = make_unique_dir("permissions_umask");
+    let src = root.join("src");
+    write_file(&src.join("run.sh"), "run", 0o775);
+    write_file(&src.join("notes.txt"), "notes", 0o666);
+    let permissions = PermissionOptions {
+        umask: Some(0o027),
+        ..PermissionOptions::default()
+    };
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Targz, "src.tar.gz"),
+        (FileType::Tarzst, "src.tar.zst"),
+        (FileType::Zip, "src.zip"),
+    ] {
+        let archive = root.join(name);
+        modules::compress(file_type, &src, &archive);
+        let out = root.join(format!("out-{}", name));
+        extract(file_type, &archive, &out, permissions.clone());
+        assert_eq!(mode_of(&out.join("src/run.sh")), 0o750, "{}", name);
+        assert_eq!(mode_of(&out.join("src/notes.txt")), 0o640, "{}", name);
+    }
+
+    // 7z entries written here carry no mode, so they start from 0644.
+    let archive = root.join("src.7z");
+    modules::compress(FileType::SevenZ, &src, &archive);
+    let out = root.join("out-7z");
+    extract(FileType::SevenZ, &archive, &out, permissions);
+    assert_eq!(mode_of(&out.join("src/notes.txt")), 0o640);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn no_same_permissions_ignores_stored_modes() {
+    let root = make_unique_dir("permissions_no_same");
+    let src = root.join("src");
+    write_file(&src.join("locked/secret.txt"), "secret", 0o400);
+    write_file(&src.join("run.sh"), "run", 0o4755);
+    fs::set_permissions(src.join("locked"), fs::Permissions::from_mode(0o700)).expect("chmod");
+    let permissions = PermissionOptions {
+        no_same_permissions: true,
+        umask: Some(0o022),
+        ..PermissionOptions::default()
+    };
+    for (file_type, name) in [(FileType::Tar, "src.tar"), (FileType::Zip, "src.zip")] {
+        let archive = root.join(name);
+        modules::compress(file_type, &src, &archive);
+        let out = root.join(format!("out-{}", name));
+        extract(file_type, &archive, &out, permissions.clone());
+        assert_eq!(
+            mode_of(&out.join("src/locked/secret.txt")),
+            0o644,
+            "{}",
+            name
+        );
+        assert_eq!(mode_of(&out.join("src/run.sh")), 0o644, "{}", name);
+        assert_eq!(mode_of(&out.join("src/locked")), 0o755, "{}", name);
+    }
+    cleanup_dir(&root);
+}
+
+// Giving files away needs root, both to set up the source and to restore it.
+#[test]
+fn numeric_owners_are_restored_through_the_maps() {
+    if !is_root() {
+        eprintln!("skipping: restoring owners needs root");
+        return;
+    }
+    let root = make_unique_dir("permissions_owner");
+    let src = root.join("src");
+    write_file(&src.join("owned.txt"), "owned", 0o644);
+    std::os::unix::fs::chown(src.join("owned.txt"), Some(1234), Some(2345)).expect("chown");
+    std::os::unix::fs::chown(&src, Some(1234), Some(2345)).expect("chown dir");
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+
+    let plain = root.join("plain");
+    extract(
+        FileType::Tar,
+        &archive,
+        &plain,
+        PermissionOptions::default(),
+    );
+    assert_eq!(
+        fs::metadata(plain.join("src/owned.txt"))
+            .expect("stat")
+            .uid(),
+        0
+    );
+
+    let mapped = root.join("mapped");
+    let permissions = PermissionOptions {
+        numeric_owner: true,
+        uid_map: vec![(1234, 4321)],
+        ..PermissionOptions::default()
+    };
+    extract(FileType::Tar, &archive, &mapped, permissions.clone());
+    for path in [mapped.join("src"), mapped.join("src/owned.txt")] {
+        let metadata = fs::metadata(&path).expect("stat");
+        assert_eq!((metadata.uid(), metadata.gid()), (4321, 2345), "{:?}", path);
+    }
+
+    // Info-ZIP records owners in an extra field the zip crate leaves alone.
+    if Command::new("zip").arg("-v").output().is_ok() {
+        let status = Command::new("zip")
+            .current_dir(&root)
+            .args(["-qr", "src.zip", "src"])
+            .status()
+            .expect("run zip");
+        assert!(status.success(), "zip failed");
+        let out = root.join("zip");
+        extract(FileType::Zip, &root.join("src.zip"), &out, permissions);
+        let metadata = fs::metadata(out.join("src/owned.txt")).expect("stat");
+        assert_eq!((metadata.uid(), metadata.gid()), (4321, 2345));
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn id_maps_need_numeric_owners() {
+    let root = make_unique_dir("permissions_maps");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a", 0o644);
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+    let result = service::decompress(DecompressRequest {
+        input: archive,
+        output: root.join("out"),
+        level: Level::Auto,
+        options: DecompressOptions {
+            permissions: PermissionOptions {
+                gid_map: vec![(1, 2)],
+                ..PermissionOptions::default()
+            },
+            ..DecompressOptions::default()
+        },
+    });
+    assert!(matches!(result, Err(MagicPackError::InvalidInput(_))));
+
+    assert_eq!(PermissionOptions::parse_id_map("1000:0"), Ok((1000, 0)));
+    assert!(PermissionOptions::parse_id_map("1000").is_err());
+    assert_eq!(PermissionOptions::parse_umask("027"), Ok(0o027));
+    assert!(PermissionOptions::parse_umask("8").is_err());
+    cleanup_dir(&root);
+}
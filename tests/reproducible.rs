@@ -0,0 +1,132 @@
+use std::fs;
+use std::path::Path;
+
+use filetime::FileTime;
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, ReproducibleOptions};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+const EPOCH: i64 = 1_600_000_000;
+
+const ALL_TYPES: [(FileType, &str); 8] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Targz, "src.tar.gz"),
+    (FileType::Tarbz2, "src.tar.bz2"),
+    (FileType::Tarxz, "src.tar.xz"),
+    (FileType::Tarzst, "src.tar.zst"),
+    (FileType::Tarlz4, "src.tar.lz4"),
+    (FileType::Zip, "src.zip"),
+    (FileType::SevenZ, "src.7z"),
+];
+
+fn reproducible() -> CompressOptions {
+    CompressOptions {
+        reproducible: ReproducibleOptions {
+            enabled: true,
+            source_date_epoch: Some(EPOCH),
+        },
+        ..CompressOptions::default()
+    }
+}
+
+// The same files written in the given order, stamped `mtime` seconds after
+// EPOCH and with group write permission when `umask_002` is set.
+fn write_tree(src: &Path, names: &[&str], mtime: i64, umask_002: bool) {
+    for name in names {
+        let path = src.join(name);
+        write_file(&path, name);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = if umask_002 { 0o664 } else { 0o644 };
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).expect("chmod");
+        }
+        #[cfg(not(unix))]
+        let _ = umask_002;
+        filetime::set_file_mtime(&path, FileTime::from_unix_time(EPOCH + mtime, 0))
+            .expect("set mtime");
+    }
+}
+
+#[test]
+fn same_tree_gives_the_same_bytes() {
+    let root = make_unique_dir("reproducible_bytes");
+    let first = root.join("first/src");
+    write_tree(&first, &["a.txt", "b/c.txt", "b/d.txt", "e.txt"], 10, false);
+    let second = root.join("second/src");
+    write_tree(
+        &second,
+        &["e.txt", "b/d.txt", "b/c.txt", "a.txt"],
+        5000,
+        true,
+    );
+    for dir in [&first, &second, &first.join("b"), &second.join("b")] {
+        filetime::set_file_mtime(dir, FileTime::now()).expect("set dir mtime");
+    }
+
+    for (file_type, name) in ALL_TYPES {
+        let one = root.join(format!("one-{}", name));
+        let two = root.join(format!("two-{}", name));
+        modules::compress_with_options(file_type, &first, &one, &reproducible());
+        modules::compress_with_options(file_type, &second, &two, &reproducible());
+        assert_eq!(
+            fs::read(&one).expect("read first"),
+            fs::read(&two).expect("read second"),
+            "{} differs",
+            name
+        );
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn mtimes_are_clamped_and_older_ones_kept() {
+    let root = make_unique_dir("reproducible_clamp");
+    let src = root.join("src");
+    write_tree(&src, &["new.txt"], 100, false);
+    write_tree(&src, &["old.txt"], -100, false);
+
+    for (file_type, name) in ALL_TYPES {
+        let archive = root.join(name);
+        modules::compress_with_options(file_type, &src, &archive, &reproducible());
+        let out = root.join(format!("out-{}", name));
+        modules::decompress(file_type, &archive, &out);
+        let mtime = |file: &str| {
+            let metadata = fs::metadata(out.join("src").join(file)).expect("stat");
+            FileTime::from_last_modification_time(&metadata).unix_seconds()
+        };
+        assert_eq!(mtime("new.txt"), EPOCH, "{}", name);
+        assert_eq!(mtime("old.txt"), EPOCH - 100, "{}", name);
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn tar_entries_are_sorted_and_owned_by_root() {
+    let root = make_unique_dir("reproducible_tar_headers");
+    let src = root.join("src");
+    write_tree(&src, &["z.txt", "a.txt", "m/b.txt"], 0, true);
+    let archive = root.join("src.tar");
+    modules::compress_with_options(FileType::Tar, &src, &archive, &reproducible());
+
+    let mut tar = tar::Archive::new(fs::File::open(&archive).expect("open tar"));
+    let mut names = Vec::new();
+    for entry in tar.entries().expect("entries") {
+        let entry = entry.expect("entry");
+        let header = entry.header();
+        assert_eq!(header.uid().expect("uid"), 0);
+        assert_eq!(header.gid().expect("gid"), 0);
+        assert_eq!(header.username().expect("username"), Some(""));
+        let mode = header.mode().expect("mode");
+        assert!(mode == 0o644 || mode == 0o755, "mode {:o}", mode);
+        names.push(entry.path().expect("path").to_string_lossy().into_owned());
+    }
+    assert_eq!(
+        names,
+        ["src", "src/a.txt", "src/m", "src/m/b.txt", "src/z.txt"]
+    );
+    cleanup_dir(&root);
+}
@@ -1,30 +1,15 @@
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::process::{Command, Stdio};
 
 use magic_pack::contents::enums::FileType;
 use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, SevenZOptions};
+use zip::write::FileOptions;
 
-fn make_unique_dir(name: &str) -> PathBuf {
-    let base = PathBuf::from("target/tests");
-    fs::create_dir_all(&base).expect("create base test dir");
-    let nanos = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("time")
-        .as_nanos();
-    let pid = process::id();
-    let dir = base.join(format!("{}_{}_{}", name, pid, nanos));
-    fs::create_dir_all(&dir).expect("create test dir");
-    dir
-}
-
-fn write_file(path: &Path, contents: &str) {
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).expect("create parent");
-    }
-    fs::write(path, contents).expect("write file");
-}
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
 
 fn prepare_src_dir(root: &Path, name: &str) -> PathBuf {
     let src_dir = root.join(name);
@@ -33,10 +18,6 @@ fn prepare_src_dir(root: &Path, name: &str) -> PathBuf {
     src_dir
 }
 
-fn cleanup_dir(path: &Path) {
-    let _ = fs::remove_dir_all(path);
-}
-
 #[test]
 fn roundtrip_gz() {
     let root = make_unique_dir("roundtrip_gz");
@@ -170,6 +151,46 @@ fn roundtrip_7z_dir() {
     cleanup_dir(&root);
 }
 
+#[test]
+fn roundtrip_7z_solid_dir() {
+    let root = make_unique_dir("roundtrip_7z_solid");
+    let src_dir = prepare_src_dir(&root, "srcdir");
+    write_file(&src_dir.join("sub/c.rs"), "fn main() {}");
+    write_file(&src_dir.join("empty.txt"), "");
+
+    let compressed = root.join("out.7z");
+    let options = CompressOptions {
+        sevenz: SevenZOptions {
+            solid: true,
+            solid_block_size: Some(8),
+            preset: 1,
+            dict_size: Some(100_000),
+            sort_by_extension: true,
+        },
+        ..CompressOptions::default()
+    };
+    modules::compress_with_options(FileType::SevenZ, &src_dir, &compressed, &options);
+
+    let unpack = root.join("unpack");
+    fs::create_dir_all(&unpack).expect("create unpack dir");
+    modules::decompress(FileType::SevenZ, &compressed, &unpack);
+
+    assert_eq!(
+        fs::read_to_string(unpack.join("srcdir/a.txt")).expect("read a.txt"),
+        "hello"
+    );
+    assert_eq!(
+        fs::read_to_string(unpack.join("srcdir/sub/b.txt")).expect("read b.txt"),
+        "world"
+    );
+    assert_eq!(
+        fs::read_to_string(unpack.join("srcdir/sub/c.rs")).expect("read c.rs"),
+        "fn main() {}"
+    );
+    assert!(unpack.join("srcdir/empty.txt").exists());
+    cleanup_dir(&root);
+}
+
 #[test]
 fn roundtrip_xz() {
     let root = make_unique_dir("roundtrip_xz");
@@ -297,10 +318,22 @@ fn detect_file_types() {
     fs::write(&zip_file, [0x50, 0x4b, 0x03, 0x04]).expect("write zip");
     assert_eq!(modules::get_file_type(&zip_file).unwrap(), FileType::Zip);
 
+    let mut header = [0u8; 512];
+    header[257..263].copy_from_slice(b"ustar\0");
     let tar_file = root.join("sample.tar");
-    fs::write(&tar_file, b"ustar").expect("write tar");
+    fs::write(&tar_file, header).expect("write tar");
     assert_eq!(modules::get_file_type(&tar_file).unwrap(), FileType::Tar);
 
+    // the magic only counts at its place in the header
+    let text_file = root.join("sample.txt");
+    fs::write(&text_file, "ustar is in the middle of this sentence").expect("write text");
+    assert!(modules::get_file_type(&text_file).is_err());
+
+    // an unreadable file is an error, not a panic
+    let missing = root.join("missing");
+    let err = modules::get_file_type(&missing).expect_err("missing file");
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+
     let sevenz_file = root.join("sample.7z");
     fs::write(&sevenz_file, [0x37, 0x7a, 0xbc, 0xaf]).expect("write 7z");
     assert_eq!(
@@ -322,3 +355,141 @@ fn detect_file_types() {
 
     cleanup_dir(&root);
 }
+
+// Zip entries carry the file's own mode and mtime, not the time of packing.
+#[cfg(unix)]
+#[test]
+fn roundtrip_zip_keeps_mode_and_mtime() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = make_unique_dir("roundtrip_zip_metadata");
+    let src_dir = prepare_src_dir(&root, "srcdir");
+    let file_a = src_dir.join("a.txt");
+    let file_b = src_dir.join("sub/b.txt");
+    fs::set_permissions(&file_a, fs::Permissions::from_mode(0o644)).expect("chmod a");
+    fs::set_permissions(&file_b, fs::Permissions::from_mode(0o750)).expect("chmod b");
+    // 2020-01-01T00:00:00Z
+    let mtime = filetime::FileTime::from_unix_time(1_577_836_800, 0);
+    filetime::set_file_mtime(&file_a, mtime).expect("set mtime");
+
+    let compressed = root.join("out.zip");
+    modules::compress(FileType::Zip, &src_dir, &compressed);
+    let unpack = root.join("unpack");
+    modules::decompress(FileType::Zip, &compressed, &unpack);
+
+    let mode = |path: &Path| fs::metadata(path).expect("stat").permissions().mode() & 0o7777;
+    assert_eq!(mode(&unpack.join("srcdir/a.txt")), 0o644);
+    assert_eq!(mode(&unpack.join("srcdir/sub/b.txt")), 0o750);
+    let metadata = fs::metadata(unpack.join("srcdir/a.txt")).expect("stat a");
+    assert_eq!(
+        filetime::FileTime::from_last_modification_time(&metadata),
+        mtime
+    );
+    cleanup_dir(&root);
+}
+
+// DOS times only reach from 1980 to 2107: earlier and later mtimes are
+// clamped to the ends, and a leap day is kept as it is.
+#[test]
+fn zip_dos_time_edges() {
+    let root = make_unique_dir("zip_dos_time_edges");
+    let src_dir = root.join("srcdir");
+    for (name, seconds) in [
+        // 1970-01-02T00:00:00Z
+        ("early.txt", 86_400),
+        // 2200-01-01T00:00:00Z
+        ("late.txt", 7_258_118_400),
+        // 2024-02-29T12:00:00Z
+        ("leap.txt", 1_709_208_000),
+    ] {
+        let path = src_dir.join(name);
+        write_file(&path, name);
+        filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(seconds, 0))
+            .expect("set mtime");
+    }
+
+    // in UTC the DOS time is the mtime itself
+    let archive = root.join("out.zip");
+    let status = Command::new(env!("CARGO_BIN_EXE_magic-pack"))
+        .env("TZ", "UTC")
+        .args(["-c", "-f", "zip", "-o"])
+        .arg(&archive)
+        .arg(&src_dir)
+        .stdout(Stdio::null())
+        .status()
+        .expect("run magic-pack");
+    assert!(status.success());
+
+    let mut zip = zip::ZipArchive::new(fs::File::open(&archive).expect("open zip")).expect("zip");
+    for (name, expected) in [
+        ("srcdir/early.txt", (1980, 1, 1, 0, 0, 0)),
+        ("srcdir/late.txt", (2107, 12, 31, 23, 59, 58)),
+        ("srcdir/leap.txt", (2024, 2, 29, 12, 0, 0)),
+    ] {
+        let time = zip.by_name(name).expect("entry").last_modified();
+        assert_eq!(
+            (
+                time.year(),
+                time.month(),
+                time.day(),
+                time.hour(),
+                time.minute(),
+                time.second()
+            ),
+            expected,
+            "{}",
+            name
+        );
+    }
+    cleanup_dir(&root);
+}
+
+// Without an extended timestamp field the DOS time is local time, leap days
+// and the last second a DOS time can hold included.
+#[test]
+fn zip_dos_time_without_extended_field() {
+    let root = make_unique_dir("zip_dos_time_local");
+    let archive = root.join("dates.zip");
+    let mut zip = zip::ZipWriter::new(fs::File::create(&archive).expect("create zip"));
+    for (name, time) in [
+        ("dates/leap.txt", (2024, 2, 29, 12, 0, 0)),
+        ("dates/last.txt", (2107, 12, 31, 23, 59, 58)),
+    ] {
+        let (year, month, day, hour, minute, second) = time;
+        let time = zip::DateTime::from_date_and_time(year, month, day, hour, minute, second)
+            .expect("dos time");
+        zip.start_file(name, FileOptions::default().last_modified_time(time))
+            .expect("start file");
+        zip.write_all(name.as_bytes()).expect("write entry");
+    }
+    zip.finish().expect("finish zip");
+
+    // Tokyo is 9 hours ahead of UTC all year round
+    for (tz, offset) in [("UTC", 0), ("Asia/Tokyo", 9 * 3600)] {
+        let out = root.join(format!("out-{}", tz.replace('/', "-")));
+        fs::create_dir_all(&out).expect("create out");
+        let status = Command::new(env!("CARGO_BIN_EXE_magic-pack"))
+            .env("TZ", tz)
+            .args(["-d", "--destination", "here", "-o"])
+            .arg(out.join("."))
+            .arg(&archive)
+            .stdout(Stdio::null())
+            .status()
+            .expect("run magic-pack");
+        assert!(status.success(), "{}", tz);
+        for (name, seconds) in [
+            ("dates/leap.txt", 1_709_208_000),
+            ("dates/last.txt", 4_354_819_198),
+        ] {
+            let metadata = fs::metadata(out.join(name)).expect("stat");
+            assert_eq!(
+                filetime::FileTime::from_last_modification_time(&metadata),
+                filetime::FileTime::from_unix_time(seconds - offset, 0),
+                "{} in {}",
+                name,
+                tz
+            );
+        }
+    }
+    cleanup_dir(&root);
+}
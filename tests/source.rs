@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::Path;
+
+use filetime::FileTime;
+use magic_pack::contents::enums::{ConflictPolicy, DirectoryMode, FileType};
+use magic_pack::modules::options::{CompressOptions, DecompressOptions, SourceOptions};
+use magic_pack::service::{
+    self, CompressRequest, DecompressRequest, Level, MagicPackError, OperationResult,
+};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .expect("read dir")
+        .map(|entry| {
+            entry
+                .expect("dir entry")
+                .file_name()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn mtime(path: &Path) -> FileTime {
+    FileTime::from_last_modification_time(&fs::metadata(path).expect("stat"))
+}
+
+const REMOVE: SourceOptions = SourceOptions {
+    remove: true,
+    preserve_metadata: false,
+};
+
+const PRESERVE: SourceOptions = SourceOptions {
+    remove: false,
+    preserve_metadata: true,
+};
+
+fn pack(
+    file_type: FileType,
+    input: &Path,
+    output: &Path,
+    options: CompressOptions,
+) -> Result<OperationResult, MagicPackError> {
+    service::compress(CompressRequest {
+        file_type,
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        options,
+    })
+}
+
+fn unpack(input: &Path, output: &Path, options: DecompressOptions) -> OperationResult {
+    service::decompress(DecompressRequest {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        level: Level::Layers(1),
+        options,
+    })
+    .expect("decompress")
+}
+
+fn with_source(source: SourceOptions) -> CompressOptions {
+    CompressOptions {
+        source,
+        ..CompressOptions::default()
+    }
+}
+
+#[test]
+fn compress_removes_the_source_for_every_codec() {
+    for (file_type, name) in [
+        (FileType::Gz, "notes.txt.gz"),
+        (FileType::Bz2, "notes.txt.bz2"),
+        (FileType::Xz, "notes.txt.xz"),
+        (FileType::Zst, "notes.txt.zst"),
+        (FileType::Lz4, "notes.txt.lz4"),
+    ] {
+        let root = make_unique_dir("source_remove");
+        let src = root.join("notes.txt");
+        write_file(&src, "some notes");
+        let compressed = root.join(name);
+        pack(file_type, &src, &compressed, with_source(REMOVE)).expect(name);
+        assert_eq!(names(&root), [name], "{}", name);
+
+        let out = root.join("out");
+        unpack(&compressed, &out, DecompressOptions::default());
+        assert_eq!(
+            fs::read_to_string(out.join("notes.txt")).expect("read"),
+            "some notes"
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn compress_keeps_the_source_by_default() {
+    let root = make_unique_dir("source_keep");
+    let src = root.join("notes.txt");
+    write_file(&src, "some notes");
+    pack(
+        FileType::Zst,
+        &src,
+        &root.join("notes.txt.zst"),
+        CompressOptions::default(),
+    )
+    .expect("compress");
+    assert_eq!(names(&root), ["notes.txt", "notes.txt.zst"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn compress_preserves_mode_and_mtime() {
+    let root = make_unique_dir("source_preserve");
+    let src = root.join("notes.txt");
+    write_file(&src, "some notes");
+    let stamp = FileTime::from_unix_time(1_500_000_000, 0);
+    filetime::set_file_mtime(&src, stamp).expect("set mtime");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).expect("chmod");
+    }
+
+    let compressed = root.join("notes.txt.xz");
+    pack(FileType::Xz, &src, &compressed, with_source(PRESERVE)).expect("compress");
+    assert_eq!(mtime(&compressed), stamp);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&compressed)
+            .expect("stat")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn decompress_removes_the_archive_and_preserves_its_mtime() {
+    let root = make_unique_dir("source_decompress");
+    let src = root.join("notes.txt");
+    write_file(&src, "some notes");
+    let compressed = root.join("notes.txt.gz");
+    pack(FileType::Gz, &src, &compressed, CompressOptions::default()).expect("compress");
+    let stamp = FileTime::from_unix_time(1_400_000_000, 0);
+    filetime::set_file_mtime(&compressed, stamp).expect("set mtime");
+
+    let out = root.join("out");
+    let result = unpack(
+        &compressed,
+        &out,
+        DecompressOptions {
+            source: SourceOptions {
+                remove: true,
+                preserve_metadata: true,
+            },
+            ..DecompressOptions::default()
+        },
+    );
+    assert_eq!(result.output_path, out.join("notes.txt"));
+    assert!(!compressed.exists());
+    assert_eq!(mtime(&result.output_path), stamp);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn decompress_keeps_an_archive_that_was_not_fully_written() {
+    let root = make_unique_dir("source_decompress_skip");
+    let src = root.join("notes.txt");
+    write_file(&src, "some notes");
+    let compressed = root.join("notes.txt.bz2");
+    pack(FileType::Bz2, &src, &compressed, CompressOptions::default()).expect("compress");
+
+    let out = root.join("out");
+    write_file(&out.join("notes.txt"), "mine");
+    let result = unpack(
+        &compressed,
+        &out,
+        DecompressOptions {
+            conflicts: ConflictPolicy::Skip,
+            source: REMOVE,
+            ..DecompressOptions::default()
+        },
+    );
+    assert!(compressed.exists());
+    assert!(result
+        .warnings
+        .iter()
+        .any(|warning| warning.contains("notes.txt.bz2")));
+    assert_eq!(
+        fs::read_to_string(out.join("notes.txt")).expect("read"),
+        "mine"
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn per_file_mode_removes_each_source() {
+    let root = make_unique_dir("source_per_file");
+    let logs = root.join("logs");
+    write_file(&logs.join("a.log"), "a");
+    write_file(&logs.join("old/b.log"), "b");
+    pack(
+        FileType::Gz,
+        &logs,
+        Path::new("."),
+        CompressOptions {
+            directories: DirectoryMode::PerFile,
+            source: REMOVE,
+            ..CompressOptions::default()
+        },
+    )
+    .expect("compress");
+    assert_eq!(names(&logs), ["a.log.gz", "old"]);
+    assert_eq!(names(&logs.join("old")), ["b.log.gz"]);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn source_options_need_a_single_stream_file() {
+    let root = make_unique_dir("source_invalid");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    for (file_type, name) in [(FileType::Zip, "src.zip"), (FileType::Gz, "src.tar.gz")] {
+        let result = pack(file_type, &src, &root.join(name), with_source(REMOVE));
+        assert!(
+            matches!(result, Err(MagicPackError::InvalidInput(_))),
+            "{}",
+            name
+        );
+    }
+    assert!(src.join("a.txt").exists());
+    cleanup_dir(&root);
+}
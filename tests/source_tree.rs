@@ -0,0 +1,137 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use magic_pack::contents::enums::{FileType, LinkPolicy};
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, DecompressOptions, FilterOptions};
+use magic_pack::service::{self, CompressRequest};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+// Every path below `dir`, with a trailing `/` on directories and `@` on
+// symlinks, sorted.
+fn listing(dir: &Path) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).expect("read dir") {
+            let path = entry.expect("dir entry").path();
+            let relative = path
+                .strip_prefix(dir)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            let file_type = fs::symlink_metadata(&path).expect("stat").file_type();
+            if file_type.is_symlink() {
+                paths.push(format!("{}@", relative));
+            } else if file_type.is_dir() {
+                paths.push(format!("{}/", relative));
+                pending.push(path);
+            } else {
+                paths.push(relative);
+            }
+        }
+    }
+    paths.sort();
+    paths
+}
+
+fn compress(
+    file_type: FileType,
+    src: &Path,
+    output: &Path,
+    options: &CompressOptions,
+) -> Vec<String> {
+    service::compress(CompressRequest {
+        file_type,
+        input: src.to_path_buf(),
+        output: output.to_path_buf(),
+        options: options.clone(),
+    })
+    .expect("compress")
+    .warnings
+}
+
+const CONTAINERS: [(FileType, &str); 4] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Tarxz, "src.tar.xz"),
+    (FileType::Zip, "src.zip"),
+    (FileType::SevenZ, "src.7z"),
+];
+
+#[test]
+fn every_container_stores_the_same_tree() {
+    let root = make_unique_dir("source_tree_same");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    write_file(&src.join("deep/er/b.txt"), "b");
+    write_file(&src.join("deep/skip.log"), "log");
+    write_file(&src.join("target/build.o"), "o");
+    fs::create_dir_all(src.join("empty")).expect("create empty dir");
+    symlink("a.txt", src.join("link")).expect("symlink");
+    let options = CompressOptions {
+        filter: FilterOptions {
+            exclude: vec!["*.log".to_string(), "target".to_string()],
+            ..FilterOptions::default()
+        },
+        ..CompressOptions::default()
+    };
+
+    let mut listings = Vec::new();
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        let warnings = compress(file_type, &src, &archive, &options);
+        assert!(warnings.is_empty(), "{}: {:?}", name, warnings);
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        listings.push((name, listing(&out)));
+    }
+    let expected = [
+        "src/",
+        "src/a.txt",
+        "src/deep/",
+        "src/deep/er/",
+        "src/deep/er/b.txt",
+        "src/empty/",
+        "src/link@",
+    ];
+    for (name, listing) in listings {
+        assert_eq!(listing, expected, "{}", name);
+    }
+    cleanup_dir(&root);
+}
+
+// A followed link back to an ancestor can't be walked; it is reported
+// instead of quietly left out.
+#[test]
+fn walk_errors_are_reported() {
+    let root = make_unique_dir("source_tree_errors");
+    let src = root.join("src");
+    write_file(&src.join("sub/a.txt"), "a");
+    symlink("..", src.join("sub/up")).expect("symlink");
+    let options = CompressOptions {
+        links: LinkPolicy::Follow,
+        ..CompressOptions::default()
+    };
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        let warnings = compress(file_type, &src, &archive, &options);
+        assert_eq!(warnings.len(), 1, "{}: {:?}", name, warnings);
+        assert!(
+            warnings[0].starts_with("skipped ") && warnings[0].contains("sub/up"),
+            "{}: {:?}",
+            name,
+            warnings
+        );
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        assert!(out.join("src/sub/a.txt").is_file(), "{}", name);
+    }
+    cleanup_dir(&root);
+}
@@ -0,0 +1,319 @@
+// Holes are found with SEEK_DATA/SEEK_HOLE, which only Linux is counted on
+// for here.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use magic_pack::contents::enums::{FileType, TarFormat};
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, ReproducibleOptions};
+use magic_pack::service::{self, UpdateRequest};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir};
+
+const APPARENT_SIZE: u64 = 128 * 1024 * 1024;
+// anything above this means the holes were filled in
+const SMALL: u64 = 1024 * 1024;
+
+// Bytes the file takes on disk.
+fn allocated(path: &Path) -> u64 {
+    fs::metadata(path).expect("stat").blocks() * 512
+}
+
+// A large file that is mostly holes, with a little data at the given offsets.
+// None when the filesystem under target/ doesn't do holes.
+fn write_sparse(path: &Path, size: u64, data_at: &[u64]) -> Option<()> {
+    fs::create_dir_all(path.parent().expect("parent")).expect("create parent");
+    let mut file = fs::File::create(path).expect("create sparse file");
+    file.set_len(size).expect("set length");
+    for offset in data_at {
+        file.seek(SeekFrom::Start(*offset)).expect("seek");
+        file.write_all(format!("data at {}", offset).as_bytes())
+            .expect("write data");
+    }
+    file.sync_all().expect("sync");
+    if allocated(path) > SMALL {
+        eprintln!("skipping: the filesystem doesn't keep holes");
+        return None;
+    }
+    Some(())
+}
+
+fn same_contents(left: &Path, right: &Path) -> bool {
+    let mut left = fs::File::open(left).expect("open left");
+    let mut right = fs::File::open(right).expect("open right");
+    if left.metadata().expect("stat").len() != right.metadata().expect("stat").len() {
+        return false;
+    }
+    let mut left_buf = vec![0u8; 1 << 16];
+    let mut right_buf = vec![0u8; 1 << 16];
+    loop {
+        let n = left.read(&mut left_buf).expect("read left");
+        if n == 0 {
+            return true;
+        }
+        right.read_exact(&mut right_buf[..n]).expect("read right");
+        if left_buf[..n] != right_buf[..n] {
+            return false;
+        }
+    }
+}
+
+#[test]
+fn sparse_files_keep_their_holes_through_tar() {
+    let root = make_unique_dir("sparse_roundtrip");
+    let src = root.join("src");
+    let sparse = src.join("disk.img");
+    // the last offset leaves a hole at the end
+    let data_at = [0, 10 * 1024 * 1024, 64 * 1024 * 1024 + 17];
+    if write_sparse(&sparse, APPARENT_SIZE, &data_at).is_none() {
+        return cleanup_dir(&root);
+    }
+    fs::write(src.join("plain.txt"), "plain").expect("write plain");
+
+    for (file_type, name) in [(FileType::Tar, "tar"), (FileType::Targz, "tar.gz")] {
+        for format in [TarFormat::Gnu, TarFormat::Pax] {
+            let archive = root.join(format!("{:?}.{}", format, name));
+            let options = CompressOptions {
+                tar_format: format,
+                ..CompressOptions::default()
+            };
+            modules::compress_with_options(file_type, &src, &archive, &options);
+            assert!(
+                fs::metadata(&archive).expect("stat archive").len() < SMALL,
+                "{:?} {} stored the holes",
+                format,
+                name
+            );
+
+            let out = root.join(format!("out-{:?}-{}", format, name));
+            modules::decompress(file_type, &archive, &out);
+            let restored = out.join("src/disk.img");
+            assert!(same_contents(&sparse, &restored), "{:?} {}", format, name);
+            assert!(
+                allocated(&restored) < SMALL,
+                "{:?} {} filled in the holes",
+                format,
+                name
+            );
+            assert_eq!(
+                fs::read_to_string(out.join("src/plain.txt")).expect("plain"),
+                "plain"
+            );
+        }
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn files_that_are_all_hole_or_end_in_data_roundtrip() {
+    let root = make_unique_dir("sparse_edges");
+    let src = root.join("src");
+    let empty = src.join("empty.img");
+    let tail = src.join("tail.img");
+    if write_sparse(&empty, APPARENT_SIZE, &[]).is_none()
+        || write_sparse(&tail, APPARENT_SIZE - 8, &[APPARENT_SIZE - 16]).is_none()
+    {
+        return cleanup_dir(&root);
+    }
+
+    for format in [TarFormat::Gnu, TarFormat::Pax] {
+        let archive = root.join(format!("{:?}.tar", format));
+        let options = CompressOptions {
+            tar_format: format,
+            ..CompressOptions::default()
+        };
+        modules::compress_with_options(FileType::Tar, &src, &archive, &options);
+        let out = root.join(format!("out-{:?}", format));
+        modules::decompress(FileType::Tar, &archive, &out);
+        for name in ["empty.img", "tail.img"] {
+            let restored = out.join("src").join(name);
+            assert!(
+                same_contents(&src.join(name), &restored),
+                "{:?} {}",
+                format,
+                name
+            );
+            assert!(allocated(&restored) < SMALL, "{:?} {}", format, name);
+        }
+    }
+    cleanup_dir(&root);
+}
+
+// Holes depend on the filesystem, so reproducible archives store the zeros;
+// the content still has to come back the same.
+#[test]
+fn reproducible_archives_store_sparse_files_dense() {
+    let root = make_unique_dir("sparse_reproducible");
+    let src = root.join("src");
+    let sparse = src.join("small.img");
+    if write_sparse(&sparse, 4 * 1024 * 1024, &[4096, 3 * 1024 * 1024]).is_none() {
+        return cleanup_dir(&root);
+    }
+    let archive = root.join("src.tar");
+    let options = CompressOptions {
+        tar_format: TarFormat::Pax,
+        reproducible: ReproducibleOptions {
+            enabled: true,
+            source_date_epoch: None,
+        },
+        ..CompressOptions::default()
+    };
+    modules::compress_with_options(FileType::Tar, &src, &archive, &options);
+    assert!(fs::metadata(&archive).expect("stat archive").len() > 4 * 1024 * 1024);
+
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    assert!(same_contents(&sparse, &out.join("src/small.img")));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn gnu_tar_reads_pax_sparse_entries() {
+    if Command::new("tar").arg("--version").output().is_err() {
+        eprintln!("warning: `tar` not found in PATH; skipping sparse interop test");
+        return;
+    }
+    let root = make_unique_dir("sparse_gnu_tar");
+    let src = root.join("src");
+    let sparse = src.join("disk.img");
+    if write_sparse(&sparse, APPARENT_SIZE, &[0, 32 * 1024 * 1024]).is_none() {
+        return cleanup_dir(&root);
+    }
+    let archive = root.join("src.tar");
+    let options = CompressOptions {
+        tar_format: TarFormat::Pax,
+        ..CompressOptions::default()
+    };
+    modules::compress_with_options(FileType::Tar, &src, &archive, &options);
+
+    let out = root.join("out");
+    fs::create_dir_all(&out).expect("create out");
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(&out)
+        .status()
+        .expect("run tar");
+    assert!(status.success(), "tar extract failed");
+    let restored = out.join("src/disk.img");
+    assert!(same_contents(&sparse, &restored));
+    assert!(allocated(&restored) < SMALL);
+    cleanup_dir(&root);
+}
+
+// Entry names of a plain tar as stored, placeholders included.
+fn tar_names(archive: &Path) -> Vec<String> {
+    let mut archive = tar::Archive::new(fs::File::open(archive).expect("open tar"));
+    archive
+        .entries()
+        .expect("tar entries")
+        .map(|entry| {
+            let entry = entry.expect("tar entry");
+            let path = entry.path().expect("entry path");
+            path.to_string_lossy().trim_end_matches('/').to_string()
+        })
+        .collect()
+}
+
+// A PAX sparse member is stored under a placeholder, but an update finds it
+// by its real name, to delete it or to replace it.
+#[test]
+fn pax_sparse_members_are_updated_by_their_real_name() {
+    let root = make_unique_dir("sparse_update");
+    let src = root.join("src");
+    let sparse = src.join("big.img");
+    let options = CompressOptions {
+        tar_format: TarFormat::Pax,
+        ..CompressOptions::default()
+    };
+    let update = |archive: &Path, add: Vec<PathBuf>, delete: Vec<PathBuf>| {
+        service::update(UpdateRequest {
+            file_type: FileType::Tar,
+            archive: archive.to_path_buf(),
+            add,
+            delete,
+            options: options.clone(),
+        })
+        .expect("update")
+    };
+
+    if write_sparse(&sparse, 8 * 1024 * 1024, &[0, 4 * 1024 * 1024]).is_none() {
+        return cleanup_dir(&root);
+    }
+    let archive = root.join("src.tar");
+    modules::compress_with_options(FileType::Tar, &src, &archive, &options);
+    assert!(tar_names(&archive).contains(&String::from("src/GNUSparseFile.0/big.img")));
+
+    // delete
+    let deleted = root.join("deleted.tar");
+    fs::copy(&archive, &deleted).expect("copy archive");
+    let result = update(&deleted, Vec::new(), vec![PathBuf::from("src/big.img")]);
+    assert!(result.warnings.is_empty(), "{:?}", result.warnings);
+    assert_eq!(tar_names(&deleted), ["src"]);
+
+    // replace, with other data in other places
+    fs::remove_file(&sparse).expect("remove sparse");
+    if write_sparse(&sparse, 8 * 1024 * 1024, &[1024 * 1024 + 5]).is_none() {
+        return cleanup_dir(&root);
+    }
+    update(&archive, vec![src.clone()], Vec::new());
+    let names = tar_names(&archive);
+    assert_eq!(
+        names
+            .iter()
+            .filter(|name| name.ends_with("big.img"))
+            .count(),
+        1,
+        "{:?}",
+        names
+    );
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    assert!(same_contents(&sparse, &out.join("src/big.img")));
+    cleanup_dir(&root);
+}
+
+// A kept hardlink to a deleted PAX sparse member takes over its data, under
+// the link's own name rather than the deleted one.
+#[test]
+fn hardlinks_to_deleted_pax_sparse_members_keep_their_name() {
+    let root = make_unique_dir("sparse_update_link");
+    let src = root.join("src");
+    let sparse = src.join("big.img");
+    if write_sparse(&sparse, 8 * 1024 * 1024, &[0, 4 * 1024 * 1024]).is_none() {
+        return cleanup_dir(&root);
+    }
+    fs::hard_link(&sparse, src.join("link.img")).expect("hard link");
+    let options = CompressOptions {
+        tar_format: TarFormat::Pax,
+        ..CompressOptions::default()
+    };
+    let archive = root.join("src.tar");
+    modules::compress_with_options(FileType::Tar, &src, &archive, &options);
+
+    let result = service::update(UpdateRequest {
+        file_type: FileType::Tar,
+        archive: archive.clone(),
+        add: Vec::new(),
+        delete: vec![PathBuf::from("src/big.img")],
+        options,
+    })
+    .expect("update");
+    assert!(result.warnings.is_empty(), "{:?}", result.warnings);
+    assert_eq!(tar_names(&archive), ["src", "src/GNUSparseFile.0/link.img"]);
+
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    assert!(!out.join("src/big.img").exists());
+    let restored = out.join("src/link.img");
+    assert!(same_contents(&sparse, &restored));
+    assert!(allocated(&restored) < SMALL, "filled in the holes");
+    cleanup_dir(&root);
+}
@@ -0,0 +1,255 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use magic_pack::contents::enums::{DirectoryMode, FileType, SpecialFilePolicy};
+use magic_pack::modules;
+use magic_pack::modules::error::{ArchiveError, SpecialKind};
+use magic_pack::modules::options::{CompressOptions, DecompressOptions};
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn mkfifo(path: &Path) {
+    let status = Command::new("mkfifo")
+        .arg(path)
+        .status()
+        .expect("run mkfifo");
+    assert!(status.success(), "mkfifo failed");
+}
+
+// A source tree with a regular file next to a FIFO.
+fn fifo_source(root: &Path) -> PathBuf {
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    mkfifo(&src.join("pipe"));
+    src
+}
+
+fn compress(
+    file_type: FileType,
+    src: &Path,
+    output: &Path,
+    special_files: SpecialFilePolicy,
+) -> Result<Vec<String>, MagicPackError> {
+    service::compress(CompressRequest {
+        file_type,
+        input: src.to_path_buf(),
+        output: output.to_path_buf(),
+        options: CompressOptions {
+            special_files,
+            ..CompressOptions::default()
+        },
+    })
+    .map(|result| result.warnings)
+}
+
+fn extract(
+    archive: &Path,
+    out: &Path,
+    options: &DecompressOptions,
+) -> Result<Vec<String>, ArchiveError> {
+    modules::decompress_with_options(FileType::Tar, archive, out, options)
+        .map(|report| report.warnings)
+}
+
+#[test]
+fn fifos_roundtrip_through_tar() {
+    let root = make_unique_dir("special_fifo_tar");
+    let src = fifo_source(&root);
+    for (file_type, name) in [(FileType::Tar, "src.tar"), (FileType::Targz, "src.tar.gz")] {
+        let archive = root.join(name);
+        let warnings =
+            compress(file_type, &src, &archive, SpecialFilePolicy::Store).expect("compress");
+        assert!(warnings.is_empty(), "{}: {:?}", name, warnings);
+
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        let metadata = fs::symlink_metadata(out.join("src/pipe")).expect("stat pipe");
+        assert!(metadata.file_type().is_fifo(), "{}", name);
+        assert_eq!(fs::read_to_string(out.join("src/a.txt")).unwrap(), "a");
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn containers_without_special_entries_skip_with_a_warning() {
+    let root = make_unique_dir("special_fifo_zip");
+    let src = fifo_source(&root);
+    for (file_type, name) in [(FileType::Zip, "src.zip"), (FileType::SevenZ, "src.7z")] {
+        let archive = root.join(name);
+        let warnings =
+            compress(file_type, &src, &archive, SpecialFilePolicy::Store).expect("compress");
+        assert_eq!(warnings.len(), 1, "{}: {:?}", name, warnings);
+        assert!(warnings[0].contains("FIFO"), "{}: {:?}", name, warnings);
+
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        assert!(out.join("src/a.txt").exists(), "{}", name);
+        assert!(
+            fs::symlink_metadata(out.join("src/pipe")).is_err(),
+            "{}",
+            name
+        );
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn skip_and_fail_policies_on_compress() {
+    let root = make_unique_dir("special_compress_policy");
+    let src = fifo_source(&root);
+
+    let archive = root.join("skipped.tar");
+    let warnings = compress(FileType::Tar, &src, &archive, SpecialFilePolicy::Skip).expect("skip");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    let out = root.join("skipped");
+    extract(&archive, &out, &DecompressOptions::default()).expect("decompress");
+    assert!(out.join("src/a.txt").exists());
+    assert!(fs::symlink_metadata(out.join("src/pipe")).is_err());
+
+    let result = compress(
+        FileType::Tar,
+        &src,
+        &root.join("failed.tar"),
+        SpecialFilePolicy::Fail,
+    );
+    assert!(
+        matches!(result, Err(MagicPackError::OperationFailed(_))),
+        "{:?}",
+        result
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn skip_and_fail_policies_on_extract() {
+    let root = make_unique_dir("special_extract_policy");
+    let src = fifo_source(&root);
+    let archive = root.join("src.tar");
+    compress(FileType::Tar, &src, &archive, SpecialFilePolicy::Store).expect("compress");
+
+    let out = root.join("skipped");
+    let options = DecompressOptions {
+        special_files: SpecialFilePolicy::Skip,
+        ..DecompressOptions::default()
+    };
+    let warnings = extract(&archive, &out, &options).expect("decompress");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(out.join("src/a.txt").exists());
+    assert!(fs::symlink_metadata(out.join("src/pipe")).is_err());
+
+    let options = DecompressOptions {
+        special_files: SpecialFilePolicy::Fail,
+        ..DecompressOptions::default()
+    };
+    match extract(&archive, &root.join("failed"), &options) {
+        Err(ArchiveError::SpecialFile { path, kind }) => {
+            assert_eq!(kind, SpecialKind::Fifo);
+            assert!(path.ends_with("src/pipe"), "{:?}", path);
+        }
+        other => panic!("expected a special file error, got {:?}", other),
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn sockets_are_never_stored() {
+    let root = make_unique_dir("special_socket");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let _listener = UnixListener::bind(src.join("sock")).expect("bind socket");
+    let archive = root.join("src.tar");
+    let warnings =
+        compress(FileType::Tar, &src, &archive, SpecialFilePolicy::Store).expect("compress");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].contains("socket"), "{:?}", warnings);
+
+    let out = root.join("out");
+    extract(&archive, &out, &DecompressOptions::default()).expect("decompress");
+    assert!(out.join("src/a.txt").exists());
+    assert!(fs::symlink_metadata(out.join("src/sock")).is_err());
+    cleanup_dir(&root);
+}
+
+// Making a device node needs root, both for the source and the extraction.
+#[test]
+fn device_nodes_need_allow_devices() {
+    if !is_root() {
+        eprintln!("skipping: device nodes need root");
+        return;
+    }
+    let root = make_unique_dir("special_device");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let status = Command::new("mknod")
+        .arg(src.join("null"))
+        .args(["c", "1", "3"])
+        .status()
+        .expect("run mknod");
+    assert!(status.success(), "mknod failed");
+    let archive = root.join("src.tar");
+    compress(FileType::Tar, &src, &archive, SpecialFilePolicy::Store).expect("compress");
+
+    let refused = root.join("refused");
+    let warnings = extract(&archive, &refused, &DecompressOptions::default()).expect("decompress");
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].contains("not allowed"), "{:?}", warnings);
+    assert!(fs::symlink_metadata(refused.join("src/null")).is_err());
+
+    let allowed = root.join("allowed");
+    let options = DecompressOptions {
+        allow_devices: true,
+        ..DecompressOptions::default()
+    };
+    extract(&archive, &allowed, &options).expect("decompress");
+    let metadata = fs::symlink_metadata(allowed.join("src/null")).expect("stat device");
+    assert!(metadata.file_type().is_char_device());
+    assert_eq!(metadata.rdev(), fs::metadata("/dev/null").unwrap().rdev());
+    cleanup_dir(&root);
+}
+
+// Per-file mode walks the input like the containers do, so FIFOs are left
+// out with a warning, or end the run under fail, instead of being opened.
+#[test]
+fn per_file_mode_follows_the_policy() {
+    let root = make_unique_dir("special_per_file");
+    let src = fifo_source(&root);
+    let per_file = |special_files| {
+        service::compress(CompressRequest {
+            file_type: FileType::Gz,
+            input: src.clone(),
+            output: PathBuf::from("."),
+            options: CompressOptions {
+                directories: DirectoryMode::PerFile,
+                special_files,
+                ..CompressOptions::default()
+            },
+        })
+    };
+
+    assert!(per_file(SpecialFilePolicy::Fail).is_err());
+    assert!(!src.join("a.txt.gz").exists());
+
+    let warnings = per_file(SpecialFilePolicy::Store)
+        .expect("compress")
+        .warnings;
+    assert_eq!(warnings.len(), 1, "{:?}", warnings);
+    assert!(warnings[0].contains("pipe"), "{:?}", warnings);
+    assert!(src.join("a.txt.gz").exists());
+    assert!(!src.join("pipe.gz").exists());
+    cleanup_dir(&root);
+}
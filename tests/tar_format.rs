@@ -0,0 +1,224 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use magic_pack::contents::enums::{FileType, TarFormat};
+use magic_pack::modules;
+use magic_pack::modules::options::CompressOptions;
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn with_format(tar_format: TarFormat) -> CompressOptions {
+    CompressOptions {
+        tar_format,
+        ..CompressOptions::default()
+    }
+}
+
+fn pack(
+    file_type: FileType,
+    input: &Path,
+    output: &Path,
+    tar_format: TarFormat,
+) -> Result<(), MagicPackError> {
+    service::compress(CompressRequest {
+        file_type,
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        options: with_format(tar_format),
+    })
+    .map(|_| ())
+}
+
+// The magic and version fields of every header in the archive.
+fn magics(tar: &[u8]) -> Vec<Vec<u8>> {
+    let mut archive = tar::Archive::new(tar);
+    archive
+        .entries()
+        .expect("entries")
+        .map(|entry| entry.expect("entry").header().as_bytes()[257..265].to_vec())
+        .collect()
+}
+
+// A path below src that is too long for a ustar name, even split at a slash.
+fn long_name() -> PathBuf {
+    PathBuf::from(format!("{}/{}.txt", "d".repeat(150), "f".repeat(120)))
+}
+
+const USTAR_MAGIC: &[u8] = b"ustar\x0000";
+const GNU_MAGIC: &[u8] = b"ustar  \x00";
+
+// The raw tar inside a tar.* archive.
+fn tar_bytes(file_type: FileType, archive: &Path) -> Vec<u8> {
+    let file = fs::File::open(archive).expect("open archive");
+    let mut reader: Box<dyn Read> = match file_type {
+        FileType::Tar => Box::new(file),
+        FileType::Targz => Box::new(flate2::read::GzDecoder::new(file)),
+        FileType::Tarbz2 => Box::new(bzip2::read::BzDecoder::new(file)),
+        FileType::Tarxz => Box::new(xz2::read::XzDecoder::new(file)),
+        FileType::Tarzst => Box::new(zstd::Decoder::new(file).expect("zst decoder")),
+        FileType::Tarlz4 => Box::new(lz4_flex::frame::FrameDecoder::new(file)),
+        other => panic!("not a tar variant: {:?}", other),
+    };
+    let mut tar = Vec::new();
+    reader.read_to_end(&mut tar).expect("decode archive");
+    tar
+}
+
+#[test]
+fn every_tar_variant_writes_the_chosen_format() {
+    let root = make_unique_dir("tar_format_variants");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    write_file(&src.join("sub/b.txt"), "b");
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Targz, "src.tar.gz"),
+        (FileType::Tarbz2, "src.tar.bz2"),
+        (FileType::Tarxz, "src.tar.xz"),
+        (FileType::Tarzst, "src.tar.zst"),
+        (FileType::Tarlz4, "src.tar.lz4"),
+    ] {
+        for (format, magic) in [
+            (TarFormat::Gnu, GNU_MAGIC),
+            (TarFormat::Ustar, USTAR_MAGIC),
+            (TarFormat::Pax, USTAR_MAGIC),
+        ] {
+            let archive = root.join(format!("{:?}-{}", format, name));
+            modules::compress_with_options(file_type, &src, &archive, &with_format(format));
+            let magics = magics(&tar_bytes(file_type, &archive));
+            assert_eq!(magics.len(), 4, "{:?} {}", format, name);
+            assert!(
+                magics.iter().all(|found| found == magic),
+                "{:?} {}",
+                format,
+                name
+            );
+
+            let out = root.join(format!("out-{:?}-{}", format, name));
+            modules::decompress(file_type, &archive, &out);
+            assert_eq!(
+                fs::read_to_string(out.join("src/sub/b.txt")).expect("b"),
+                "b"
+            );
+        }
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn ustar_refuses_a_name_it_cannot_hold() {
+    let root = make_unique_dir("tar_format_ustar_long");
+    let src = root.join("src");
+    write_file(&src.join(long_name()), "long");
+    let result = pack(FileType::Tar, &src, &root.join("src.tar"), TarFormat::Ustar);
+    match result {
+        Err(MagicPackError::OperationFailed(message)) => {
+            assert!(message.contains("cannot be stored as ustar"), "{}", message)
+        }
+        other => panic!("expected a ustar error, got {:?}", other.err()),
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn pax_keeps_long_names_and_subsecond_mtimes() {
+    let root = make_unique_dir("tar_format_pax");
+    let src = root.join("src");
+    let long = src.join(long_name());
+    write_file(&long, "long");
+    let mtime = FileTime::from_unix_time(1_700_000_000, 123_456_789);
+    filetime::set_file_mtime(&long, mtime).expect("set mtime");
+    let archive = root.join("src.tar");
+    pack(FileType::Tar, &src, &archive, TarFormat::Pax).expect("pax");
+
+    let tar = fs::read(&archive).expect("read tar");
+    assert!(magics(&tar).iter().all(|found| found == USTAR_MAGIC));
+    // no GNU long-name entries
+    assert!(!tar.windows(13).any(|window| window == b"././@LongLink"));
+
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    let restored = out.join("src").join(long_name());
+    assert_eq!(fs::read_to_string(&restored).expect("long"), "long");
+    assert_eq!(
+        FileTime::from_last_modification_time(&fs::metadata(&restored).expect("stat")),
+        mtime
+    );
+    cleanup_dir(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn pax_keeps_long_link_targets_that_ustar_refuses() {
+    let root = make_unique_dir("tar_format_pax_link");
+    let src = root.join("src");
+    let target = format!("{}/target.txt", "t".repeat(120));
+    write_file(&src.join(&target), "target");
+    std::os::unix::fs::symlink(&target, src.join("link")).expect("symlink");
+
+    let refused = pack(FileType::Tar, &src, &root.join("u.tar"), TarFormat::Ustar);
+    assert!(matches!(refused, Err(MagicPackError::OperationFailed(_))));
+
+    let archive = root.join("p.tar");
+    pack(FileType::Tar, &src, &archive, TarFormat::Pax).expect("pax");
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+    assert_eq!(
+        fs::read_link(out.join("src/link")).expect("link"),
+        PathBuf::from(&target)
+    );
+    cleanup_dir(&root);
+}
+
+// Handing a file to a uid beyond ustar's 2097151 needs root.
+#[cfg(unix)]
+#[test]
+fn large_ids_go_into_pax_records() {
+    let root = make_unique_dir("tar_format_ids");
+    let src = root.join("src");
+    write_file(&src.join("owned.txt"), "owned");
+    if std::os::unix::fs::chown(src.join("owned.txt"), Some(3_000_000), Some(3_000_001)).is_err() {
+        eprintln!("skipping: changing owners needs root");
+        return cleanup_dir(&root);
+    }
+
+    let refused = pack(FileType::Tar, &src, &root.join("u.tar"), TarFormat::Ustar);
+    assert!(matches!(refused, Err(MagicPackError::OperationFailed(_))));
+
+    let archive = root.join("p.tar");
+    pack(FileType::Tar, &src, &archive, TarFormat::Pax).expect("pax");
+    let tar = fs::read(&archive).expect("read tar");
+    let mut archive = tar::Archive::new(&tar[..]);
+    let owned = archive
+        .entries()
+        .expect("entries")
+        .map(|entry| entry.expect("entry"))
+        .find(|entry| entry.path().expect("path").ends_with("owned.txt"))
+        .expect("owned.txt");
+    assert_eq!(owned.header().uid().expect("uid"), 3_000_000);
+    assert_eq!(owned.header().gid().expect("gid"), 3_000_001);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn xattrs_need_pax_or_gnu() {
+    let root = make_unique_dir("tar_format_xattrs");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: src.clone(),
+        output: root.join("src.tar"),
+        options: CompressOptions {
+            xattrs: true,
+            tar_format: TarFormat::Ustar,
+            ..CompressOptions::default()
+        },
+    });
+    assert!(matches!(result, Err(MagicPackError::InvalidInput(_))));
+    cleanup_dir(&root);
+}
@@ -0,0 +1,240 @@
+use std::fs;
+use std::path::Path;
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::error::{ArchiveError, UnsafeReason};
+use magic_pack::modules::options::{CompressOptions, DecompressOptions};
+use magic_pack::modules::transform::{self, Transform};
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn sed(expression: &str) -> Transform {
+    Transform::parse(expression).expect("parse transform")
+}
+
+fn renamed(transforms: &[Transform], name: &str) -> String {
+    transform::apply(transforms, Path::new(name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+const CONTAINERS: [(FileType, &str); 4] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Targz, "src.tar.gz"),
+    (FileType::Zip, "src.zip"),
+    (FileType::SevenZ, "src.7z"),
+];
+
+#[test]
+fn sed_expressions() {
+    let cases = [
+        ("s,^build/out,dist,", "build/out/app.js", "dist/app.js"),
+        ("s,^build/out,dist,", "src/build/out.rs", "src/build/out.rs"),
+        ("s/\\.htm$/.html/", "site/index.htm", "site/index.html"),
+        ("s/a/b/", "a/a/a", "b/a/a"),
+        ("s/a/b/g", "a/a/a", "b/b/b"),
+        ("s/README/readme/i", "docs/ReadMe.md", "docs/readme.md"),
+        (
+            "s|^([^/]+)/(.*)$|\\2/\\1|",
+            "top/inner/file",
+            "inner/file/top",
+        ),
+        (
+            "s/v[0-9]+(\\.[0-9]+)*/v&-old/",
+            "pkg/v1.2.3/lib",
+            "pkg/vv1.2.3-old/lib",
+        ),
+        ("s/(foo|ba+r)/[&]/g", "foo/baaar/bz", "[foo]/[baaar]/bz"),
+        ("s/x*/-/g", "ab", "-a-b-"),
+        ("s/o{2,}/0/g", "fo/foo/fooo", "fo/f0/f0"),
+        ("s,[^a-z/],_,g", "a-B/c.d", "a__/c_d"),
+        ("s/\\//:/g", "a/b", "a:b"),
+        ("s,^tmp/.*,,", "tmp/cache/blob", ""),
+        ("s/([0-9]+)/$1\\$/", "v12", "v$1$"),
+    ];
+    for (expression, name, expected) in cases {
+        assert_eq!(
+            renamed(&[sed(expression)], name),
+            expected,
+            "{} on {}",
+            expression,
+            name
+        );
+    }
+
+    for invalid in [
+        "y/a/b/",
+        "s/a/b",
+        "s/a/b/q",
+        "s/(a/b/",
+        "s/a)/b/",
+        "s/*a/b/",
+        "s/[a/b/",
+        "s/a/\\2/",
+        "s/a{3,1}/b/",
+    ] {
+        assert!(Transform::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+// Names come from untrusted archives too, so matching has to stay linear in
+// the length of the name, whatever the expression.
+#[test]
+fn pathological_expressions_stay_fast() {
+    let name = "a".repeat(24);
+    assert_eq!(renamed(&[sed("s/(a|a)*b/x/")], &name), name);
+    let name = "n".repeat(20_000);
+    assert_eq!(renamed(&[sed("s/.*/x/")], &name), "x");
+}
+
+#[test]
+fn strip_prefix_and_prefix_chain() {
+    let transforms = Transform::chain(Some("src/"), &[sed("s/\\.txt$/.md/")], Some("pkg-1.0/"));
+    assert_eq!(renamed(&transforms, "src/docs/a.txt"), "pkg-1.0/docs/a.md");
+    // the stripped directory itself leaves nothing to prefix
+    assert_eq!(renamed(&transforms, "src"), "");
+    // only a whole leading component is stripped
+    assert_eq!(renamed(&transforms, "srcdir/a.txt"), "pkg-1.0/srcdir/a.md");
+}
+
+#[test]
+fn compress_renames_entries_in_every_container() {
+    let root = make_unique_dir("transform_compress");
+    let src = root.join("src");
+    write_file(&src.join("build/out/app.js"), "app");
+    write_file(&src.join("notes.txt"), "notes");
+    let options = CompressOptions {
+        transforms: Transform::chain(
+            Some("src"),
+            &[sed("s,^build/out,dist,")],
+            Some("project-1.2.3"),
+        ),
+        ..CompressOptions::default()
+    };
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        service::compress(CompressRequest {
+            file_type,
+            input: src.clone(),
+            output: archive.clone(),
+            options: options.clone(),
+        })
+        .expect("compress");
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        let project = out.join("project-1.2.3");
+        assert_eq!(
+            fs::read_to_string(project.join("dist/app.js")).unwrap(),
+            "app",
+            "{}",
+            name
+        );
+        assert_eq!(
+            fs::read_to_string(project.join("notes.txt")).unwrap(),
+            "notes",
+            "{}",
+            name
+        );
+        // the emptied build directory is still an entry of its own
+        assert!(!project.join("build/out").exists(), "{}", name);
+        assert!(!out.join("src").exists(), "{}", name);
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn decompress_renames_entries_in_every_container() {
+    let root = make_unique_dir("transform_decompress");
+    let src = root.join("src");
+    write_file(&src.join("build/out/app.js"), "app");
+    write_file(&src.join("site/index.htm"), "index");
+    let options = DecompressOptions {
+        strip_components: 1,
+        transforms: vec![sed("s,^build/out,dist,"), sed("s/\\.htm$/.html/")],
+        ..DecompressOptions::default()
+    };
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        modules::compress(file_type, &src, &archive);
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &options).expect("decompress");
+        assert!(out.join("dist/app.js").is_file(), "{}", name);
+        assert!(out.join("site/index.html").is_file(), "{}", name);
+        assert!(!out.join("build/out").exists(), "{}", name);
+    }
+    cleanup_dir(&root);
+}
+
+// Hardlink entries name their target, which has to be renamed along with it.
+#[cfg(unix)]
+#[test]
+fn hardlink_targets_follow_the_rename() {
+    use std::os::unix::fs::MetadataExt;
+
+    let root = make_unique_dir("transform_hardlink");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "shared");
+    fs::hard_link(src.join("a.txt"), src.join("b.txt")).expect("hard link");
+    let archive = root.join("src.tar");
+    service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: src.clone(),
+        output: archive.clone(),
+        options: CompressOptions {
+            transforms: vec![sed("s,^src,packed,")],
+            ..CompressOptions::default()
+        },
+    })
+    .expect("compress");
+
+    let out = root.join("out");
+    let options = DecompressOptions {
+        transforms: vec![sed("s,^packed,unpacked,")],
+        ..DecompressOptions::default()
+    };
+    modules::decompress_with_options(FileType::Tar, &archive, &out, &options).expect("decompress");
+    let a = fs::metadata(out.join("unpacked/a.txt")).expect("stat a");
+    let b = fs::metadata(out.join("unpacked/b.txt")).expect("stat b");
+    assert_eq!(a.ino(), b.ino());
+    cleanup_dir(&root);
+}
+
+#[test]
+fn renames_out_of_the_root_are_refused() {
+    let root = make_unique_dir("transform_unsafe");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    let result = service::compress(CompressRequest {
+        file_type: FileType::Tar,
+        input: src.clone(),
+        output: root.join("escape.tar"),
+        options: CompressOptions {
+            transforms: vec![sed("s,^,../,")],
+            ..CompressOptions::default()
+        },
+    });
+    assert!(
+        matches!(result, Err(MagicPackError::OperationFailed(_))),
+        "{:?}",
+        result
+    );
+
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+    let options = DecompressOptions {
+        transforms: vec![sed("s,^src,../outside,")],
+        ..DecompressOptions::default()
+    };
+    match modules::decompress_with_options(FileType::Tar, &archive, &root.join("out"), &options) {
+        Err(ArchiveError::UnsafeEntry { reason, .. }) => {
+            assert_eq!(reason, UnsafeReason::PathTraversal)
+        }
+        other => panic!("expected an unsafe entry error, got {:?}", other),
+    }
+    assert!(!root.join("outside").exists());
+    cleanup_dir(&root);
+}
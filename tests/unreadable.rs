@@ -0,0 +1,255 @@
+#![cfg(unix)]
+
+use std::fs;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+use magic_pack::contents::enums::{DirectoryMode, FileType, LinkPolicy, UnreadablePolicy};
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, DecompressOptions, NestedOptions};
+use magic_pack::service::{
+    self, CompressRequest, DecompressRequest, Level, MagicPackError, OperationResult,
+};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+// Root reads everything regardless of permission bits.
+fn is_root() -> bool {
+    fs::metadata("/proc/self")
+        .map(|metadata| metadata.uid() == 0)
+        .unwrap_or(false)
+}
+
+fn chmod(path: &Path, mode: u32) {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).expect("chmod");
+}
+
+fn compress(
+    file_type: FileType,
+    src: &Path,
+    output: &Path,
+    options: CompressOptions,
+) -> Result<OperationResult, MagicPackError> {
+    service::compress(CompressRequest {
+        file_type,
+        input: src.to_path_buf(),
+        output: output.to_path_buf(),
+        options,
+    })
+}
+
+const CONTAINERS: [(FileType, &str); 4] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Targz, "src.tar.gz"),
+    (FileType::Zip, "src.zip"),
+    (FileType::SevenZ, "src.7z"),
+];
+
+// A followed link back to an ancestor can't be walked whoever runs the tests.
+#[test]
+fn loops_are_skipped_or_fail() {
+    let root = make_unique_dir("unreadable_loop");
+    let src = root.join("src");
+    write_file(&src.join("sub/a.txt"), "a");
+    symlink("..", src.join("sub/up")).expect("symlink");
+    let options = |unreadable| CompressOptions {
+        links: LinkPolicy::Follow,
+        unreadable,
+        ..CompressOptions::default()
+    };
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        let result =
+            compress(file_type, &src, &archive, options(UnreadablePolicy::Skip)).expect("compress");
+        assert_eq!(result.warnings.len(), 1, "{}: {:?}", name, result.warnings);
+        assert!(result.warnings[0].contains("sub/up"), "{}", name);
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        assert!(out.join("src/sub/a.txt").is_file(), "{}", name);
+
+        let strict = root.join(format!("strict-{}", name));
+        match compress(file_type, &src, &strict, options(UnreadablePolicy::Fail)) {
+            Err(MagicPackError::OperationFailed(message)) => {
+                assert!(message.contains("sub/up"), "{}: {}", name, message)
+            }
+            other => panic!("{}: expected a failure, got {:?}", name, other),
+        }
+    }
+    cleanup_dir(&root);
+}
+
+#[test]
+fn permission_denied_is_skipped_or_fails() {
+    if is_root() {
+        return;
+    }
+    let root = make_unique_dir("unreadable_denied");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    write_file(&src.join("secret.txt"), "secret");
+    write_file(&src.join("locked/b.txt"), "b");
+    chmod(&src.join("secret.txt"), 0o000);
+    chmod(&src.join("locked"), 0o000);
+
+    for (file_type, name) in CONTAINERS {
+        let archive = root.join(name);
+        let options = CompressOptions::default();
+        let result = compress(file_type, &src, &archive, options).expect("compress");
+        assert_eq!(result.warnings.len(), 2, "{}: {:?}", name, result.warnings);
+        assert!(
+            result
+                .warnings
+                .iter()
+                .all(|warning| warning.starts_with("skipped ")),
+            "{}: {:?}",
+            name,
+            result.warnings
+        );
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        assert!(out.join("src/a.txt").is_file(), "{}", name);
+        assert!(!out.join("src/secret.txt").exists(), "{}", name);
+
+        let options = CompressOptions {
+            unreadable: UnreadablePolicy::Fail,
+            ..CompressOptions::default()
+        };
+        let strict = root.join(format!("strict-{}", name));
+        assert!(
+            matches!(
+                compress(file_type, &src, &strict, options),
+                Err(MagicPackError::OperationFailed(_))
+            ),
+            "{}",
+            name
+        );
+    }
+
+    // gzip -r style, one file at a time
+    let options = CompressOptions {
+        directories: DirectoryMode::PerFile,
+        ..CompressOptions::default()
+    };
+    let result = compress(FileType::Gz, &src, Path::new("."), options).expect("compress");
+    assert_eq!(result.warnings.len(), 2, "{:?}", result.warnings);
+    assert!(src.join("a.txt.gz").is_file());
+    assert!(!src.join("secret.txt.gz").exists());
+
+    let options = CompressOptions {
+        directories: DirectoryMode::PerFile,
+        unreadable: UnreadablePolicy::Fail,
+        ..CompressOptions::default()
+    };
+    assert!(compress(FileType::Gz, &src, Path::new("."), options).is_err());
+
+    chmod(&src.join("secret.txt"), 0o644);
+    chmod(&src.join("locked"), 0o755);
+    cleanup_dir(&root);
+}
+
+// Every name of a file shares its permission bits, so once the first name
+// is left out the later ones can't point back at it and go the same way.
+#[test]
+fn unreadable_hardlinks_are_all_skipped() {
+    if is_root() {
+        return;
+    }
+    let root = make_unique_dir("unreadable_hardlinks");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    write_file(&src.join("secret.txt"), "secret");
+    fs::create_dir_all(src.join("copies")).expect("create copies");
+    fs::hard_link(src.join("secret.txt"), src.join("copies/secret.txt")).expect("hard link");
+    chmod(&src.join("secret.txt"), 0o000);
+
+    let archive = root.join("src.tar");
+    let result =
+        compress(FileType::Tar, &src, &archive, CompressOptions::default()).expect("compress");
+    assert_eq!(result.warnings.len(), 2, "{:?}", result.warnings);
+    let mut tar = tar::Archive::new(fs::File::open(&archive).expect("open tar"));
+    for entry in tar.entries().expect("entries") {
+        let entry = entry.expect("entry");
+        assert_ne!(
+            entry.header().entry_type(),
+            tar::EntryType::Link,
+            "{:?}",
+            entry.path()
+        );
+    }
+    let out = root.join("out");
+    modules::decompress_with_options(FileType::Tar, &archive, &out, &DecompressOptions::default())
+        .expect("decompress");
+    assert!(out.join("src/a.txt").is_file());
+    assert!(!out.join("src/secret.txt").exists());
+    assert!(!out.join("src/copies/secret.txt").exists());
+
+    chmod(&src.join("secret.txt"), 0o644);
+    cleanup_dir(&root);
+}
+
+// A directory the nested-archive walk can't read ends the run with an error
+// naming it, instead of quietly leaving the archives in it packed.
+#[test]
+fn nested_walk_reports_what_it_cannot_read() {
+    if is_root() {
+        return;
+    }
+    let root = make_unique_dir("unreadable_nested");
+    let inner = root.join("inner");
+    write_file(&inner.join("a.txt"), "a");
+    let inner_archive = root.join("inner.tar.gz");
+    modules::compress(FileType::Targz, &inner, &inner_archive);
+
+    // A locked directory can't be packed from disk without root, so the
+    // outer tar is written by hand.
+    let archive = root.join("outer.tar");
+    let mut builder = tar::Builder::new(fs::File::create(&archive).expect("create tar"));
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o000);
+    header.set_size(0);
+    builder
+        .append_data(&mut header, "outer/locked/", std::io::empty())
+        .expect("append dir");
+    builder
+        .append_path_with_name(&inner_archive, "outer/locked/inner.tar.gz")
+        .expect("append archive");
+    builder.finish().expect("finish tar");
+    drop(builder);
+
+    let result = service::decompress(DecompressRequest {
+        input: archive,
+        output: root.join("out"),
+        level: Level::default(),
+        options: DecompressOptions {
+            nested: NestedOptions {
+                enabled: true,
+                ..NestedOptions::default()
+            },
+            ..DecompressOptions::default()
+        },
+    });
+    match result {
+        Err(err) => assert!(err.to_string().contains("locked"), "{}", err),
+        Ok(result) => assert!(
+            result
+                .warnings
+                .iter()
+                .any(|warning| warning.contains("locked")),
+            "{:?}",
+            result.warnings
+        ),
+    }
+
+    Command::new("chmod")
+        .arg("-R")
+        .arg("u+rwx")
+        .arg(&root)
+        .status()
+        .expect("run chmod");
+    cleanup_dir(&root);
+}
@@ -0,0 +1,478 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use magic_pack::contents::enums::{FileType, TarFormat};
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, DecompressOptions};
+use magic_pack::modules::transform::Transform;
+use magic_pack::service::{self, MagicPackError, OperationResult, UpdateRequest};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, tool_available, write_file};
+
+fn update(
+    file_type: FileType,
+    archive: &Path,
+    add: &[&Path],
+    delete: &[&str],
+    options: CompressOptions,
+) -> Result<OperationResult, MagicPackError> {
+    service::update(UpdateRequest {
+        file_type,
+        archive: archive.to_path_buf(),
+        add: add.iter().map(|path| path.to_path_buf()).collect(),
+        delete: delete.iter().map(PathBuf::from).collect(),
+        options,
+    })
+}
+
+// Entry names of a plain tar, in archive order.
+fn tar_names(archive: &Path) -> Vec<String> {
+    let mut archive = tar::Archive::new(File::open(archive).expect("open tar"));
+    archive
+        .entries()
+        .expect("tar entries")
+        .map(|entry| {
+            let entry = entry.expect("tar entry");
+            let path = entry.path().expect("entry path");
+            path.to_string_lossy().trim_end_matches('/').to_string()
+        })
+        .collect()
+}
+
+const ARCHIVES: [(FileType, &str); 7] = [
+    (FileType::Tar, "src.tar"),
+    (FileType::Targz, "src.tar.gz"),
+    (FileType::Tarbz2, "src.tar.bz2"),
+    (FileType::Tarxz, "src.tar.xz"),
+    (FileType::Tarzst, "src.tar.zst"),
+    (FileType::Tarlz4, "src.tar.lz4"),
+    (FileType::Zip, "src.zip"),
+];
+
+#[test]
+fn replace_and_delete_in_every_format() {
+    let root = make_unique_dir("update_replace");
+    let src = root.join("src");
+    for (file_type, name) in ARCHIVES {
+        cleanup_dir(&src);
+        write_file(&src.join("a.txt"), "old");
+        write_file(&src.join("b.txt"), "bravo");
+        write_file(&src.join("sub/c.txt"), "charlie");
+        let archive = root.join(name);
+        modules::compress(file_type, &src, &archive);
+
+        write_file(&src.join("a.txt"), "new");
+        write_file(&src.join("d.txt"), "delta");
+        fs::remove_file(src.join("b.txt")).expect("remove b");
+        fs::remove_dir_all(src.join("sub")).expect("remove sub");
+        let result = update(
+            file_type,
+            &archive,
+            &[&src],
+            &["src/b.txt", "src/sub", "src/nothing"],
+            CompressOptions::default(),
+        )
+        .expect("update");
+        assert_eq!(
+            result.warnings,
+            ["no entry src/nothing to delete"],
+            "{}",
+            name
+        );
+
+        let out = root.join(format!("out-{}", name));
+        modules::decompress_with_options(file_type, &archive, &out, &DecompressOptions::default())
+            .expect("decompress");
+        assert_eq!(
+            fs::read_to_string(out.join("src/a.txt")).unwrap(),
+            "new",
+            "{}",
+            name
+        );
+        assert!(out.join("src/d.txt").is_file(), "{}", name);
+        assert!(!out.join("src/b.txt").exists(), "{}", name);
+        assert!(!out.join("src/sub").exists(), "{}", name);
+    }
+    let mut names = tar_names(&root.join("src.tar"));
+    names.sort();
+    assert_eq!(names, ["src", "src/a.txt", "src/d.txt"]);
+    cleanup_dir(&root);
+}
+
+// Nothing replaced or deleted: the new entries go on the end and the old
+// ones stay where they were, byte for byte.
+#[test]
+fn tar_appends_in_place() {
+    let root = make_unique_dir("update_append");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "alpha");
+    let extra = root.join("extra");
+    write_file(&extra.join("b.txt"), "bravo");
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+    let before = fs::read(&archive).expect("read tar");
+
+    let options = CompressOptions {
+        transforms: Transform::chain(None, &[], Some("src")),
+        ..CompressOptions::default()
+    };
+    update(FileType::Tar, &archive, &[&extra], &[], options).expect("update");
+    let after = fs::read(&archive).expect("read tar");
+    // everything but the two end-of-archive blocks
+    assert_eq!(after[..before.len() - 1024], before[..before.len() - 1024]);
+    assert_eq!(
+        tar_names(&archive),
+        ["src", "src/a.txt", "src/extra", "src/extra/b.txt"]
+    );
+    cleanup_dir(&root);
+}
+
+// An entry that can't be appended fails the update and takes the ones
+// appended before it along, leaving the archive as it was.
+#[test]
+fn failed_append_restores_the_tar() {
+    let root = make_unique_dir("update_append_failed");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "alpha");
+    let extra = root.join("extra");
+    write_file(&extra.join("b.txt"), "bravo");
+    fs::create_dir_all(extra.join("x".repeat(120))).expect("create long dir");
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+    let before = fs::read(&archive).expect("read tar");
+
+    let options = CompressOptions {
+        tar_format: TarFormat::Ustar,
+        ..CompressOptions::default()
+    };
+    let result = update(FileType::Tar, &archive, &[&extra], &[], options);
+    assert!(
+        matches!(result, Err(MagicPackError::OperationFailed(_))),
+        "{:?}",
+        result
+    );
+    assert_eq!(fs::read(&archive).expect("read tar"), before);
+    cleanup_dir(&root);
+}
+
+// Destructors don't run when a signal ends the process; the handler the CLI
+// installs cuts an in-place append off again instead.
+#[cfg(unix)]
+#[test]
+fn signals_restore_the_tar_being_appended_to() {
+    use std::io::Write;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let root = make_unique_dir("update_append_signal");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "alpha");
+    // 256 MiB of noise takes long enough to append to catch it halfway
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let noise: Vec<u8> = (0..64 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let big = root.join("extra/big.bin");
+    fs::create_dir_all(big.parent().expect("parent")).expect("create extra");
+    let mut file = File::create(&big).expect("create big.bin");
+    for _ in 0..4 {
+        file.write_all(&noise).expect("write big.bin");
+    }
+    drop(file);
+    let archive = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &archive);
+    let before = fs::read(&archive).expect("read tar");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_magic-pack"))
+        .args(["-u", "-f", "tar", "--add"])
+        .arg(root.join("extra"))
+        .arg(&archive)
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .expect("run magic-pack");
+    let started = Instant::now();
+    while fs::metadata(&archive).expect("stat tar").len() <= before.len() as u64 {
+        assert!(
+            started.elapsed() < Duration::from_secs(30),
+            "nothing appended"
+        );
+        thread::sleep(Duration::from_millis(1));
+    }
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let status = child.wait().expect("wait for magic-pack");
+    assert_eq!(status.code(), Some(130));
+    assert!(fs::read(&archive).expect("read tar") == before);
+    cleanup_dir(&root);
+}
+
+// Entries zip keeps are copied as they are, still deflated.
+#[test]
+fn zip_copies_kept_entries_raw() {
+    if !tool_available("zip") {
+        eprintln!("warning: `zip` not found in PATH; skipping");
+        return;
+    }
+    let root = make_unique_dir("update_zip_raw");
+    let src = root.join("src");
+    write_file(&src.join("big.txt"), &"deflate me ".repeat(1000));
+    write_file(&src.join("old.txt"), "old");
+    let archive = fs::canonicalize(&root).unwrap().join("src.zip");
+    let status = Command::new("zip")
+        .current_dir(&root)
+        .args(["-q", "-r", "src.zip", "src"])
+        .status()
+        .expect("run zip");
+    assert!(status.success());
+    let (method, compressed) = {
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let big = zip.by_name("src/big.txt").unwrap();
+        (big.compression(), big.compressed_size())
+    };
+    assert_eq!(method, zip::CompressionMethod::Deflated);
+
+    let new = root.join("new.txt");
+    write_file(&new, "new");
+    let options = CompressOptions {
+        transforms: Transform::chain(None, &[], Some("src")),
+        ..CompressOptions::default()
+    };
+    update(FileType::Zip, &archive, &[&new], &["src/old.txt"], options).expect("update");
+
+    let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+    let mut names: Vec<&str> = zip.file_names().collect();
+    names.sort();
+    assert_eq!(names, ["src/", "src/big.txt", "src/new.txt"]);
+    let big = zip.by_name("src/big.txt").unwrap();
+    assert_eq!(big.compression(), zip::CompressionMethod::Deflated);
+    assert_eq!(big.compressed_size(), compressed);
+    drop(big);
+
+    if tool_available("unzip") {
+        let output = Command::new("unzip")
+            .arg("-t")
+            .arg(&archive)
+            .output()
+            .expect("run unzip");
+        assert!(output.status.success(), "unzip -t failed");
+    }
+    cleanup_dir(&root);
+}
+
+// Members GNU tar wrote with long names, PAX records and `./` prefixes are
+// copied untouched and matched by name all the same.
+#[test]
+fn foreign_tar_members_are_kept_or_replaced() {
+    if !tool_available("tar") {
+        eprintln!("warning: `tar` not found in PATH; skipping");
+        return;
+    }
+    let root = make_unique_dir("update_foreign");
+    let src = root.join("src");
+    let long_name = format!("{}/file.txt", "d".repeat(150));
+    write_file(&src.join(&long_name), "long");
+    write_file(&src.join("a.txt"), "old");
+    write_file(&src.join("b.txt"), "bravo");
+    for (format, name) in [("gnu", "gnu.tar.gz"), ("pax", "pax.tar.gz")] {
+        let archive = root.join(name);
+        let status = Command::new("tar")
+            .current_dir(&root)
+            .args(["-czf", name, &format!("--format={}", format), "./src"])
+            .status()
+            .expect("run tar");
+        assert!(status.success());
+
+        write_file(&src.join("a.txt"), "new");
+        let options = CompressOptions {
+            transforms: Transform::chain(None, &[], Some("src")),
+            ..CompressOptions::default()
+        };
+        update(
+            FileType::Targz,
+            &archive,
+            &[&src.join("a.txt")],
+            &["src/b.txt"],
+            options,
+        )
+        .expect("update");
+
+        let out = root.join(format!("out-{}", format));
+        fs::create_dir_all(&out).unwrap();
+        let status = Command::new("tar")
+            .args(["-xzf"])
+            .arg(&archive)
+            .arg("-C")
+            .arg(&out)
+            .status()
+            .expect("run tar");
+        assert!(status.success(), "{}", format);
+        assert_eq!(
+            fs::read_to_string(out.join("src").join(&long_name)).unwrap(),
+            "long",
+            "{}",
+            format
+        );
+        assert_eq!(
+            fs::read_to_string(out.join("src/a.txt")).unwrap(),
+            "new",
+            "{}",
+            format
+        );
+        assert!(!out.join("src/b.txt").exists(), "{}", format);
+        write_file(&src.join("a.txt"), "old");
+    }
+    cleanup_dir(&root);
+}
+
+// Hardlinks an update keeps still hold what they did when the member they
+// point at is replaced or deleted: the first one takes its place and the
+// later ones point at that one.
+#[test]
+fn tar_hardlinks_to_dropped_members_are_rewritten() {
+    let root = make_unique_dir("update_hardlinks");
+    let archive = root.join("src.tar");
+    let long_name = format!("src/{}.txt", "l".repeat(120));
+    let mut builder = tar::Builder::new(File::create(&archive).expect("create tar"));
+    let mut append = |name: &str, link: Option<&str>, data: &str| {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(data.len() as u64);
+        match link {
+            Some(target) => {
+                header.set_entry_type(tar::EntryType::Link);
+                builder.append_link(&mut header, name, target)
+            }
+            None => builder.append_data(&mut header, name, data.as_bytes()),
+        }
+        .expect("append");
+    };
+    append("src/a.txt", None, "shared");
+    append("src/b.txt", Some("src/a.txt"), "");
+    append("src/c.txt", Some("./src/a.txt"), "");
+    append("src/x.txt", None, "x");
+    append(&long_name, Some("src/x.txt"), "");
+    append("src/y.txt", Some(&long_name), "");
+    append("src/p.txt", None, "p");
+    append("src/q.txt", Some("src/p.txt"), "");
+    append("src/r.txt", Some("src/q.txt"), "");
+    append("src/unlinked.txt", None, "gone");
+    builder.into_inner().expect("finish tar");
+
+    write_file(&root.join("new/a.txt"), "new");
+    let options = CompressOptions {
+        transforms: Transform::chain(None, &[], Some("src")),
+        ..CompressOptions::default()
+    };
+    update(
+        FileType::Tar,
+        &archive,
+        &[&root.join("new/a.txt")],
+        &["src/x.txt", "src/p.txt", "src/q.txt", "src/unlinked.txt"],
+        options,
+    )
+    .expect("update");
+
+    let mut members = Vec::new();
+    let mut tar = tar::Archive::new(File::open(&archive).expect("open tar"));
+    for entry in tar.entries().expect("tar entries") {
+        let entry = entry.expect("tar entry");
+        let link = entry
+            .link_name()
+            .expect("link name")
+            .map(|link| link.to_string_lossy().into_owned());
+        members.push((
+            entry
+                .path()
+                .expect("entry path")
+                .to_string_lossy()
+                .into_owned(),
+            entry.header().entry_type(),
+            link,
+        ));
+    }
+    let regular = |name: &str| (name.to_string(), tar::EntryType::Regular, None);
+    let link = |name: &str, target: &str| {
+        (
+            name.to_string(),
+            tar::EntryType::Link,
+            Some(target.to_string()),
+        )
+    };
+    assert_eq!(
+        members,
+        [
+            regular("src/b.txt"),
+            link("src/c.txt", "src/b.txt"),
+            regular(&long_name),
+            link("src/y.txt", &long_name),
+            regular("src/r.txt"),
+            regular("src/a.txt"),
+        ]
+    );
+
+    let out = root.join("out");
+    modules::decompress_with_options(FileType::Tar, &archive, &out, &DecompressOptions::default())
+        .expect("decompress");
+    let read = |name: &str| fs::read_to_string(out.join(name)).expect("read");
+    assert_eq!(read("src/a.txt"), "new");
+    assert_eq!(read("src/b.txt"), "shared");
+    assert_eq!(read("src/c.txt"), "shared");
+    assert_eq!(read(&long_name), "x");
+    assert_eq!(read("src/y.txt"), "x");
+    assert!(!out.join("src/x.txt").exists());
+    assert_eq!(read("src/r.txt"), "p");
+    assert!(!out.join("src/p.txt").exists());
+    assert!(!out.join("src/unlinked.txt").exists());
+    // no spooled data left next to the archive
+    assert_eq!(fs::read_dir(&root).expect("list root").count(), 3);
+    cleanup_dir(&root);
+}
+
+#[test]
+fn invalid_updates_are_refused() {
+    let root = make_unique_dir("update_invalid");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "alpha");
+    let sevenz = root.join("src.7z");
+    modules::compress(FileType::SevenZ, &src, &sevenz);
+    let tar = root.join("src.tar");
+    modules::compress(FileType::Tar, &src, &tar);
+
+    let refused = |result: Result<OperationResult, MagicPackError>| {
+        matches!(result, Err(MagicPackError::InvalidInput(_)))
+    };
+    let options = CompressOptions::default;
+    assert!(refused(update(
+        FileType::SevenZ,
+        &sevenz,
+        &[&src],
+        &[],
+        options()
+    )));
+    assert!(refused(update(FileType::Gz, &tar, &[&src], &[], options())));
+    assert!(refused(update(FileType::Tar, &tar, &[], &[], options())));
+    assert!(refused(update(
+        FileType::Tar,
+        &tar,
+        &[],
+        &["../src"],
+        options()
+    )));
+    assert!(refused(update(FileType::Tar, &tar, &[], &["."], options())));
+    assert!(refused(update(
+        FileType::Tar,
+        &root.join("missing.tar"),
+        &[&src],
+        &[],
+        options()
+    )));
+    assert_eq!(tar_names(&tar), ["src", "src/a.txt"]);
+    cleanup_dir(&root);
+}
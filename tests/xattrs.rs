@@ -0,0 +1,303 @@
+// Extended attributes are only read and restored on Linux. Each test sets up
+// what it needs as the current user and returns early when the filesystem
+// under target/ doesn't take user.* attributes (some tmpfs and overlay mounts).
+#![cfg(target_os = "linux")]
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use magic_pack::contents::enums::FileType;
+use magic_pack::modules;
+use magic_pack::modules::options::{CompressOptions, DecompressOptions};
+use magic_pack::service::{self, CompressRequest, MagicPackError};
+
+mod common;
+use common::{cleanup_dir, make_unique_dir, write_file};
+
+fn c_path(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).expect("path without nul")
+}
+
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let path = c_path(path);
+    let name = CString::new(name).expect("name without nul");
+    let result = unsafe {
+        libc::setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr() as *const libc::c_void,
+            value.len(),
+            0,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn get_xattr(path: &Path, name: &str) -> Option<Vec<u8>> {
+    let path = c_path(path);
+    let name = CString::new(name).expect("name without nul");
+    let mut buffer = vec![0u8; 1024];
+    let size = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buffer.as_mut_ptr() as *mut libc::c_void,
+            buffer.len(),
+        )
+    };
+    if size < 0 {
+        return None;
+    }
+    buffer.truncate(size as usize);
+    Some(buffer)
+}
+
+// src/a.txt and src/sub with a user attribute each, or None when the
+// filesystem won't take them.
+fn write_tree(root: &Path) -> Option<PathBuf> {
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    fs::create_dir_all(src.join("sub")).expect("create sub");
+    if let Err(err) = set_xattr(&src.join("a.txt"), "user.origin", b"upstream") {
+        eprintln!("skipping: user xattrs unsupported here: {}", err);
+        return None;
+    }
+    set_xattr(&src.join("sub"), "user.checksum", b"\x00\x01binary").expect("dir xattr");
+    Some(src)
+}
+
+fn with_xattrs() -> CompressOptions {
+    CompressOptions {
+        xattrs: true,
+        ..CompressOptions::default()
+    }
+}
+
+fn restore_xattrs() -> DecompressOptions {
+    DecompressOptions {
+        xattrs: true,
+        ..DecompressOptions::default()
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+fn tar_roundtrip_restores_user_xattrs() {
+    for (file_type, name) in [
+        (FileType::Tar, "src.tar"),
+        (FileType::Targz, "src.tar.gz"),
+        (FileType::Tarzst, "src.tar.zst"),
+    ] {
+        let root = make_unique_dir("xattrs_roundtrip");
+        let src = match write_tree(&root) {
+            Some(src) => src,
+            None => return cleanup_dir(&root),
+        };
+        let archive = root.join(name);
+        modules::compress_with_options(file_type, &src, &archive, &with_xattrs());
+        let out = root.join("out");
+        let report = modules::decompress_with_options(file_type, &archive, &out, &restore_xattrs())
+            .expect(name);
+        assert!(
+            report.warnings.is_empty(),
+            "{}: {:?}",
+            name,
+            report.warnings
+        );
+
+        assert_eq!(
+            get_xattr(&out.join("src/a.txt"), "user.origin").as_deref(),
+            Some(&b"upstream"[..]),
+            "{}",
+            name
+        );
+        assert_eq!(
+            get_xattr(&out.join("src/sub"), "user.checksum").as_deref(),
+            Some(&b"\x00\x01binary"[..]),
+            "{}",
+            name
+        );
+        cleanup_dir(&root);
+    }
+}
+
+#[test]
+fn xattrs_are_stored_as_pax_records_only_when_asked() {
+    let root = make_unique_dir("xattrs_pax");
+    let src = match write_tree(&root) {
+        Some(src) => src,
+        None => return cleanup_dir(&root),
+    };
+    let with = root.join("with.tar");
+    let without = root.join("without.tar");
+    modules::compress_with_options(FileType::Tar, &src, &with, &with_xattrs());
+    modules::compress(FileType::Tar, &src, &without);
+
+    let with = fs::read(with).expect("read tar");
+    assert!(contains(&with, b"SCHILY.xattr.user.origin=upstream\n"));
+    assert!(contains(&with, b"SCHILY.xattr.user.checksum="));
+    assert!(!contains(
+        &fs::read(without).expect("read tar"),
+        b"SCHILY.xattr."
+    ));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn xattrs_are_not_restored_without_the_flag() {
+    let root = make_unique_dir("xattrs_no_restore");
+    let src = match write_tree(&root) {
+        Some(src) => src,
+        None => return cleanup_dir(&root),
+    };
+    let archive = root.join("src.tar");
+    modules::compress_with_options(FileType::Tar, &src, &archive, &with_xattrs());
+    let out = root.join("out");
+    modules::decompress(FileType::Tar, &archive, &out);
+
+    assert_eq!(fs::read_to_string(out.join("src/a.txt")).expect("a"), "a");
+    assert_eq!(get_xattr(&out.join("src/a.txt"), "user.origin"), None);
+    assert_eq!(get_xattr(&out.join("src/sub"), "user.checksum"), None);
+    cleanup_dir(&root);
+}
+
+// system.posix_acl_access in the kernel's xattr encoding: a version header,
+// then (tag, permissions, id) for owner, one named user, group, mask, other.
+fn access_acl(uid: u32) -> Vec<u8> {
+    const UNDEFINED: u32 = u32::MAX;
+    let mut acl = 2u32.to_le_bytes().to_vec();
+    for (tag, perm, id) in [
+        (0x01u16, 6u16, UNDEFINED),
+        (0x02, 4, uid),
+        (0x04, 4, UNDEFINED),
+        (0x10, 4, UNDEFINED),
+        (0x20, 4, UNDEFINED),
+    ] {
+        acl.extend_from_slice(&tag.to_le_bytes());
+        acl.extend_from_slice(&perm.to_le_bytes());
+        acl.extend_from_slice(&id.to_le_bytes());
+    }
+    acl
+}
+
+#[test]
+fn posix_acls_survive_a_roundtrip() {
+    let root = make_unique_dir("xattrs_acl");
+    let src = root.join("src");
+    write_file(&src.join("shared.txt"), "shared");
+    let acl = access_acl(4242);
+    if let Err(err) = set_xattr(&src.join("shared.txt"), "system.posix_acl_access", &acl) {
+        eprintln!("skipping: POSIX ACLs unsupported here: {}", err);
+        return cleanup_dir(&root);
+    }
+    let stored = get_xattr(&src.join("shared.txt"), "system.posix_acl_access").expect("acl");
+
+    let archive = root.join("src.tar.gz");
+    modules::compress_with_options(FileType::Targz, &src, &archive, &with_xattrs());
+    let out = root.join("out");
+    let report =
+        modules::decompress_with_options(FileType::Targz, &archive, &out, &restore_xattrs())
+            .expect("decompress");
+    assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    assert_eq!(
+        get_xattr(&out.join("src/shared.txt"), "system.posix_acl_access"),
+        Some(stored)
+    );
+    cleanup_dir(&root);
+}
+
+// Setting security.capability needs CAP_SETFCAP, so this only runs as root.
+#[test]
+fn file_capabilities_survive_a_roundtrip_as_root() {
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("skipping: file capabilities need root");
+        return;
+    }
+    let root = make_unique_dir("xattrs_capability");
+    let src = root.join("src");
+    write_file(&src.join("server"), "#!/bin/sh\n");
+    // VFS_CAP_REVISION_2, effective, permitted cap_net_bind_service
+    let mut capability = (0x0200_0000u32 | 1).to_le_bytes().to_vec();
+    for word in [1u32 << 10, 0, 0, 0] {
+        capability.extend_from_slice(&word.to_le_bytes());
+    }
+    if let Err(err) = set_xattr(&src.join("server"), "security.capability", &capability) {
+        eprintln!("skipping: file capabilities unsupported here: {}", err);
+        return cleanup_dir(&root);
+    }
+    let stored = get_xattr(&src.join("server"), "security.capability").expect("capability");
+
+    let archive = root.join("src.tar");
+    modules::compress_with_options(FileType::Tar, &src, &archive, &with_xattrs());
+    let out = root.join("out");
+    modules::decompress_with_options(FileType::Tar, &archive, &out, &restore_xattrs())
+        .expect("decompress");
+    assert_eq!(
+        get_xattr(&out.join("src/server"), "security.capability"),
+        Some(stored)
+    );
+    cleanup_dir(&root);
+}
+
+#[test]
+fn unrestorable_xattrs_become_warnings() {
+    let root = make_unique_dir("xattrs_unrestorable");
+    let archive = root.join("foreign.tar");
+    {
+        let mut builder = tar::Builder::new(fs::File::create(&archive).expect("create tar"));
+        // no Linux filesystem has a "bogus" attribute namespace
+        builder
+            .append_pax_extensions([("SCHILY.xattr.bogus.flag", &b"1"[..])])
+            .expect("pax");
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        builder
+            .append_data(&mut header, "data.txt", &b"data"[..])
+            .expect("append");
+        builder.finish().expect("finish");
+    }
+    let out = root.join("out");
+    let report = modules::decompress_with_options(FileType::Tar, &archive, &out, &restore_xattrs())
+        .expect("decompress");
+
+    assert_eq!(
+        fs::read_to_string(out.join("data.txt")).expect("data"),
+        "data"
+    );
+    assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+    assert!(report.warnings[0].contains("bogus.flag"));
+    cleanup_dir(&root);
+}
+
+#[test]
+fn xattrs_need_a_tar_format() {
+    let root = make_unique_dir("xattrs_invalid");
+    let src = root.join("src");
+    write_file(&src.join("a.txt"), "a");
+    for (file_type, name) in [(FileType::Zip, "src.zip"), (FileType::SevenZ, "src.7z")] {
+        let result = service::compress(CompressRequest {
+            file_type,
+            input: src.clone(),
+            output: root.join(name),
+            options: with_xattrs(),
+        });
+        assert!(
+            matches!(result, Err(MagicPackError::InvalidInput(_))),
+            "{}",
+            name
+        );
+    }
+    cleanup_dir(&root);
+}